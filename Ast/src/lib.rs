@@ -0,0 +1,185 @@
+/* The abstract syntax shared by every stage of the Toi toolchain.
+ *
+ * The parser produces these trees, the typer checks them and the evaluator runs
+ * them, so a program parsed from text can be handed straight to type_check_expr
+ * and eval_expr without any conversion.
+ *
+ * Every tree, type and span implements serde's Serialize and Deserialize, so other
+ * tools can exchange programs as JSON (through serde_json) or as S-expressions
+ * (see sexp.rs) instead of concrete syntax.
+ * */
+
+pub mod sexp;
+
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+/* A span is a half-open range [start, end) of byte offsets into the source text */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    /* The smallest span covering both spans */
+    pub fn join(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    /* The 1-based line and column of the start of the span within src */
+    pub fn line_col(&self, src: &str) -> (usize, usize) {
+        let before = &src[..self.start.min(src.len())];
+        let line = before.matches('\n').count() + 1;
+        let col = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+        (line, col)
+    }
+}
+
+/* A value of t : Type represents a given type */
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub enum Type {
+    Number,  /* represents "num" type */
+    String,  /* represents "string" type */
+    Boolean, /* represents "boolean" type */
+    Unit,    /* represents "unit" type, of host functions with no result */
+    Function(Vec<Type>, Box<Type>), /* represents type of function t1 -> t2 */
+}
+
+/* Types print the way they are written in Toi programs, e.g. (number)->boolean */
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Number => write!(f, "number"),
+            Type::String => write!(f, "string"),
+            Type::Boolean => write!(f, "boolean"),
+            Type::Unit => write!(f, "unit"),
+            Type::Function(args, ret) => {
+                let arg_strs: Vec<String> = args.iter().map(Type::to_string).collect();
+                write!(f, "({})->{}", arg_strs.join(","), ret)
+            }
+        }
+    }
+}
+
+/* This enumeration type lists out the different comparison operators */
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub enum Comparison {
+    LessEqual,
+    Less,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+}
+
+/* A value e : Expr is an AST for a Toi expression  */
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub enum Expr {
+    Id(String),  /* Identifier, i.e., variable name */
+    Numeral(i64), /* Number literal, e.g., 5 */
+    /* Number literal with a fractional part: Decimal(d, p) is d/10^p, e.g.,
+     * Decimal(12, 1) is 1.2. Toi numbers are integers, so it does not type-check */
+    Decimal(i64, u32),
+    StringLiteral(String), /* String literal, e.g., "hi" */
+    True, /* Boolean literal true */
+    False, /* Boolean literal false */
+    /* To reduce the number of cases, we combine all the comparison operators.
+     * The arguments indicate the first operand, what kind of comparison,
+     * then the second operand.
+     * For example, Compare(e1,Greater,e2) is (e1 > e2) */
+    Compare(Box<Expr>, Comparison, Box<Expr>),
+    Times(Box<Expr>,Box<Expr>), /* Multiplication */
+    Plus(Box<Expr>,Box<Expr>),  /* Addition */
+    Minus(Box<Expr>,Box<Expr>), /* Subtraction */
+    Let(Box<Defn>,Box<Expr>),   /* Let-definitions */
+    Call(String, Vec<Expr>),    /* Function calls */
+    /* assert(c, m) is true when the condition c holds, and otherwise stops the
+     * program with the message m */
+    Assert(Box<Expr>, Box<Expr>),
+    /* If(c, e1, e2) is e1 when the condition c holds and e2 otherwise; only the
+     * branch that is chosen is evaluated */
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    /* Marks the source text an expression was parsed from. Spans carry no
+     * meaning of their own, so every consumer can look straight through them. */
+    Spanned(Span, Box<Expr>),
+}
+
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub enum Defn {
+    /* Variable definitions */
+    VarDefn(String, Box<Expr>),
+    /* Function definitions. Functions can be called recursively
+     * For example in pseudo-code:
+     * FunDecl("f", [("x",number),("y",number)], bool, Compare(x,Equal,y))
+     * is the AST for the Toi function definition:
+     *   function f(x:number,y:number):bool =
+     *     (x = y) */
+    FunDefn(String, Vec<(String,Type)>, Type, Box<Expr>),
+    /* Groups of mutually recursive function definitions, each written like a
+     * FunDefn. Every function in the group can call every other one:
+     *   let rec even(n) {..odd(n-1)..} and odd(n) {..even(n-1)..} in even(10) */
+    RecDefn(Vec<RecFun>),
+}
+
+/* One function of a RecDefn group: its name, parameters, return type and body */
+pub type RecFun = (String, Vec<(String,Type)>, Type, Box<Expr>);
+
+/* A top-level item of a Toi source file of tests: a definition, or a named test
+ * whose body must evaluate to true */
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub enum Item {
+    Defn(Defn),
+    Test(String, Box<Expr>),
+}
+
+impl Expr {
+    /* A copy of the expression with every Spanned wrapper removed, for comparing
+     * parsed programs against hand-written ASTs */
+    pub fn strip_spans(&self) -> Expr {
+        match self {
+            Expr::Spanned(_, e) => e.strip_spans(),
+            Expr::Compare(l, c, r) => Expr::Compare(Box::new(l.strip_spans()), c.clone(), Box::new(r.strip_spans())),
+            Expr::Times(l, r) => Expr::Times(Box::new(l.strip_spans()), Box::new(r.strip_spans())),
+            Expr::Plus(l, r) => Expr::Plus(Box::new(l.strip_spans()), Box::new(r.strip_spans())),
+            Expr::Minus(l, r) => Expr::Minus(Box::new(l.strip_spans()), Box::new(r.strip_spans())),
+            Expr::Let(d, e) => Expr::Let(Box::new(d.strip_spans()), Box::new(e.strip_spans())),
+            Expr::Call(f, args) => Expr::Call(f.clone(), args.iter().map(Expr::strip_spans).collect()),
+            Expr::Assert(c, m) => Expr::Assert(Box::new(c.strip_spans()), Box::new(m.strip_spans())),
+            Expr::If(c, t, e) => Expr::If(Box::new(c.strip_spans()), Box::new(t.strip_spans()), Box::new(e.strip_spans())),
+            e => e.clone(),
+        }
+    }
+
+    /* The span of the outermost Spanned wrapper, if there is one */
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Expr::Spanned(s, _) => Some(*s),
+            _ => None,
+        }
+    }
+}
+
+impl Defn {
+    pub fn strip_spans(&self) -> Defn {
+        match self {
+            Defn::VarDefn(x, e) => Defn::VarDefn(x.clone(), Box::new(e.strip_spans())),
+            Defn::FunDefn(f, params, t, body) => Defn::FunDefn(f.clone(), params.clone(), t.clone(), Box::new(body.strip_spans())),
+            Defn::RecDefn(fs) => Defn::RecDefn(fs.iter()
+                .map(|(f, params, t, body)| (f.clone(), params.clone(), t.clone(), Box::new(body.strip_spans())))
+                .collect()),
+        }
+    }
+
+    /* The names the definition binds */
+    pub fn names(&self) -> Vec<String> {
+        match self {
+            Defn::VarDefn(x, _) | Defn::FunDefn(x, _, _, _) => vec![x.clone()],
+            Defn::RecDefn(fs) => fs.iter().map(|(f, _, _, _)| f.clone()).collect(),
+        }
+    }
+}
@@ -0,0 +1,709 @@
+/* An S-expression encoding for anything that implements serde's Serialize and
+ * Deserialize, used to exchange Toi syntax trees, types and values with other tools.
+ *
+ * Rust data is written as follows:
+ *   unit variants and unit structs     Name                 e.g. True, Number
+ *   newtype variants                   (Name x)             e.g. (Numeral 3)
+ *   tuple variants                     (Name x1 ... xN)     e.g. (Plus (Id "x") (Numeral 1))
+ *   structs and struct variants        (Name (f1 x1) ... (fN xN))
+ *   sequences, tuples                  (x1 ... xN)
+ *   maps                               ((k1 v1) ... (kN vN))
+ *   strings                            "text", with \" \\ \n \t escapes
+ *   integers                           -12
+ *   booleans                           #t, #f
+ *   None and Some(x)                   nil, (some x)
+ *   unit                               ()
+ * Newtype structs and boxes are written as their contents. Decoding is driven by
+ * the type being decoded, so from_str(&to_string(&x)?)? == x for every type that
+ * derives Serialize and Deserialize. Floats cannot be encoded. A ; starts a
+ * comment that runs to the end of the line.
+ */
+use std::fmt;
+use serde::{de, ser, Serialize};
+use serde::de::{DeserializeOwned, IntoDeserializer};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Sexp {
+    Atom(String),
+    Str(String),
+    Int(i128),
+    List(Vec<Sexp>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error(msg.to_string())
+    }
+}
+
+pub fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String, Error> {
+    let mut out = String::new();
+    write_sexp(&value.serialize(Encoder)?, &mut out);
+    Ok(out)
+}
+
+pub fn from_str<T: DeserializeOwned>(text: &str) -> Result<T, Error> {
+    T::deserialize(Decoder(read_sexp(text)?))
+}
+
+/* Printing and reading the text of an S-expression */
+
+fn write_sexp(s: &Sexp, out: &mut String) {
+    match s {
+        Sexp::Atom(a) => out.push_str(a),
+        Sexp::Int(n) => out.push_str(&n.to_string()),
+        Sexp::Str(text) => {
+            out.push('"');
+            for c in text.chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    '\t' => out.push_str("\\t"),
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+        },
+        Sexp::List(items) => {
+            out.push('(');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(' ');
+                }
+                write_sexp(item, out);
+            }
+            out.push(')');
+        },
+    }
+}
+
+struct Reader<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+fn read_sexp(text: &str) -> Result<Sexp, Error> {
+    let mut r = Reader { text, pos: 0 };
+    let s = r.datum()?;
+    r.skip_space();
+    if r.pos < text.len() {
+        return Err(r.error("end of input"))
+    }
+    Ok(s)
+}
+
+impl<'a> Reader<'a> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn error(&self, expected: &str) -> Error {
+        Error(format!("expected {} at offset {}", expected, self.pos))
+    }
+
+    fn skip_space(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == ';' {
+                while !matches!(self.bump(), None | Some('\n')) {}
+            } else if c.is_whitespace() {
+                self.bump();
+            } else {
+                break
+            }
+        }
+    }
+
+    fn datum(&mut self) -> Result<Sexp, Error> {
+        self.skip_space();
+        match self.peek() {
+            Some('(') => {
+                self.bump();
+                let mut items = vec![];
+                loop {
+                    self.skip_space();
+                    match self.peek() {
+                        Some(')') => {
+                            self.bump();
+                            return Ok(Sexp::List(items))
+                        },
+                        None => return Err(self.error(")")),
+                        _ => items.push(self.datum()?),
+                    }
+                }
+            },
+            Some('"') => {
+                self.bump();
+                let mut text = String::new();
+                loop {
+                    match self.bump() {
+                        Some('"') => return Ok(Sexp::Str(text)),
+                        Some('\\') => match self.bump() {
+                            Some('"') => text.push('"'),
+                            Some('\\') => text.push('\\'),
+                            Some('n') => text.push('\n'),
+                            Some('t') => text.push('\t'),
+                            _ => return Err(self.error("an escape \\\", \\\\, \\n or \\t")),
+                        },
+                        Some(c) => text.push(c),
+                        None => return Err(self.error("closing \"")),
+                    }
+                }
+            },
+            Some(c) if c != ')' => {
+                let start = self.pos;
+                while self.peek().is_some_and(|c| !c.is_whitespace() && !"()\";".contains(c)) {
+                    self.bump();
+                }
+                let token = &self.text[start..self.pos];
+                let digits = token.strip_prefix('-').unwrap_or(token);
+                if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+                    token.parse().map(Sexp::Int).map_err(|_| Error(format!("integer {} is too large", token)))
+                } else {
+                    Ok(Sexp::Atom(token.to_string()))
+                }
+            },
+            _ => Err(self.error("an S-expression")),
+        }
+    }
+}
+
+/* Encoding Rust data as S-expressions */
+
+struct Encoder;
+
+/* Collects the items of a compound value, after an optional head atom */
+struct Compound {
+    items: Vec<Sexp>,
+}
+
+impl Compound {
+    fn new(head: Option<&str>) -> Compound {
+        Compound { items: head.map(|h| Sexp::Atom(h.to_string())).into_iter().collect() }
+    }
+
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.items.push(value.serialize(Encoder)?);
+        Ok(())
+    }
+
+    fn push_field<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<(), Error> {
+        self.items.push(Sexp::List(vec![Sexp::Atom(key.to_string()), value.serialize(Encoder)?]));
+        Ok(())
+    }
+}
+
+/* Collects the (key value) pairs of a map */
+struct MapEncoder {
+    items: Vec<Sexp>,
+    key: Option<Sexp>,
+}
+
+impl ser::Serializer for Encoder {
+    type Ok = Sexp;
+    type Error = Error;
+    type SerializeSeq = Compound;
+    type SerializeTuple = Compound;
+    type SerializeTupleStruct = Compound;
+    type SerializeTupleVariant = Compound;
+    type SerializeMap = MapEncoder;
+    type SerializeStruct = Compound;
+    type SerializeStructVariant = Compound;
+
+    fn serialize_bool(self, v: bool) -> Result<Sexp, Error> {
+        Ok(Sexp::Atom(if v { "#t" } else { "#f" }.to_string()))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Sexp, Error> { Ok(Sexp::Int(v.into())) }
+    fn serialize_i16(self, v: i16) -> Result<Sexp, Error> { Ok(Sexp::Int(v.into())) }
+    fn serialize_i32(self, v: i32) -> Result<Sexp, Error> { Ok(Sexp::Int(v.into())) }
+    fn serialize_i64(self, v: i64) -> Result<Sexp, Error> { Ok(Sexp::Int(v.into())) }
+    fn serialize_i128(self, v: i128) -> Result<Sexp, Error> { Ok(Sexp::Int(v)) }
+    fn serialize_u8(self, v: u8) -> Result<Sexp, Error> { Ok(Sexp::Int(v.into())) }
+    fn serialize_u16(self, v: u16) -> Result<Sexp, Error> { Ok(Sexp::Int(v.into())) }
+    fn serialize_u32(self, v: u32) -> Result<Sexp, Error> { Ok(Sexp::Int(v.into())) }
+    fn serialize_u64(self, v: u64) -> Result<Sexp, Error> { Ok(Sexp::Int(v.into())) }
+
+    fn serialize_f32(self, _v: f32) -> Result<Sexp, Error> {
+        Err(Error("floats cannot be written as S-expressions".to_string()))
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Sexp, Error> {
+        Err(Error("floats cannot be written as S-expressions".to_string()))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Sexp, Error> {
+        Ok(Sexp::Str(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Sexp, Error> {
+        Ok(Sexp::Str(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Sexp, Error> {
+        Ok(Sexp::List(v.iter().map(|b| Sexp::Int((*b).into())).collect()))
+    }
+
+    fn serialize_none(self) -> Result<Sexp, Error> {
+        Ok(Sexp::Atom("nil".to_string()))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Sexp, Error> {
+        Ok(Sexp::List(vec![Sexp::Atom("some".to_string()), value.serialize(Encoder)?]))
+    }
+
+    fn serialize_unit(self) -> Result<Sexp, Error> {
+        Ok(Sexp::List(vec![]))
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<Sexp, Error> {
+        Ok(Sexp::Atom(name.to_string()))
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<Sexp, Error> {
+        Ok(Sexp::Atom(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<Sexp, Error> {
+        value.serialize(Encoder)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _index: u32, variant: &'static str, value: &T) -> Result<Sexp, Error> {
+        Ok(Sexp::List(vec![Sexp::Atom(variant.to_string()), value.serialize(Encoder)?]))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Compound, Error> {
+        Ok(Compound::new(None))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Compound, Error> {
+        Ok(Compound::new(None))
+    }
+
+    fn serialize_tuple_struct(self, name: &'static str, _len: usize) -> Result<Compound, Error> {
+        Ok(Compound::new(Some(name)))
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, variant: &'static str, _len: usize) -> Result<Compound, Error> {
+        Ok(Compound::new(Some(variant)))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapEncoder, Error> {
+        Ok(MapEncoder { items: vec![], key: None })
+    }
+
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<Compound, Error> {
+        Ok(Compound::new(Some(name)))
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, variant: &'static str, _len: usize) -> Result<Compound, Error> {
+        Ok(Compound::new(Some(variant)))
+    }
+}
+
+impl ser::SerializeSeq for Compound {
+    type Ok = Sexp;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Sexp, Error> {
+        Ok(Sexp::List(self.items))
+    }
+}
+
+impl ser::SerializeTuple for Compound {
+    type Ok = Sexp;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Sexp, Error> {
+        Ok(Sexp::List(self.items))
+    }
+}
+
+impl ser::SerializeTupleStruct for Compound {
+    type Ok = Sexp;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Sexp, Error> {
+        Ok(Sexp::List(self.items))
+    }
+}
+
+impl ser::SerializeTupleVariant for Compound {
+    type Ok = Sexp;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Sexp, Error> {
+        Ok(Sexp::List(self.items))
+    }
+}
+
+impl ser::SerializeStruct for Compound {
+    type Ok = Sexp;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        self.push_field(key, value)
+    }
+
+    fn end(self) -> Result<Sexp, Error> {
+        Ok(Sexp::List(self.items))
+    }
+}
+
+impl ser::SerializeStructVariant for Compound {
+    type Ok = Sexp;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        self.push_field(key, value)
+    }
+
+    fn end(self) -> Result<Sexp, Error> {
+        Ok(Sexp::List(self.items))
+    }
+}
+
+impl ser::SerializeMap for MapEncoder {
+    type Ok = Sexp;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = Some(key.serialize(Encoder)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self.key.take().ok_or_else(|| Error("map value without a key".to_string()))?;
+        self.items.push(Sexp::List(vec![key, value.serialize(Encoder)?]));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Sexp, Error> {
+        Ok(Sexp::List(self.items))
+    }
+}
+
+/* Decoding S-expressions as Rust data */
+
+struct Decoder(Sexp);
+
+fn mismatch(expected: &str, found: &Sexp) -> Error {
+    let mut text = String::new();
+    write_sexp(found, &mut text);
+    Error(format!("expected {} but found {}", expected, text))
+}
+
+impl Decoder {
+    fn list(self, expected: &str) -> Result<Vec<Sexp>, Error> {
+        match self.0 {
+            Sexp::List(items) => Ok(items),
+            s => Err(mismatch(expected, &s)),
+        }
+    }
+
+    /* The items of a list that starts with the atom name */
+    fn named_list(self, name: &str) -> Result<Vec<Sexp>, Error> {
+        let expected = format!("({} ...)", name);
+        match self.0 {
+            Sexp::List(mut items) if items.first() == Some(&Sexp::Atom(name.to_string())) => {
+                items.remove(0);
+                Ok(items)
+            },
+            s => Err(mismatch(&expected, &s)),
+        }
+    }
+
+    fn int(self) -> Result<i128, Error> {
+        match self.0 {
+            Sexp::Int(n) => Ok(n),
+            s => Err(mismatch("an integer", &s)),
+        }
+    }
+}
+
+/* Visits an integer as i64 when it is negative and u64 otherwise, which serde's
+ * own visitors range-check for every integer type */
+fn visit_int<'de, V: de::Visitor<'de>>(n: i128, visitor: V) -> Result<V::Value, Error> {
+    if let Ok(n) = u64::try_from(n) {
+        visitor.visit_u64(n)
+    } else if let Ok(n) = i64::try_from(n) {
+        visitor.visit_i64(n)
+    } else {
+        visitor.visit_i128(n)
+    }
+}
+
+/* Named fields as (field value) pairs, for structs, struct variants and maps */
+fn visit_fields<'de, V: de::Visitor<'de>>(items: Vec<Sexp>, visitor: V) -> Result<V::Value, Error> {
+    let mut pairs = vec![];
+    for item in items {
+        match item {
+            Sexp::List(pair) if pair.len() == 2 => {
+                let mut pair = pair.into_iter();
+                pairs.push((pair.next().unwrap(), pair.next().unwrap()));
+            },
+            s => return Err(mismatch("a (key value) pair", &s)),
+        }
+    }
+    visitor.visit_map(Fields { pairs: pairs.into_iter(), value: None })
+}
+
+macro_rules! decode_int {
+    ($($method:ident)*) => {
+        $(fn $method<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            visit_int(self.int()?, visitor)
+        })*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Decoder {
+    type Error = Error;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Sexp::Int(n) => visit_int(n, visitor),
+            Sexp::Str(s) => visitor.visit_string(s),
+            Sexp::Atom(a) if a == "#t" => visitor.visit_bool(true),
+            Sexp::Atom(a) if a == "#f" => visitor.visit_bool(false),
+            Sexp::Atom(a) => visitor.visit_string(a),
+            Sexp::List(items) => visitor.visit_seq(Items(items.into_iter())),
+        }
+    }
+
+    fn deserialize_bool<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Sexp::Atom(a) if a == "#t" => visitor.visit_bool(true),
+            Sexp::Atom(a) if a == "#f" => visitor.visit_bool(false),
+            s => Err(mismatch("#t or #f", &s)),
+        }
+    }
+
+    decode_int!(deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64 deserialize_i128
+                deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_u128);
+
+    fn deserialize_f32<V: de::Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(Error("floats cannot be read from S-expressions".to_string()))
+    }
+
+    fn deserialize_f64<V: de::Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(Error("floats cannot be read from S-expressions".to_string()))
+    }
+
+    fn deserialize_char<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_str<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Sexp::Str(s) => visitor.visit_string(s),
+            s => Err(mismatch("a string", &s)),
+        }
+    }
+
+    fn deserialize_bytes<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_byte_buf<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let bytes = self.list("a list of bytes")?.into_iter()
+            .map(|b| match b {
+                Sexp::Int(n) => u8::try_from(n).map_err(|_| mismatch("a byte", &b)),
+                b => Err(mismatch("a byte", &b)),
+            })
+            .collect::<Result<Vec<u8>, Error>>()?;
+        visitor.visit_byte_buf(bytes)
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Sexp::Atom(a) if a == "nil" => visitor.visit_none(),
+            s => visitor.visit_some(Decoder(Decoder(s).named_list("some")?.into_iter().next()
+                .ok_or_else(|| Error("expected a value after some".to_string()))?)),
+        }
+    }
+
+    fn deserialize_unit<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Sexp::List(items) if items.is_empty() => visitor.visit_unit(),
+            s => Err(mismatch("()", &s)),
+        }
+    }
+
+    fn deserialize_unit_struct<V: de::Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Sexp::Atom(a) if a == name => visitor.visit_unit(),
+            s => Err(mismatch(name, &s)),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(Items(self.list("a list")?.into_iter()))
+    }
+
+    fn deserialize_tuple<V: de::Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: de::Visitor<'de>>(self, name: &'static str, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(Items(self.named_list(name)?.into_iter()))
+    }
+
+    fn deserialize_map<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visit_fields(self.list("a list of (key value) pairs")?, visitor)
+    }
+
+    fn deserialize_struct<V: de::Visitor<'de>>(self, name: &'static str, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        visit_fields(self.named_list(name)?, visitor)
+    }
+
+    fn deserialize_enum<V: de::Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Sexp::Atom(a) => visitor.visit_enum(Variant { name: a, args: vec![] }),
+            Sexp::List(mut items) => match items.first() {
+                Some(Sexp::Atom(_)) => {
+                    let Sexp::Atom(a) = items.remove(0) else { unreachable!() };
+                    visitor.visit_enum(Variant { name: a, args: items })
+                },
+                _ => Err(mismatch("a variant", &Sexp::List(items))),
+            },
+            s => Err(mismatch("a variant", &s)),
+        }
+    }
+
+    fn deserialize_identifier<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Sexp::Atom(a) | Sexp::Str(a) => visitor.visit_string(a),
+            s => Err(mismatch("a name", &s)),
+        }
+    }
+
+    fn deserialize_ignored_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+}
+
+struct Items(std::vec::IntoIter<Sexp>);
+
+impl<'de> de::SeqAccess<'de> for Items {
+    type Error = Error;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Error> {
+        match self.0.next() {
+            Some(s) => seed.deserialize(Decoder(s)).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+struct Fields {
+    pairs: std::vec::IntoIter<(Sexp, Sexp)>,
+    value: Option<Sexp>,
+}
+
+impl<'de> de::MapAccess<'de> for Fields {
+    type Error = Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+        match self.pairs.next() {
+            Some((k, v)) => {
+                self.value = Some(v);
+                seed.deserialize(Decoder(k)).map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let v = self.value.take().ok_or_else(|| Error("map key without a value".to_string()))?;
+        seed.deserialize(Decoder(v))
+    }
+}
+
+struct Variant {
+    name: String,
+    args: Vec<Sexp>,
+}
+
+impl<'de> de::EnumAccess<'de> for Variant {
+    type Error = Error;
+    type Variant = Variant;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Variant), Error> {
+        let name: de::value::StringDeserializer<Error> = self.name.clone().into_deserializer();
+        Ok((seed.deserialize(name)?, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Variant {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        if self.args.is_empty() {
+            Ok(())
+        } else {
+            Err(Error(format!("variant {} takes no arguments", self.name)))
+        }
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        let [arg] = <[Sexp; 1]>::try_from(self.args)
+            .map_err(|_| Error(format!("variant {} takes one argument", self.name)))?;
+        seed.deserialize(Decoder(arg))
+    }
+
+    fn tuple_variant<V: de::Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(Items(self.args.into_iter()))
+    }
+
+    fn struct_variant<V: de::Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        visit_fields(self.args, visitor)
+    }
+}
+
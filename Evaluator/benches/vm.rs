@@ -0,0 +1,55 @@
+/* Compares the bytecode VM with eval_expr and the CEK machine on a few programs.
+ * Run with
+ *   cargo bench -p toi-evaluator
+ * Each program is run a few times by each evaluator and the fastest time is kept.
+ * The VM's time does not include compiling, which is reported on its own.
+ */
+use std::time::{Duration, Instant};
+use toi_evaluator::engine::Engine;
+use toi_evaluator::evaluator::{Evaluator, Expr, Value};
+use toi_parser::parser;
+
+const RUNS: usize = 5;
+
+const PROGRAMS: [(&str, &str); 4] = [
+  ("sum loop", "let rec sum(n, acc) { if n == 0 then acc else sum(n - 1, acc + n) } in sum(200000, 0)"),
+  ("fib", "let rec fib(n) { if n < 2 then n else fib(n - 1) + fib(n - 2) } in fib(20)"),
+  ("closures", "let function twice(g: (number) -> number, x) { g(g(x)) } in
+    let rec go(n, acc) {
+      let function add(x) { x + n } in
+      if n == 0 then acc else go(n - 1, twice(add, acc))
+    } in go(50000, 0)"),
+  ("even/odd", "let rec even(n): boolean { if n == 0 then true else odd(n - 1) }
+    and odd(n): boolean { if n == 0 then false else even(n - 1) } in even(200001)"),
+];
+
+/* The fastest of RUNS runs of f, and the value it gave */
+fn time(f: impl Fn() -> Value) -> (Duration, Value) {
+  let mut best = Duration::MAX;
+  let mut value = Value::Unit;
+  for _ in 0..RUNS {
+    let start = Instant::now();
+    value = f();
+    best = best.min(start.elapsed());
+  }
+  (best, value)
+}
+
+fn main() {
+  let engine = Engine::new();
+  let evaluator = Evaluator::new();
+  println!("{:<10} {:>12} {:>12} {:>12} {:>12} {:>8}", "program", "eval_expr", "cek", "vm", "compile", "speedup");
+  for (name, src) in PROGRAMS {
+    let e: Expr = parser::parser::expr(src).expect("benchmark programs parse");
+    let p = engine.check(e).expect("benchmark programs type-check");
+    let (tree, expected) = time(|| engine.run_with(&evaluator, &p).expect("eval_expr runs"));
+    let (cek, _) = time(|| engine.run_cek(&evaluator, &p).expect("the CEK machine runs"));
+    let compile_start = Instant::now();
+    let program = engine.compile(&p).expect("the program was checked by this engine");
+    let compile = compile_start.elapsed();
+    let (bytecode, got) = time(|| evaluator.run_compiled(&program).expect("the VM runs"));
+    assert_eq!(got, expected, "the VM and eval_expr disagree on {}", name);
+    println!("{:<10} {:>12.2?} {:>12.2?} {:>12.2?} {:>12.2?} {:>7.1}x", name, tree, cek, bytecode, compile,
+      tree.as_secs_f64() / bytecode.as_secs_f64());
+  }
+}
@@ -0,0 +1,208 @@
+/* Arbitrary-precision integers for the BigInt arithmetic policy (see
+ * ArithPolicy in evaluator.rs).
+ *
+ * A BigInt is a sign and a magnitude stored as base 2^32 digits, least
+ * significant first. The magnitude never has leading zero digits and zero is
+ * never negative, so equal numbers have equal representations and the derived
+ * Eq and Hash agree with the numeric value. Only the operations Toi needs are
+ * provided: +, -, *, comparison, conversion to and from i64 and printing.
+ */
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+use serde::{Deserialize, Serialize};
+
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct BigInt {
+  negative: bool,
+  magnitude: Vec<u32>,
+}
+
+impl BigInt {
+  fn new(negative: bool, mut magnitude: Vec<u32>) -> BigInt {
+    while magnitude.last() == Some(&0) {
+      magnitude.pop();
+    }
+    let negative = negative && !magnitude.is_empty();
+    BigInt { negative, magnitude }
+  }
+
+  pub fn is_negative(&self) -> bool {
+    self.negative
+  }
+
+  /* The lowest 64 bits of the magnitude */
+  fn low_u64(&self) -> u64 {
+    let lo = self.magnitude.first().copied().unwrap_or(0) as u64;
+    let hi = self.magnitude.get(1).copied().unwrap_or(0) as u64;
+    (hi << 32) | lo
+  }
+
+  /* The number as an i64, if it fits in one */
+  pub fn to_i64(&self) -> Option<i64> {
+    if self.magnitude.len() > 2 {
+      return None
+    }
+    let m = self.low_u64();
+    if self.negative {
+      0i64.checked_sub_unsigned(m)
+    } else {
+      i64::try_from(m).ok()
+    }
+  }
+
+  /* The number modulo 2^64, as two's-complement i64 arithmetic would give it */
+  pub fn wrapping_to_i64(&self) -> i64 {
+    let m = self.low_u64() as i64;
+    if self.negative { m.wrapping_neg() } else { m }
+  }
+
+  /* The i64 closest to the number */
+  pub fn saturating_to_i64(&self) -> i64 {
+    match self.to_i64() {
+      Some(n) => n,
+      None if self.negative => i64::MIN,
+      None => i64::MAX,
+    }
+  }
+}
+
+impl From<i64> for BigInt {
+  fn from(n: i64) -> BigInt {
+    let m = n.unsigned_abs();
+    BigInt::new(n < 0, vec![m as u32, (m >> 32) as u32])
+  }
+}
+
+fn cmp_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+  a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+  let mut sum = Vec::with_capacity(a.len().max(b.len()) + 1);
+  let mut carry = 0u64;
+  for i in 0..a.len().max(b.len()) {
+    let s = a.get(i).copied().unwrap_or(0) as u64 + b.get(i).copied().unwrap_or(0) as u64 + carry;
+    sum.push(s as u32);
+    carry = s >> 32;
+  }
+  sum.push(carry as u32);
+  sum
+}
+
+/* a - b, where a is at least b */
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+  let mut diff = Vec::with_capacity(a.len());
+  let mut borrow = 0i64;
+  for (i, d) in a.iter().enumerate() {
+    let mut s = *d as i64 - b.get(i).copied().unwrap_or(0) as i64 - borrow;
+    borrow = 0;
+    if s < 0 {
+      s += 1 << 32;
+      borrow = 1;
+    }
+    diff.push(s as u32);
+  }
+  diff
+}
+
+fn mul_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+  let mut product = vec![0u32; a.len() + b.len()];
+  for (i, x) in a.iter().enumerate() {
+    let mut carry = 0u64;
+    for (j, y) in b.iter().enumerate() {
+      let p = *x as u64 * *y as u64 + product[i + j] as u64 + carry;
+      product[i + j] = p as u32;
+      carry = p >> 32;
+    }
+    product[i + b.len()] = carry as u32;
+  }
+  product
+}
+
+/* Adds two numbers given as signs and magnitudes */
+fn add_signed(a_neg: bool, a: &[u32], b_neg: bool, b: &[u32]) -> BigInt {
+  if a_neg == b_neg {
+    return BigInt::new(a_neg, add_magnitude(a, b))
+  }
+  match cmp_magnitude(a, b) {
+    Ordering::Less => BigInt::new(b_neg, sub_magnitude(b, a)),
+    _ => BigInt::new(a_neg, sub_magnitude(a, b)),
+  }
+}
+
+impl Add for &BigInt {
+  type Output = BigInt;
+  fn add(self, other: &BigInt) -> BigInt {
+    add_signed(self.negative, &self.magnitude, other.negative, &other.magnitude)
+  }
+}
+
+impl Sub for &BigInt {
+  type Output = BigInt;
+  fn sub(self, other: &BigInt) -> BigInt {
+    add_signed(self.negative, &self.magnitude, !other.negative, &other.magnitude)
+  }
+}
+
+impl Mul for &BigInt {
+  type Output = BigInt;
+  fn mul(self, other: &BigInt) -> BigInt {
+    BigInt::new(self.negative != other.negative, mul_magnitude(&self.magnitude, &other.magnitude))
+  }
+}
+
+impl Neg for &BigInt {
+  type Output = BigInt;
+  fn neg(self) -> BigInt {
+    BigInt::new(!self.negative, self.magnitude.clone())
+  }
+}
+
+impl Ord for BigInt {
+  fn cmp(&self, other: &BigInt) -> Ordering {
+    match (self.negative, other.negative) {
+      (false, true) => Ordering::Greater,
+      (true, false) => Ordering::Less,
+      (false, false) => cmp_magnitude(&self.magnitude, &other.magnitude),
+      (true, true) => cmp_magnitude(&other.magnitude, &self.magnitude),
+    }
+  }
+}
+
+impl PartialOrd for BigInt {
+  fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+/* Prints in decimal, by repeatedly dividing the magnitude by 10^9 */
+impl fmt::Display for BigInt {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    const CHUNK: u64 = 1_000_000_000;
+    let mut rest = self.magnitude.clone();
+    let mut chunks = Vec::new();
+    while !rest.is_empty() {
+      let mut remainder = 0u64;
+      for d in rest.iter_mut().rev() {
+        let n = (remainder << 32) | *d as u64;
+        *d = (n / CHUNK) as u32;
+        remainder = n % CHUNK;
+      }
+      chunks.push(remainder);
+      while rest.last() == Some(&0) {
+        rest.pop();
+      }
+    }
+    if self.negative {
+      write!(f, "-")?;
+    }
+    match chunks.split_last() {
+      None => write!(f, "0"),
+      Some((first, others)) => {
+        write!(f, "{}", first)?;
+        others.iter().rev().try_for_each(|c| write!(f, "{:09}", c))
+      },
+    }
+  }
+}
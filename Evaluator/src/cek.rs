@@ -0,0 +1,322 @@
+/* A CEK machine for Toi: an evaluator that keeps its control (the expression
+ * being evaluated), environment and continuation in a loop instead of on the Rust
+ * stack, so that programs can recurse as deeply as the heap allows.
+ *
+ * The machine is always in one of two states:
+ *   Eval(e, E)  evaluate e in the environment E
+ *   Return(v)   hand the value v to the frame on top of the continuation stack
+ * Each frame remembers what is left to do once a subexpression has a value, e.g.
+ * Left(Plus(e1,e2), E) is waiting for the value of e1 before evaluating e2 in E.
+ * The machine stops when a value is returned to an empty stack.
+ *
+ * eval_cek follows the rules of eval_expr (see the header of evaluator.rs) one
+ * step at a time and gives the same value or error, at the same span, under the
+ * same arithmetic policy and limits. Like eval_expr it makes proper tail calls:
+ * a call in tail position replaces the frames of the call it is made from.
+ * Under call by name or by need (see strategy.rs) a thunk is forced by evaluating
+ * its argument on the machine, like any other subexpression.
+ */
+use rpds::HashTrieMap;
+use toi_ast::{Defn, Expr, Span, Type};
+use toi_typing::typer::CheckedProgram;
+use crate::engine::Engine;
+use crate::evaluator::{compare_numbers, holds, ArithOp, EnvRecord, EvalError, Evaluator, Resource, Value};
+use crate::profiler::Recorder;
+use crate::memo::Key;
+use crate::strategy::Thunk;
+
+type Env = HashTrieMap<String, EnvRecord>;
+
+enum State {
+  Eval(Expr, Env),
+  Return(Value),
+}
+
+/* The continuation frames. Left and Right hold the whole expression they belong to,
+ * from which the remaining operands and any error are taken. */
+enum Frame {
+  /* Waiting for the first operand of a Compare, Times, Plus or Minus, the
+   * condition of an Assert or If, or the value of a let var */
+  Left(Expr, Env),
+  /* Waiting for the second operand of a Compare, Times, Plus or Minus */
+  Right(Expr, Value),
+  /* Waiting for the message of an Assert whose condition was false */
+  Message,
+  /* Waiting for the arguments of a call: the value of callee has been looked up,
+   * and done holds the arguments evaluated so far */
+  Args { call: Expr, callee: Value, done: Vec<Value>, env: Env },
+  /* Waiting for the value of the function called by a call, which was a thunk */
+  Callee(Expr, Env),
+  /* Waiting for the value of the argument of a thunk, to keep it if it is by need */
+  Force(Thunk),
+  /* Waiting for the body of a function called when depth calls were in progress,
+   * whose value is the value of the memoized calls (see memo.rs) */
+  Return(usize, Vec<Key>),
+  Spanned(Span),
+}
+
+fn is_number(v: &Value) -> bool {
+  matches!(v, Value::Numeral(_) | Value::BigNumeral(_))
+}
+
+/* The arithmetic operator of e, if e is Times, Plus or Minus */
+fn arith_op(e: &Expr) -> Option<ArithOp> {
+  match e {
+    Expr::Times(_, _) => Some(ArithOp::Times),
+    Expr::Plus(_, _) => Some(ArithOp::Plus),
+    Expr::Minus(_, _) => Some(ArithOp::Minus),
+    _ => None,
+  }
+}
+
+/* Evaluates a checked program with the default settings on a CEK machine, as
+ * eval_program does with eval_expr */
+pub fn eval_cek(p: &CheckedProgram) -> Result<Value, EvalError> {
+  Engine::standard(p).run_cek(&Evaluator::default(), p)
+}
+
+impl Evaluator {
+  /* Evaluates e in env on a CEK machine, within this evaluator's limits */
+  pub(crate) fn eval_cek(&self, env: &Env, e: &Expr) -> Result<Value, EvalError> {
+    self.start();
+    let mut stack = vec![];
+    let mut state = State::Eval(e.clone(), env.clone());
+    loop {
+      let next = match state {
+        State::Eval(e, env) => self.cek_eval(e, env, &mut stack),
+        State::Return(v) => match stack.pop() {
+          Some(frame) => self.cek_return(frame, v, &mut stack),
+          None => return Ok(v),
+        },
+      };
+      state = match next {
+        Ok(s) => s,
+        // The innermost span still on the stack is where the error happened
+        Err(err) => return Err(match stack.iter().rev().find_map(|f| match f { Frame::Spanned(s) => Some(*s), _ => None }) {
+          Some(s) => err.at(s),
+          None => err,
+        }),
+      };
+    }
+  }
+
+  /* Evaluates d in env like eval_defn, running the expression of a var
+   * definition on a CEK machine */
+  pub(crate) fn eval_defn_cek(&self, env: &Env, d: &Defn) -> Result<Env, EvalError> {
+    match d {
+      Defn::VarDefn(x, e) => {
+        let v = self.eval_cek(env, e)?;
+        self.check_env(env.insert(x.clone(), EnvRecord::VarRecord(v)))
+      },
+      d => self.eval_defn(env, d),
+    }
+  }
+
+  /* Takes one step from Eval(e, env) */
+  fn cek_eval(&self, e: Expr, env: Env, stack: &mut Vec<Frame>) -> Result<State, EvalError> {
+    if !matches!(e, Expr::Spanned(_, _)) {
+      self.step()?;
+    }
+    match e {
+      Expr::Id(s) => match env.get(&s) {
+        Some(env_record) => Ok(self.cek_force(env_record.value(), stack)),
+        None => Err(EvalError::UnboundVariable(s.clone(), Expr::Id(s))),
+      },
+      Expr::Numeral(n) => Ok(State::Return(Value::Numeral(n))),
+      Expr::Decimal(_, _) => Err(EvalError::FractionalNumeral(e)),
+      Expr::StringLiteral(s) => Ok(State::Return(Value::String(s))),
+      Expr::True => Ok(State::Return(Value::Boolean(true))),
+      Expr::False => Ok(State::Return(Value::Boolean(false))),
+      Expr::Compare(ref l, _, _) | Expr::Times(ref l, _) | Expr::Plus(ref l, _) | Expr::Minus(ref l, _)
+      | Expr::Assert(ref l, _) | Expr::If(ref l, _, _) => {
+        let first = (**l).clone();
+        stack.push(Frame::Left(e, env.clone()));
+        Ok(State::Eval(first, env))
+      },
+      Expr::Let(ref d, _) => match &**d {
+        Defn::VarDefn(_, v) => {
+          let first = (**v).clone();
+          stack.push(Frame::Left(e, env.clone()));
+          Ok(State::Eval(first, env))
+        },
+        d => {
+          let Expr::Let(_, body) = e else { unreachable!() };
+          Ok(State::Eval(*body, self.defn(&env, d)?))
+        },
+      },
+      Expr::Call(ref f, _) => {
+        let Some(env_record) = env.get(f) else {
+          return Err(EvalError::UnboundVariable(f.clone(), e))
+        };
+        match env_record.value() {
+          thunk @ Value::Thunk(_) => {
+            stack.push(Frame::Callee(e, env));
+            Ok(self.cek_force(thunk, stack))
+          },
+          callee => self.cek_callee(e, env, callee, stack),
+        }
+      },
+      Expr::Spanned(s, inner) => {
+        stack.push(Frame::Spanned(s));
+        Ok(State::Eval(*inner, env))
+      },
+    }
+  }
+
+  /* Returns v, or evaluates the argument of v if it is a thunk not forced yet */
+  fn cek_force(&self, v: Value, stack: &mut Vec<Frame>) -> State {
+    match v {
+      Value::Thunk(t) => match t.forced() {
+        Some(v) => State::Return(v),
+        None => {
+          let (argument, env) = (t.expr.clone(), t.env.clone());
+          stack.push(Frame::Force(t));
+          State::Eval(argument, env)
+        },
+      },
+      v => State::Return(v),
+    }
+  }
+
+  /* Goes on with the call e in env once the value of the function it calls is known */
+  fn cek_callee(&self, e: Expr, env: Env, callee: Value, stack: &mut Vec<Frame>) -> Result<State, EvalError> {
+    let Expr::Call(ref f, ref a) = e else { unreachable!("only a call has a callee") };
+    match &callee {
+      Value::Closure(c) if c.params().len() != a.len() =>
+        return Err(EvalError::ArityMismatch(f.clone(), c.params().to_vec(), e)),
+      Value::Native(n) if n.arity() != a.len() =>
+        return Err(EvalError::ArityMismatch(f.clone(), n.params().iter().map(Type::to_string).collect(), e)),
+      Value::Closure(_) | Value::Native(_) => {},
+      _ => return Err(EvalError::NotAFunction(f.clone(), e)),
+    }
+    if let Value::Closure(_) = callee {
+      if let Some(thunks) = self.delay(&env, a) {
+        return self.cek_call(&e, &callee, thunks, stack)
+      }
+    }
+    match a.first() {
+      Some(first) => {
+        let first = first.clone();
+        stack.push(Frame::Args { call: e, callee, done: vec![], env: env.clone() });
+        Ok(State::Eval(first, env))
+      },
+      None => self.cek_call(&e, &callee, vec![], stack),
+    }
+  }
+
+  /* Takes one step from Return(v) with frame on top of the stack */
+  fn cek_return(&self, frame: Frame, v: Value, stack: &mut Vec<Frame>) -> Result<State, EvalError> {
+    match frame {
+      Frame::Left(e, env) => match &e {
+        Expr::Compare(l, _, r) | Expr::Times(l, r) | Expr::Plus(l, r) | Expr::Minus(l, r) => {
+          if !is_number(&v) {
+            return Err(EvalError::TypeMismatch(Type::Number, (**l).clone()))
+          }
+          let second = (**r).clone();
+          stack.push(Frame::Right(e, v));
+          Ok(State::Eval(second, env))
+        },
+        Expr::Assert(c, m) => match v {
+          Value::Boolean(true) => Ok(State::Return(Value::Boolean(true))),
+          Value::Boolean(false) => {
+            stack.push(Frame::Message);
+            Ok(State::Eval((**m).clone(), env))
+          },
+          _ => Err(EvalError::TypeMismatch(Type::Boolean, (**c).clone())),
+        },
+        Expr::If(c, t, f) => match v {
+          Value::Boolean(true) => Ok(State::Eval((**t).clone(), env)),
+          Value::Boolean(false) => Ok(State::Eval((**f).clone(), env)),
+          _ => Err(EvalError::TypeMismatch(Type::Boolean, (**c).clone())),
+        },
+        Expr::Let(d, body) => match &**d {
+          Defn::VarDefn(x, _) => {
+            let env = self.check_env(env.insert(x.clone(), EnvRecord::VarRecord(v)))?;
+            Ok(State::Eval((**body).clone(), env))
+          },
+          _ => unreachable!("only a let var waits for a value"),
+        },
+        _ => unreachable!("no other expression waits for its first operand"),
+      },
+      Frame::Right(e, l_val) => {
+        let (Expr::Compare(_, _, r) | Expr::Times(_, r) | Expr::Plus(_, r) | Expr::Minus(_, r)) = &e else {
+          unreachable!("only a binary operator waits for its second operand")
+        };
+        if !is_number(&v) {
+          return Err(EvalError::TypeMismatch(Type::Number, (**r).clone()))
+        }
+        match (&e, arith_op(&e)) {
+          (_, Some(op)) => self.arith.apply(op, &l_val, &v)
+            .map(State::Return)
+            .ok_or_else(|| EvalError::IntegerOverflow(op.symbol().to_string(), e.clone())),
+          (Expr::Compare(_, c, _), None) => Ok(State::Return(Value::Boolean(holds(c, compare_numbers(&l_val, &v))))),
+          _ => unreachable!(),
+        }
+      },
+      Frame::Message => match v {
+        Value::String(message) => Err(EvalError::AssertionFailed(message)),
+        v => Err(EvalError::AssertionFailed(v.to_string())),
+      },
+      Frame::Args { call, callee, mut done, env } => {
+        done.push(v);
+        let Expr::Call(_, a) = &call else { unreachable!("only a call waits for arguments") };
+        match a.get(done.len()) {
+          Some(next) => {
+            let next = next.clone();
+            stack.push(Frame::Args { call, callee, done, env: env.clone() });
+            Ok(State::Eval(next, env))
+          },
+          None => self.cek_call(&call, &callee, done, stack),
+        }
+      },
+      Frame::Callee(call, env) => self.cek_callee(call, env, v, stack),
+      Frame::Force(t) => {
+        t.keep(&v);
+        Ok(State::Return(v))
+      },
+      Frame::Return(depth, calls) => {
+        self.depth.set(depth);
+        self.record(Recorder::exit);
+        self.remember(calls, &v);
+        Ok(State::Return(v))
+      },
+      Frame::Spanned(_) => Ok(State::Return(v)),
+    }
+  }
+
+  /* Enters the function callee with its arguments evaluated */
+  fn cek_call(&self, call: &Expr, callee: &Value, args: Vec<Value>, stack: &mut Vec<Frame>) -> Result<State, EvalError> {
+    let Expr::Call(f, _) = call else { unreachable!("only a call calls a function") };
+    match callee {
+      Value::Closure(c) => {
+        let key = match self.recall(c, &args) {
+          Ok(v) => return Ok(State::Return(v)),
+          Err(key) => key,
+        };
+        // A call is in tail position when nothing but spans lies between it and
+        // the Return of the call whose body it is, and then it takes that call's place
+        let spans = stack.iter().rev().take_while(|f| matches!(f, Frame::Spanned(_))).count();
+        if let Some(Frame::Return(_, calls)) = stack.iter_mut().rev().nth(spans) {
+          calls.extend(key);
+          let call_env = self.check_env(c.call_env(args))?;
+          let innermost = if spans > 0 { stack.pop() } else { None };
+          stack.truncate(stack.len() - spans.saturating_sub(1));
+          stack.extend(innermost);
+          self.record(|r| r.tail(c.name()));
+          return Ok(State::Eval(c.body().clone(), call_env))
+        }
+        let depth = self.depth.get();
+        if depth >= self.limits.max_depth {
+          return Err(EvalError::ResourceExhausted(Resource::Depth(self.limits.max_depth)))
+        }
+        let call_env = self.check_env(c.call_env(args))?;
+        self.depth.set(depth + 1);
+        stack.push(Frame::Return(depth, key.into_iter().collect()));
+        self.record(|r| r.enter(c.name()));
+        Ok(State::Eval(c.body().clone(), call_env))
+      },
+      Value::Native(n) => n.call(&args).map(State::Return).ok_or_else(|| EvalError::NativeCallFailed(f.clone(), call.clone())),
+      _ => unreachable!("callees are checked to be functions before their arguments"),
+    }
+  }
+}
@@ -0,0 +1,331 @@
+/* A debugger for Toi programs, which evaluates like eval_expr but pauses to let
+ * a Controller look at the state of the evaluation and say how to go on.
+ *
+ * Evaluation pauses before an expression that does some work: an operator, let,
+ * call, assert or if, or the body of a function just entered, whatever it is.
+ * Names and literals are not worth stopping at. It pauses
+ *   at the first such expression, so that breakpoints can be set
+ *   on entering a function with a Breakpoint::Function
+ *   on reaching a source line with a Breakpoint::Line, once per visit to the line
+ *   after a step:
+ *     StepInto stops at the very next expression, perhaps inside a call
+ *     StepOver stops at the next expression once the current one is evaluated.
+ *       The body of a let and the branches of an if take the place of the let or
+ *       if, so stepping over one of those goes on to its body or branch.
+ *     StepOut stops at the next expression once the current function returns
+ * and at each pause the Controller gets the expression, its environment and
+ * span, the call stack and the breakpoints, which it may change.
+ *
+ * Calls are made on the Rust stack, so the debugger is meant for programs that
+ * do not recurse very deeply. The evaluator's limits still apply, and a program
+ * stops with EvalError::ResourceExhausted once STACK_DEPTH calls are in progress
+ * even if its max_depth is higher.
+ */
+use rpds::HashTrieMap;
+use toi_ast::{Defn, Expr, Span, Type};
+use crate::evaluator::{compare_numbers, holds, ArithOp, Closure, EnvRecord, EvalError, Evaluator, Resource, Value};
+
+type Env = HashTrieMap<String, EnvRecord>;
+
+/* A place where evaluation pauses whatever the controller last asked for */
+#[derive(Hash, Eq, PartialEq, Debug, Clone)]
+pub enum Breakpoint {
+  Function(String),
+  Line(usize),
+}
+
+/* How evaluation goes on after a pause */
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Command {
+  Continue,
+  StepInto,
+  StepOver,
+  StepOut,
+  Quit,
+}
+
+/* A call of a Toi function in progress: the function, its arguments, and the
+ * span of the call that made it, if known */
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Frame {
+  pub function: String,
+  pub args: Vec<(String, Value)>,
+  pub call: Option<Span>,
+}
+
+/* Why evaluation paused */
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Reason {
+  Start,
+  Step,
+  Breakpoint(Breakpoint),
+}
+
+/* The state of the evaluation at a pause. span is the innermost span around
+ * expr, and line its line, if the debugger was given the source text. The
+ * stack has the innermost call last. */
+pub struct Pause<'a> {
+  pub reason: Reason,
+  pub expr: &'a Expr,
+  pub env: &'a Env,
+  pub span: Option<Span>,
+  pub line: Option<usize>,
+  pub stack: &'a [Frame],
+}
+
+/* What decides how a paused evaluation goes on, such as a person at a console */
+pub trait Controller {
+  fn pause(&mut self, pause: &Pause, breakpoints: &mut Vec<Breakpoint>) -> Command;
+}
+
+/* The settings and breakpoints a program is debugged with */
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+  pub evaluator: Evaluator,
+  pub source: Option<String>,
+  pub breakpoints: Vec<Breakpoint>,
+}
+
+/* Why a debugged evaluation stopped early: an error, or the controller quit */
+enum Halt {
+  Error(EvalError),
+  Quit,
+}
+
+impl From<EvalError> for Halt {
+  fn from(err: EvalError) -> Halt {
+    Halt::Error(err)
+  }
+}
+
+impl Halt {
+  fn at(self, span: Span) -> Halt {
+    match self {
+      Halt::Error(err) => Halt::Error(err.at(span)),
+      Halt::Quit => Halt::Quit,
+    }
+  }
+}
+
+/* When to pause next, other than at a breakpoint */
+#[derive(Clone, Copy)]
+enum Mode {
+  Start,
+  Into,
+  Over(usize),
+  Out(usize),
+  Continue,
+}
+
+/* One debugged evaluation in progress */
+struct Run<'a> {
+  debugger: &'a mut Debugger,
+  controller: &'a mut dyn Controller,
+  mode: Mode,
+  stack: Vec<Frame>,
+  nesting: usize,
+  last_line: Option<usize>,
+  entered: Option<String>,
+}
+
+impl Debugger {
+  pub fn new(evaluator: Evaluator) -> Debugger {
+    Debugger { evaluator, source: None, breakpoints: vec![] }
+  }
+
+  /* The text of the program, which lines are counted in */
+  pub fn with_source(mut self, text: &str) -> Debugger {
+    self.source = Some(text.to_string());
+    self
+  }
+
+  pub fn with_breakpoint(mut self, b: Breakpoint) -> Debugger {
+    self.breakpoints.push(b);
+    self
+  }
+
+  /* Evaluates e in env under controller, giving its value or error like
+   * eval_expr, or None if the controller quit */
+  pub(crate) fn debug(&mut self, env: &Env, e: &Expr, controller: &mut dyn Controller) -> Option<Result<Value, EvalError>> {
+    self.evaluator.start();
+    let mut run = Run { debugger: self, controller, mode: Mode::Start, stack: vec![], nesting: 0, last_line: None, entered: None };
+    match run.eval(env, e, None) {
+      Ok(v) => Some(Ok(v)),
+      Err(Halt::Error(err)) => Some(Err(err)),
+      Err(Halt::Quit) => None,
+    }
+  }
+}
+
+impl Run<'_> {
+  /* Pauses before e if the mode or a breakpoint says to */
+  fn reach(&mut self, env: &Env, e: &Expr, span: Option<Span>) -> Result<(), Halt> {
+    let entered = self.entered.take();
+    if entered.is_none() && matches!(e, Expr::Id(_) | Expr::Numeral(_) | Expr::StringLiteral(_) | Expr::True | Expr::False) {
+      return Ok(())
+    }
+    let line = match (span, &self.debugger.source) {
+      (Some(s), Some(text)) => Some(s.line_col(text).0),
+      _ => None,
+    };
+    let mut reason = match self.mode {
+      Mode::Start => Some(Reason::Start),
+      Mode::Into => Some(Reason::Step),
+      Mode::Over(nesting) if self.nesting <= nesting => Some(Reason::Step),
+      Mode::Out(depth) if self.stack.len() < depth => Some(Reason::Step),
+      _ => None,
+    };
+    if let Some(l) = line.filter(|l| self.last_line != Some(*l)) {
+      self.last_line = Some(l);
+      if self.debugger.breakpoints.contains(&Breakpoint::Line(l)) {
+        reason = Some(Reason::Breakpoint(Breakpoint::Line(l)));
+      }
+    }
+    if let Some(f) = entered.filter(|f| self.debugger.breakpoints.contains(&Breakpoint::Function(f.clone()))) {
+      reason = Some(Reason::Breakpoint(Breakpoint::Function(f)));
+    }
+    let Some(reason) = reason else { return Ok(()) };
+    let pause = Pause { reason, expr: e, env, span, line, stack: &self.stack };
+    self.mode = match self.controller.pause(&pause, &mut self.debugger.breakpoints) {
+      Command::Continue => Mode::Continue,
+      Command::StepInto => Mode::Into,
+      Command::StepOver => Mode::Over(self.nesting),
+      Command::StepOut => Mode::Out(self.stack.len()),
+      Command::Quit => return Err(Halt::Quit),
+    };
+    Ok(())
+  }
+
+  /* Evaluates e, whose innermost enclosing span is span */
+  fn eval(&mut self, env: &Env, e: &Expr, span: Option<Span>) -> Result<Value, Halt> {
+    if let Expr::Spanned(s, inner) = e {
+      return self.eval(env, inner, Some(*s)).map_err(|h| h.at(*s))
+    }
+    self.debugger.evaluator.step()?;
+    self.nesting += 1;
+    let result = self.reach(env, e, span).and_then(|()| self.expr(env, e, span));
+    self.nesting -= 1;
+    result
+  }
+
+  /* Evaluates e in the place of the expression being evaluated */
+  fn tail(&mut self, env: &Env, e: &Expr, span: Option<Span>) -> Result<Value, Halt> {
+    self.nesting -= 1;
+    let result = self.eval(env, e, span);
+    self.nesting += 1;
+    result
+  }
+
+  fn number(&mut self, env: &Env, e: &Expr, span: Option<Span>) -> Result<Value, Halt> {
+    match self.eval(env, e, span)? {
+      v @ (Value::Numeral(_) | Value::BigNumeral(_)) => Ok(v),
+      _ => Err(EvalError::TypeMismatch(Type::Number, e.clone()).into()),
+    }
+  }
+
+  fn boolean(&mut self, env: &Env, e: &Expr, span: Option<Span>) -> Result<bool, Halt> {
+    match self.eval(env, e, span)? {
+      Value::Boolean(b) => Ok(b),
+      _ => Err(EvalError::TypeMismatch(Type::Boolean, e.clone()).into()),
+    }
+  }
+
+  fn arith(&mut self, env: &Env, e: &Expr, l: &Expr, r: &Expr, op: ArithOp, span: Option<Span>) -> Result<Value, Halt> {
+    let l_val = self.number(env, l, span)?;
+    let r_val = self.number(env, r, span)?;
+    let v = self.debugger.evaluator.arith.apply(op, &l_val, &r_val);
+    v.ok_or_else(|| EvalError::IntegerOverflow(op.symbol().to_string(), e.clone()).into())
+  }
+
+  fn args(&mut self, env: &Env, a: &[Expr], span: Option<Span>) -> Result<Vec<Value>, Halt> {
+    a.iter().map(|ai| self.eval(env, ai, span)).collect()
+  }
+
+  /* Runs the body of c with args, in a new frame */
+  fn call(&mut self, c: &Closure, args: Vec<Value>, span: Option<Span>) -> Result<Value, Halt> {
+    let max_depth = self.debugger.evaluator.stack_depth();
+    if self.stack.len() >= max_depth {
+      return Err(EvalError::ResourceExhausted(Resource::Depth(max_depth)).into())
+    }
+    let frame_args = c.params().iter().cloned().zip(args.iter().cloned()).collect();
+    let call_env = self.debugger.evaluator.check_env(c.call_env(args))?;
+    self.stack.push(Frame { function: c.name().to_string(), args: frame_args, call: span });
+    self.entered = Some(c.name().to_string());
+    let result = self.eval(&call_env, c.body(), None);
+    self.stack.pop();
+    result
+  }
+
+  fn defn(&mut self, env: &Env, d: &Defn, span: Option<Span>) -> Result<Env, Halt> {
+    match d {
+      Defn::VarDefn(x, e) => {
+        let v = self.eval(env, e, span)?;
+        Ok(self.debugger.evaluator.check_env(env.insert(x.clone(), EnvRecord::VarRecord(v)))?)
+      },
+      _ => Ok(self.debugger.evaluator.defn(env, d)?),
+    }
+  }
+
+  fn expr(&mut self, env: &Env, e: &Expr, span: Option<Span>) -> Result<Value, Halt> {
+    match e {
+      Expr::Id(x) => match env.get(x) {
+        Some(r) => Ok(r.value()),
+        None => Err(EvalError::UnboundVariable(x.clone(), e.clone()).into()),
+      },
+      Expr::Numeral(n) => Ok(Value::Numeral(*n)),
+      Expr::Decimal(_, _) => Err(EvalError::FractionalNumeral(e.clone()).into()),
+      Expr::StringLiteral(s) => Ok(Value::String(s.clone())),
+      Expr::True => Ok(Value::Boolean(true)),
+      Expr::False => Ok(Value::Boolean(false)),
+      Expr::Compare(l, c, r) => {
+        let l_val = self.number(env, l, span)?;
+        let r_val = self.number(env, r, span)?;
+        Ok(Value::Boolean(holds(c, compare_numbers(&l_val, &r_val))))
+      },
+      Expr::Times(l, r) => self.arith(env, e, l, r, ArithOp::Times, span),
+      Expr::Plus(l, r) => self.arith(env, e, l, r, ArithOp::Plus, span),
+      Expr::Minus(l, r) => self.arith(env, e, l, r, ArithOp::Minus, span),
+      Expr::Let(d, body) => {
+        let extended = self.defn(env, d, span)?;
+        self.tail(&extended, body, span)
+      },
+      Expr::Call(f, a) => {
+        let callee = match env.get(f) {
+          Some(r) => r.value(),
+          None => return Err(EvalError::UnboundVariable(f.clone(), e.clone()).into()),
+        };
+        match callee {
+          Value::Closure(c) => {
+            if c.params().len() != a.len() {
+              return Err(EvalError::ArityMismatch(f.clone(), c.params().to_vec(), e.clone()).into())
+            }
+            let args = self.args(env, a, span)?;
+            self.call(&c, args, span)
+          },
+          Value::Native(n) => {
+            if n.arity() != a.len() {
+              return Err(EvalError::ArityMismatch(f.clone(), n.params().iter().map(Type::to_string).collect(), e.clone()).into())
+            }
+            let args = self.args(env, a, span)?;
+            n.call(&args).ok_or_else(|| EvalError::NativeCallFailed(f.clone(), e.clone()).into())
+          },
+          _ => Err(EvalError::NotAFunction(f.clone(), e.clone()).into()),
+        }
+      },
+      Expr::Assert(c, m) => {
+        if self.boolean(env, c, span)? {
+          return Ok(Value::Boolean(true))
+        }
+        match self.eval(env, m, span)? {
+          Value::String(message) => Err(EvalError::AssertionFailed(message).into()),
+          v => Err(EvalError::AssertionFailed(v.to_string()).into()),
+        }
+      },
+      Expr::If(c, t, f) => {
+        let branch = if self.boolean(env, c, span)? { t } else { f };
+        self.tail(env, branch, span)
+      },
+      Expr::Spanned(_, _) => unreachable!("spans are taken off in eval"),
+    }
+  }
+}
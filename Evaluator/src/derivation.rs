@@ -0,0 +1,368 @@
+/* Derivation trees for the big-step rules in the header of evaluator.rs, which
+ * show which rule gave each value in a program:
+ *   [Call] {sq ↦ <function sq>} ⊢ sq(4) ⇓ 16
+ *     [Number] {sq ↦ <function sq>} ⊢ 4 ⇓ 4
+ *     [Times] {sq ↦ <function sq>, x ↦ 4} ⊢ x*x ⇓ 16
+ *       [Id] {sq ↦ <function sq>, x ↦ 4} ⊢ x ⇓ 4
+ *       [Id] {sq ↦ <function sq>, x ↦ 4} ⊢ x ⇓ 4
+ *
+ * Each node is a judgement E ⊢ e ⇓ v (or E ⊢ d ⇓ E' for a definition), the rule
+ * that concludes it, and the derivations of the rule's premises, in the order
+ * eval_expr evaluates them. Spanned wrappers are left out, since their rule just
+ * passes on the judgement of the expression inside.
+ *
+ * derive evaluates like eval_expr, with the same limits and arithmetic policy,
+ * but keeps the whole tree, so it is meant for small programs. It makes each
+ * call on the Rust stack, so it also stops once STACK_DEPTH calls are in
+ * progress. A program that stops with an error has no derivation, only the error
+ * eval_expr gives.
+ *
+ * to_text renders a tree as indented lines, conclusion first, and to_latex as a
+ * prooftree of the LaTeX bussproofs package, premises above the line. Native
+ * functions, which are in scope everywhere, are left out of the environments.
+ */
+use rpds::HashTrieMap;
+use toi_ast::{Defn, Expr, Type};
+use toi_typing::typer::CheckedProgram;
+use toi_parser::parser::{decl_to_string, expr_to_string};
+use crate::engine::Engine;
+use crate::evaluator::{compare_numbers, holds, ArithOp, Closure, EnvRecord, EvalError, Evaluator, Resource, Value};
+
+type Env = HashTrieMap<String, EnvRecord>;
+
+/* The rules of the big-step semantics, named after the case of eval_expr or
+ * eval_defn they come from */
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Rule {
+  Id,
+  Number,
+  String,
+  True,
+  False,
+  Compare,
+  Times,
+  Plus,
+  Minus,
+  Let,
+  Call,
+  CallNative,
+  Assert,
+  IfTrue,
+  IfFalse,
+  Var,
+  Fun,
+  Rec,
+}
+
+impl Rule {
+  pub fn name(self) -> &'static str {
+    match self {
+      Rule::Id => "Id",
+      Rule::Number => "Number",
+      Rule::String => "String",
+      Rule::True => "True",
+      Rule::False => "False",
+      Rule::Compare => "Compare",
+      Rule::Times => "Times",
+      Rule::Plus => "Plus",
+      Rule::Minus => "Minus",
+      Rule::Let => "Let",
+      Rule::Call => "Call",
+      Rule::CallNative => "CallNative",
+      Rule::Assert => "Assert",
+      Rule::IfTrue => "IfTrue",
+      Rule::IfFalse => "IfFalse",
+      Rule::Var => "Var",
+      Rule::Fun => "Fun",
+      Rule::Rec => "Rec",
+    }
+  }
+}
+
+/* What a node of a derivation concludes: that an expression evaluates to a
+ * value, or that a definition extends the environment to another */
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Judgement {
+  Expr(Expr, Value),
+  Defn(Defn, Env),
+}
+
+/* A derivation of the judgement env ⊢ judgement by rule from premises */
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Derivation {
+  pub rule: Rule,
+  pub env: Env,
+  pub judgement: Judgement,
+  pub premises: Vec<Derivation>,
+}
+
+/* Derives the value of a checked program with the default settings, run as
+ * eval_program runs it */
+pub fn derive(p: &CheckedProgram) -> Result<Derivation, EvalError> {
+  Engine::standard(p).derive(&Evaluator::default(), p)
+}
+
+impl Derivation {
+  fn new(rule: Rule, env: &Env, judgement: Judgement, premises: Vec<Derivation>) -> Derivation {
+    Derivation { rule, env: env.clone(), judgement, premises }
+  }
+
+  /* The value an expression judgement concludes with */
+  pub fn value(&self) -> Option<&Value> {
+    match &self.judgement {
+      Judgement::Expr(_, v) => Some(v),
+      Judgement::Defn(_, _) => None,
+    }
+  }
+
+  /* The number of nodes in the tree */
+  pub fn size(&self) -> usize {
+    1 + self.premises.iter().map(Derivation::size).sum::<usize>()
+  }
+
+  /* The tree as one line per judgement, each premise indented under the
+   * judgement it is a premise of */
+  pub fn to_text(&self) -> String {
+    let mut out = String::new();
+    self.write_text(0, &mut out);
+    out
+  }
+
+  fn write_text(&self, depth: usize, out: &mut String) {
+    let (subject, result) = match &self.judgement {
+      Judgement::Expr(e, v) => (expr_to_string(e.clone()), v.to_string()),
+      Judgement::Defn(d, env) => (decl_to_string(d.clone()), env_to_text(env)),
+    };
+    out.push_str(&format!("{}[{}] {} ⊢ {} ⇓ {}\n", "  ".repeat(depth), self.rule.name(), env_to_text(&self.env), subject, result));
+    for p in &self.premises {
+      p.write_text(depth + 1, out);
+    }
+  }
+
+  /* The tree as a bussproofs prooftree, to be typeset in a document that uses
+   * \usepackage{bussproofs} */
+  pub fn to_latex(&self) -> String {
+    let mut out = String::from("\\begin{prooftree}\n");
+    self.write_latex(&mut out);
+    out.push_str("\\end{prooftree}\n");
+    out
+  }
+
+  /* Writes the premises, each of which leaves one tree on bussproofs' stack, and
+   * then the inference that takes them off. No inference takes more than five,
+   * so the premises of a call with more arguments are joined five at a time by
+   * inferences with no line and nothing below it. */
+  fn write_latex(&self, out: &mut String) {
+    if self.premises.is_empty() {
+      out.push_str("\\AxiomC{}\n");
+    }
+    let mut pending = 0;
+    for p in &self.premises {
+      if pending == 5 {
+        out.push_str("\\noLine\n\\QuinaryInfC{}\n");
+        pending = 1;
+      }
+      p.write_latex(out);
+      pending += 1;
+    }
+    let (subject, result) = match &self.judgement {
+      Judgement::Expr(e, v) => (tt(&expr_to_string(e.clone())), tt(&v.to_string())),
+      Judgement::Defn(d, env) => (tt(&decl_to_string(d.clone())), env_to_latex(env)),
+    };
+    let inference = match pending.max(1) {
+      1 => "UnaryInfC",
+      2 => "BinaryInfC",
+      3 => "TrinaryInfC",
+      4 => "QuaternaryInfC",
+      _ => "QuinaryInfC",
+    };
+    out.push_str(&format!("\\RightLabel{{\\scriptsize {}}}\n\\{}{{${} \\vdash {} \\Downarrow {}$}}\n",
+      self.rule.name(), inference, env_to_latex(&self.env), subject, result));
+  }
+}
+
+/* The names of env other than native functions, in order, with their values */
+fn bindings(env: &Env) -> Vec<(&String, String)> {
+  let mut bindings: Vec<(&String, String)> = env.iter()
+    .filter(|(_, r)| !matches!(r, EnvRecord::NativeRecord(_)))
+    .map(|(x, r)| (x, r.value().to_string()))
+    .collect();
+  bindings.sort();
+  bindings
+}
+
+fn env_to_text(env: &Env) -> String {
+  let entries: Vec<String> = bindings(env).into_iter().map(|(x, v)| format!("{} ↦ {}", x, v)).collect();
+  format!("{{{}}}", entries.join(", "))
+}
+
+fn env_to_latex(env: &Env) -> String {
+  let entries: Vec<String> = bindings(env).into_iter().map(|(x, v)| format!("{} \\mapsto {}", tt(x), tt(&v))).collect();
+  format!("\\{{{}\\}}", entries.join(", "))
+}
+
+/* s in typewriter type, with the characters LaTeX treats specially escaped */
+fn tt(s: &str) -> String {
+  let mut escaped = String::new();
+  for c in s.chars() {
+    match c {
+      '\\' => escaped.push_str("\\textbackslash{}"),
+      '{' | '}' | '_' | '#' | '$' | '%' | '&' => {
+        escaped.push('\\');
+        escaped.push(c);
+      },
+      '^' => escaped.push_str("\\^{}"),
+      '~' => escaped.push_str("\\~{}"),
+      c => escaped.push(c),
+    }
+  }
+  format!("\\texttt{{{}}}", escaped)
+}
+
+impl Evaluator {
+  /* Evaluates e in env like eval_expr, giving the derivation of its value */
+  pub(crate) fn derive(&self, env: &Env, e: &Expr) -> Result<Derivation, EvalError> {
+    self.start();
+    self.derivation(env, e)
+  }
+
+  /* The derivation of the number e evaluates to */
+  fn number(&self, env: &Env, e: &Expr) -> Result<Derivation, EvalError> {
+    let d = self.derivation(env, e)?;
+    match d.value() {
+      Some(Value::Numeral(_) | Value::BigNumeral(_)) => Ok(d),
+      _ => Err(EvalError::TypeMismatch(Type::Number, e.clone())),
+    }
+  }
+
+  /* The derivation of the boolean e evaluates to, and the boolean */
+  fn boolean(&self, env: &Env, e: &Expr) -> Result<(Derivation, bool), EvalError> {
+    let d = self.derivation(env, e)?;
+    match d.value() {
+      Some(Value::Boolean(b)) => {
+        let b = *b;
+        Ok((d, b))
+      },
+      _ => Err(EvalError::TypeMismatch(Type::Boolean, e.clone())),
+    }
+  }
+
+  fn arith(&self, env: &Env, e: &Expr, l: &Expr, r: &Expr, op: ArithOp, rule: Rule) -> Result<Derivation, EvalError> {
+    let l_der = self.number(env, l)?;
+    let r_der = self.number(env, r)?;
+    let v = self.arith.apply(op, l_der.value().unwrap(), r_der.value().unwrap())
+      .ok_or_else(|| EvalError::IntegerOverflow(op.symbol().to_string(), e.clone()))?;
+    Ok(Derivation::new(rule, env, Judgement::Expr(e.clone(), v), vec![l_der, r_der]))
+  }
+
+  /* The derivation of the arguments a evaluated in env */
+  fn args(&self, env: &Env, a: &[Expr]) -> Result<Vec<Derivation>, EvalError> {
+    a.iter().map(|ai| self.derivation(env, ai)).collect()
+  }
+
+  /* The derivation of the body of c run with the values of args */
+  fn call(&self, c: &Closure, args: &[Derivation]) -> Result<Derivation, EvalError> {
+    let depth = self.depth.get();
+    if depth >= self.stack_depth() {
+      return Err(EvalError::ResourceExhausted(Resource::Depth(self.stack_depth())))
+    }
+    let call_env = self.check_env(c.call_env(args.iter().map(|d| d.value().unwrap().clone()).collect()))?;
+    self.depth.set(depth + 1);
+    let body = self.derivation(&call_env, c.body());
+    self.depth.set(depth);
+    body
+  }
+
+  fn defn_derivation(&self, env: &Env, d: &Defn) -> Result<Derivation, EvalError> {
+    let (rule, premises) = match d {
+      Defn::VarDefn(_, e) => (Rule::Var, vec![self.derivation(env, e)?]),
+      Defn::FunDefn(_, _, _, _) => (Rule::Fun, vec![]),
+      Defn::RecDefn(_) => (Rule::Rec, vec![]),
+    };
+    let extended = self.defn(env, d)?;
+    Ok(Derivation::new(rule, env, Judgement::Defn(d.clone(), extended), premises))
+  }
+
+  fn derivation(&self, env: &Env, e: &Expr) -> Result<Derivation, EvalError> {
+    if let Expr::Spanned(s, inner) = e {
+      return self.derivation(env, inner).map_err(|err| err.at(*s))
+    }
+    self.step()?;
+    let leaf = |rule, v| Ok(Derivation::new(rule, env, Judgement::Expr(e.clone(), v), vec![]));
+    match e {
+      Expr::Id(x) => match env.get(x) {
+        Some(r) => leaf(Rule::Id, r.value()),
+        None => Err(EvalError::UnboundVariable(x.clone(), e.clone())),
+      },
+      Expr::Numeral(n) => leaf(Rule::Number, Value::Numeral(*n)),
+      Expr::Decimal(_, _) => Err(EvalError::FractionalNumeral(e.clone())),
+      Expr::StringLiteral(s) => leaf(Rule::String, Value::String(s.clone())),
+      Expr::True => leaf(Rule::True, Value::Boolean(true)),
+      Expr::False => leaf(Rule::False, Value::Boolean(false)),
+      Expr::Compare(l, c, r) => {
+        let l_der = self.number(env, l)?;
+        let r_der = self.number(env, r)?;
+        let v = Value::Boolean(holds(c, compare_numbers(l_der.value().unwrap(), r_der.value().unwrap())));
+        Ok(Derivation::new(Rule::Compare, env, Judgement::Expr(e.clone(), v), vec![l_der, r_der]))
+      },
+      Expr::Times(l, r) => self.arith(env, e, l, r, ArithOp::Times, Rule::Times),
+      Expr::Plus(l, r) => self.arith(env, e, l, r, ArithOp::Plus, Rule::Plus),
+      Expr::Minus(l, r) => self.arith(env, e, l, r, ArithOp::Minus, Rule::Minus),
+      Expr::Let(d, body) => {
+        let d_der = self.defn_derivation(env, d)?;
+        let body_der = match &d_der.judgement {
+          Judgement::Defn(_, extended) => self.derivation(extended, body)?,
+          Judgement::Expr(_, _) => unreachable!("a definition concludes with an environment"),
+        };
+        let v = body_der.value().unwrap().clone();
+        Ok(Derivation::new(Rule::Let, env, Judgement::Expr(e.clone(), v), vec![d_der, body_der]))
+      },
+      Expr::Call(f, a) => {
+        let callee = match env.get(f) {
+          Some(r) => r.value(),
+          None => return Err(EvalError::UnboundVariable(f.clone(), e.clone())),
+        };
+        match callee {
+          Value::Closure(c) => {
+            if c.params().len() != a.len() {
+              return Err(EvalError::ArityMismatch(f.clone(), c.params().to_vec(), e.clone()))
+            }
+            let mut premises = self.args(env, a)?;
+            let body = self.call(&c, &premises)?;
+            let v = body.value().unwrap().clone();
+            premises.push(body);
+            Ok(Derivation::new(Rule::Call, env, Judgement::Expr(e.clone(), v), premises))
+          },
+          Value::Native(n) => {
+            if n.arity() != a.len() {
+              return Err(EvalError::ArityMismatch(f.clone(), n.params().iter().map(Type::to_string).collect(), e.clone()))
+            }
+            let premises = self.args(env, a)?;
+            let args: Vec<Value> = premises.iter().map(|d| d.value().unwrap().clone()).collect();
+            let v = n.call(&args).ok_or_else(|| EvalError::NativeCallFailed(f.clone(), e.clone()))?;
+            Ok(Derivation::new(Rule::CallNative, env, Judgement::Expr(e.clone(), v), premises))
+          },
+          _ => Err(EvalError::NotAFunction(f.clone(), e.clone())),
+        }
+      },
+      Expr::Assert(c, m) => {
+        let (c_der, holds) = self.boolean(env, c)?;
+        if holds {
+          return Ok(Derivation::new(Rule::Assert, env, Judgement::Expr(e.clone(), Value::Boolean(true)), vec![c_der]))
+        }
+        match self.derivation(env, m)?.value().unwrap() {
+          Value::String(message) => Err(EvalError::AssertionFailed(message.clone())),
+          v => Err(EvalError::AssertionFailed(v.to_string())),
+        }
+      },
+      Expr::If(c, t, f) => {
+        let (c_der, b) = self.boolean(env, c)?;
+        let (rule, branch) = if b { (Rule::IfTrue, t) } else { (Rule::IfFalse, f) };
+        let branch_der = self.derivation(env, branch)?;
+        let v = branch_der.value().unwrap().clone();
+        Ok(Derivation::new(rule, env, Judgement::Expr(e.clone(), v), vec![c_der, branch_der]))
+      },
+      Expr::Spanned(_, _) => unreachable!("spans are taken off above"),
+    }
+  }
+}
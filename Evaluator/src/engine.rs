@@ -0,0 +1,425 @@
+/* An Engine embeds the Toi type checker and evaluator in a host Rust program.
+ *
+ * Hosts register native functions written in Rust. Each one is added to the
+ * engine's typing context under its Type::Function signature, so type_check_expr
+ * checks calls to it like calls to any Toi function, and to its evaluation
+ * environment as an EnvRecord::NativeRecord, which eval_expr's Call case runs.
+ *
+ *   let mut engine = Engine::new();
+ *   engine.register_fn("double", |x: i64| 2 * x);
+ *   let program = engine.check(e)?;   // e is the AST for double(21)
+ *   engine.run(&program)              // Ok(Value::Numeral(42))
+ *
+ * Arguments and results are converted between Rust types and Values through the
+ * ToiValue trait, which also gives the Toi type of each Rust type. A function
+ * that can fail returns an Option of its result type, and None stops the program
+ * with EvalError::NativeCallFailed. A number too large for an i64, which only
+ * BigInt arithmetic produces, cannot be passed to an i64 argument either.
+ */
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use rpds::HashTrieMap;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use toi_ast::{Defn, Expr, Type};
+use toi_typing::typer;
+use toi_typing::typer::{CheckedProgram, TypeError};
+use crate::evaluator::{EnvRecord, EvalError, Evaluator, Value};
+use crate::prelude;
+use crate::smallstep::{Step, Trace};
+use crate::derivation::Derivation;
+use crate::debugger::{Controller, Debugger};
+use crate::profiler::Profile;
+use crate::vm::{self, Program};
+
+/* The implementation of a native function: it receives the evaluated arguments
+ * and returns None when they are not what the function expects */
+pub type NativeImpl = Rc<dyn Fn(&[Value]) -> Option<Value>>;
+
+/* A host function callable from Toi */
+#[derive(Clone)]
+pub struct NativeFn {
+  name: String,
+  params: Vec<Type>,
+  ret: Type,
+  func: NativeImpl,
+}
+
+impl NativeFn {
+  pub fn new(name: &str, params: Vec<Type>, ret: Type, func: NativeImpl) -> NativeFn {
+    NativeFn { name: name.to_string(), params, ret, func }
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /* The Type::Function the typer sees for this function */
+  pub fn ty(&self) -> Type {
+    Type::Function(self.params.clone(), Box::new(self.ret.clone()))
+  }
+
+  pub fn arity(&self) -> usize {
+    self.params.len()
+  }
+
+  pub fn params(&self) -> &[Type] {
+    &self.params
+  }
+
+  pub fn call(&self, args: &[Value]) -> Option<Value> {
+    (self.func)(args)
+  }
+}
+
+/* Native functions are identified by their name and signature, since Rust
+ * closures cannot be compared */
+impl PartialEq for NativeFn {
+  fn eq(&self, other: &NativeFn) -> bool {
+    self.name == other.name && self.params == other.params && self.ret == other.ret
+  }
+}
+
+impl Eq for NativeFn {}
+
+impl Hash for NativeFn {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.name.hash(state);
+    self.params.hash(state);
+    self.ret.hash(state);
+  }
+}
+
+impl fmt::Debug for NativeFn {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "<native {}: {:?}>", self.name, self.ty())
+  }
+}
+
+/* Only a native function's name can be written out. Reading one back looks the
+ * name up in the prelude, since other host functions are not known here. */
+impl Serialize for NativeFn {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&self.name)
+  }
+}
+
+impl<'de> Deserialize<'de> for NativeFn {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<NativeFn, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    prelude::builtin(&name).ok_or_else(|| de::Error::custom(format!("{} is not a prelude function", name)))
+  }
+}
+
+/* Rust types that can be passed to and returned from native functions */
+pub trait ToiValue: Sized {
+  /* The Toi type that values of this Rust type have */
+  fn toi_type() -> Type;
+  fn from_value(v: Value) -> Option<Self>;
+  fn into_value(self) -> Value;
+}
+
+impl ToiValue for i64 {
+  fn toi_type() -> Type {
+    Type::Number
+  }
+
+  fn from_value(v: Value) -> Option<i64> {
+    match v {
+      Value::Numeral(n) => Some(n),
+      _ => None,
+    }
+  }
+
+  fn into_value(self) -> Value {
+    Value::Numeral(self)
+  }
+}
+
+impl ToiValue for String {
+  fn toi_type() -> Type {
+    Type::String
+  }
+
+  fn from_value(v: Value) -> Option<String> {
+    match v {
+      Value::String(s) => Some(s),
+      _ => None,
+    }
+  }
+
+  fn into_value(self) -> Value {
+    Value::String(self)
+  }
+}
+
+impl ToiValue for () {
+  fn toi_type() -> Type {
+    Type::Unit
+  }
+
+  fn from_value(v: Value) -> Option<()> {
+    match v {
+      Value::Unit => Some(()),
+      _ => None,
+    }
+  }
+
+  fn into_value(self) -> Value {
+    Value::Unit
+  }
+}
+
+impl ToiValue for bool {
+  fn toi_type() -> Type {
+    Type::Boolean
+  }
+
+  fn from_value(v: Value) -> Option<bool> {
+    match v {
+      Value::Boolean(b) => Some(b),
+      _ => None,
+    }
+  }
+
+  fn into_value(self) -> Value {
+    Value::Boolean(self)
+  }
+}
+
+/* Rust types that native functions can return: any ToiValue, or an Option of
+ * one for functions that can fail, where None means the call failed */
+pub trait NativeReturn {
+  fn toi_type() -> Type;
+  fn into_result(self) -> Option<Value>;
+}
+
+impl<T: ToiValue> NativeReturn for T {
+  fn toi_type() -> Type {
+    T::toi_type()
+  }
+
+  fn into_result(self) -> Option<Value> {
+    Some(self.into_value())
+  }
+}
+
+impl<T: ToiValue> NativeReturn for Option<T> {
+  fn toi_type() -> Type {
+    T::toi_type()
+  }
+
+  fn into_result(self) -> Option<Value> {
+    self.map(T::into_value)
+  }
+}
+
+/* Rust closures that can be registered as native functions. Args is the tuple of
+ * the closure's argument types, which is only there to tell the impls apart. */
+pub trait NativeFunction<Args> {
+  fn params() -> Vec<Type>;
+  fn ret() -> Type;
+  fn into_impl(self) -> NativeImpl;
+}
+
+macro_rules! impl_native_function {
+  ($($arg:ident $var:ident),*) => {
+    impl<F, R, $($arg),*> NativeFunction<($($arg,)*)> for F
+    where F: Fn($($arg),*) -> R + 'static, R: NativeReturn, $($arg: ToiValue),*
+    {
+      fn params() -> Vec<Type> {
+        vec![$($arg::toi_type()),*]
+      }
+
+      fn ret() -> Type {
+        R::toi_type()
+      }
+
+      fn into_impl(self) -> NativeImpl {
+        Rc::new(move |args: &[Value]| {
+          let [$($var),*] = args else { return None };
+          $(let $var = $arg::from_value($var.clone())?;)*
+          self($($var),*).into_result()
+        })
+      }
+    }
+  };
+}
+
+impl_native_function!();
+impl_native_function!(A a);
+impl_native_function!(A a, B b);
+impl_native_function!(A a, B b, C c);
+impl_native_function!(A a, B b, C c, D d);
+
+/* A typing context and evaluation environment that agree on every name */
+#[derive(Clone)]
+pub struct Engine {
+  con: HashTrieMap<String, Type>,
+  env: HashTrieMap<String, EnvRecord>,
+}
+
+impl Default for Engine {
+  fn default() -> Engine {
+    Engine::new()
+  }
+}
+
+impl Engine {
+  /* An engine with no functions at all, for closed programs */
+  pub fn new() -> Engine {
+    Engine { con: HashTrieMap::new(), env: HashTrieMap::new() }
+  }
+
+  /* An engine with the standard prelude (see prelude.rs) */
+  pub fn with_prelude() -> Engine {
+    let mut engine = Engine::new();
+    prelude::register(&mut engine);
+    engine
+  }
+
+  /* The engine that runs a program given without one, as eval_program does:
+   * the prelude for a program checked against it, and an empty engine for any
+   * other, which runs closed programs and refuses the rest */
+  pub(crate) fn standard(p: &CheckedProgram) -> Engine {
+    if p.context().is_empty() { Engine::new() } else { prelude::engine() }
+  }
+
+  /* Registers a Rust closure of up to four arguments as a native function,
+   * taking its Toi signature from the closure's argument and result types */
+  pub fn register_fn<Args, F: NativeFunction<Args>>(&mut self, name: &str, f: F) -> &mut Engine {
+    self.register_native(NativeFn::new(name, F::params(), F::ret(), f.into_impl()))
+  }
+
+  /* Registers a native function with an explicit signature, for functions that
+   * work on Values directly */
+  pub fn register_native(&mut self, native: NativeFn) -> &mut Engine {
+    let name = native.name().to_string();
+    self.con = self.con.insert(name.clone(), native.ty());
+    self.env = self.env.insert(name, EnvRecord::NativeRecord(native));
+    self
+  }
+
+  pub fn context(&self) -> &HashTrieMap<String, Type> {
+    &self.con
+  }
+
+  pub fn environment(&self) -> &HashTrieMap<String, EnvRecord> {
+    &self.env
+  }
+
+  /* Type-checks e against this engine's native functions */
+  pub fn check(&self, e: Expr) -> Option<CheckedProgram> {
+    CheckedProgram::check_in(&self.con, e)
+  }
+
+  /* Type-checks e like check, but says why e is ill-typed if it is */
+  pub fn infer(&self, e: Expr) -> Result<CheckedProgram, TypeError> {
+    CheckedProgram::infer_in(&self.con, e)
+  }
+
+  /* Type-checks and evaluates the declaration d on the CEK machine, then adds
+   * the names it binds to this engine, so later programs can use them. Gives
+   * the TypeError if d is ill-typed, and leaves the engine as it was on any
+   * error. */
+  pub fn define(&mut self, evaluator: &Evaluator, d: &Defn) -> Result<Result<Vec<(String, Type)>, EvalError>, TypeError> {
+    let bindings = typer::infer_defn(&self.con, d)?;
+    match evaluator.eval_defn_cek(&self.env, d) {
+      Ok(env) => {
+        self.env = env;
+        for (name, t) in bindings.iter() {
+          self.con = self.con.insert(name.clone(), t.clone());
+        }
+        Ok(Ok(bindings))
+      },
+      Err(err) => Ok(Err(err)),
+    }
+  }
+
+  /* Evaluates a program checked by this engine (or an engine with the same
+   * natives). A program checked in any other context could call functions that
+   * are not there, so it is not run and gives EvalError::ContextMismatch. */
+  pub fn run(&self, p: &CheckedProgram) -> Result<Value, EvalError> {
+    self.run_with(&Evaluator::default(), p)
+  }
+
+  /* Evaluates a program checked by this engine with the given settings */
+  pub fn run_with(&self, evaluator: &Evaluator, p: &CheckedProgram) -> Result<Value, EvalError> {
+    self.accept(p)?;
+    evaluator.eval_expr(&self.env, p.expr())
+  }
+
+  /* Evaluates a program checked by this engine on the CEK machine (see cek.rs),
+   * which can run programs that recurse too deeply for run_with */
+  pub fn run_cek(&self, evaluator: &Evaluator, p: &CheckedProgram) -> Result<Value, EvalError> {
+    self.accept(p)?;
+    evaluator.eval_cek(&self.env, p.expr())
+  }
+
+  /* Evaluates a program checked by this engine by compiling it to bytecode (see
+   * vm.rs), which is faster than run_with for programs that do a lot of work.
+   * The VM always evaluates by value and does not memoize, whatever the
+   * evaluator's strategy and memo settings. */
+  pub fn run_vm(&self, evaluator: &Evaluator, p: &CheckedProgram) -> Result<Value, EvalError> {
+    self.accept(p)?;
+    evaluator.eval_vm(&self.env, p.expr())
+  }
+
+  /* Compiles a program checked by this engine to bytecode once, so that
+   * Evaluator::run_compiled can run it any number of times */
+  pub fn compile(&self, p: &CheckedProgram) -> Result<Program, EvalError> {
+    self.accept(p)?;
+    Ok(vm::compile(&self.env, p.expr()))
+  }
+
+  /* Reduces a program checked by this engine one small step (see smallstep.rs),
+   * giving the environment and expression after the step, or None if the
+   * program is a value */
+  pub fn step(&self, evaluator: &Evaluator, p: &CheckedProgram) -> Result<Step, EvalError> {
+    self.accept(p)?;
+    evaluator.small_step(&self.env, p.expr())
+  }
+
+  /* The expressions a program checked by this engine reduces through, one
+   * small step at a time (see smallstep.rs) */
+  pub fn trace(&self, evaluator: &Evaluator, p: &CheckedProgram) -> Trace {
+    match self.accept(p) {
+      Ok(()) => evaluator.trace(&self.env, p.expr()),
+      Err(err) => Trace::failed(err),
+    }
+  }
+
+  /* Evaluates a program checked by this engine, keeping the derivation of its
+   * value (see derivation.rs) */
+  pub fn derive(&self, evaluator: &Evaluator, p: &CheckedProgram) -> Result<Derivation, EvalError> {
+    self.accept(p)?;
+    evaluator.derive(&self.env, p.expr())
+  }
+
+  /* Evaluates a program checked by this engine under a debugger (see
+   * debugger.rs), or gives None if the controller quit */
+  pub fn debug(&self, debugger: &mut Debugger, p: &CheckedProgram, controller: &mut dyn Controller) -> Option<Result<Value, EvalError>> {
+    if let Err(err) = self.accept(p) {
+      return Some(Err(err))
+    }
+    debugger.debug(&self.env, p.expr(), controller)
+  }
+
+  /* Evaluates a program checked by this engine on the CEK machine, recording
+   * the calls it makes (see profiler.rs) */
+  pub fn profile(&self, evaluator: &Evaluator, p: &CheckedProgram) -> (Result<Value, EvalError>, Profile) {
+    if let Err(err) = self.accept(p) {
+      return (Err(err), Profile::default())
+    }
+    evaluator.profile(&self.env, p.expr())
+  }
+
+  /* Whether p was checked against this engine's context */
+  fn accept(&self, p: &CheckedProgram) -> Result<(), EvalError> {
+    if *p.context() == self.con {
+      Ok(())
+    } else {
+      Err(EvalError::ContextMismatch)
+    }
+  }
+}
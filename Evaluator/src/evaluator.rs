@@ -1,144 +1,749 @@
 use rpds::HashTrieMap;
+use std::fmt;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
 /**
  * An implementation of an evaluator that evaluates declarations and expressions
  * 
  * The evaluator follows the following pseudocode specifications:
  *  eval_expr(E, Id(x)) = E(x)
+ *                               (a closure or native function, if x names one)
  *  eval_expr(E, Number(n)) = n
  *  eval_expr(E, Times(e1,e2)) = interp_expr(E, e1) * interp_expr(E, e2)
  *  eval_expr(E, Plus(e1,e2)) = interp_expr(E, e1) + interp_expr(E, e2)
  *  eval_expr(E, Minus(e1,e2)) = interp_expr(E, e1) - interp_expr(E, e2)
+ *                               (where *, + and - follow the ArithPolicy)
  *  eval_expr(E, Let(d,e)) = interp_expr(interp_defn(E,d), e)
- *  eval_expr(E, Call(f,e1)) = interp_expr(E[x↦interp_expr(E,e1)], e2)
- *                               where E(f(x))=e2
+ *  eval_expr(E, Call(f,e1)) = interp_expr(E'[f↦E(f)][x↦interp_expr(E,e1)], e2)
+ *                               where E(f) is the closure ⟨x, e2, E'⟩
+ *                               (and every other function of f's let rec group is
+ *                               bound in E' the same way)
+ *  eval_expr(E, Call(f,e1)) = g(interp_expr(E,e1))
+ *                               where E(f) is the native function g
+ *                               (E(f) may also be a variable whose value is a
+ *                               closure or native function, such as a parameter)
  *  eval_defn(E,Var(x,e)) = E[x ↦ interp_expr(E, e)]
- *  eval_defn(E,Fun(f,x,e)) = E[f(x)↦e]
+ *  eval_defn(E,Fun(f,x,e)) = E[f ↦ ⟨x, e, E⟩]
+ *  eval_defn(E,Rec(f1,...,fN)) = E[f1 ↦ ⟨x1, e1, E⟩, ..., fN ↦ ⟨xN, eN, E⟩]
+ *  eval_expr(E, Assert(c,m)) = true  if interp_expr(E, c) = true
+ *  eval_expr(E, Assert(c,m)) = error AssertionFailed(interp_expr(E, m))  otherwise
+ *  eval_expr(E, If(c,e1,e2)) = interp_expr(E, e1)  if interp_expr(E, c) = true
+ *  eval_expr(E, If(c,e1,e2)) = interp_expr(E, e2)  otherwise
+ *  eval_expr(E, Spanned(s,e)) = eval_expr(E, e)
+ *
+ * Functions are closures: the body runs in the environment the function was
+ * defined in, extended with the function itself (so it can call itself) and its
+ * parameters, not in the environment of the caller. Toi is statically scoped.
+ *
+ * An error stops evaluation, and the innermost Spanned wrapper around the place
+ * it was raised records where in the source that happened.
  */
 
 /* EnvRecord defines a single record stored in the environment.
  * The name of a function or variable is its key in the environment.
- * FunRecord stores the argument names and the function body expression
- * VarRecord stores the value of the variable */
-#[derive(Hash,Eq, PartialEq, Debug)]
+ * FunRecord stores a function defined in Toi, as a closure
+ * VarRecord stores the value of the variable
+ * NativeRecord stores a function implemented in Rust by the host (see engine.rs),
+ * which is serialized by name and can only be read back for prelude functions */
+#[derive(Hash,Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub enum EnvRecord {
-    FunRecord(Vec<String>, Box<Expr>),
+    FunRecord(Closure),
     VarRecord(Value),
+    NativeRecord(NativeFn),
 }
 
-/* Values are programs that are pure data and require no further
- * computation (i.e. numerals) */
-#[derive(Hash,Eq, PartialEq, Debug, Clone)]
+impl EnvRecord {
+  /* The value of the name the record is stored under */
+  pub fn value(&self) -> Value {
+    match self {
+      EnvRecord::FunRecord(c) => Value::Closure(c.clone()),
+      EnvRecord::VarRecord(v) => v.clone(),
+      EnvRecord::NativeRecord(n) => Value::Native(n.clone()),
+    }
+  }
+}
+
+/* A closure is a function together with the environment it was defined in.
+ * group holds the name, parameter names and body of each function defined along
+ * with it (just the function itself, or every function of a let rec group), and
+ * index says which of them this closure is. The whole group is bound again each
+ * time the closure is called, so the functions can call each other without the
+ * environment having to contain itself. */
+#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Closure {
+    pub group: Rc<Vec<(String, Vec<String>, Expr)>>,
+    pub index: usize,
+    pub env: HashTrieMap<String,EnvRecord>,
+}
+
+impl Closure {
+  /* The closures of a group of functions defined together in env */
+  pub fn group(env: &HashTrieMap<String,EnvRecord>, group: Vec<(String, Vec<String>, Expr)>) -> Vec<Closure> {
+    let group = Rc::new(group);
+    (0..group.len()).map(|index| Closure { group: group.clone(), index, env: env.clone() }).collect()
+  }
+
+  pub fn name(&self) -> &str {
+    &self.group[self.index].0
+  }
+
+  pub fn params(&self) -> &[String] {
+    &self.group[self.index].1
+  }
+
+  pub fn body(&self) -> &Expr {
+    &self.group[self.index].2
+  }
+
+  /* The environment the body runs in: the defining environment, extended with the
+   * functions of the group and then with the parameters bound to args */
+  pub fn call_env(&self, args: Vec<Value>) -> HashTrieMap<String,EnvRecord> {
+    let mut env = self.env.clone();
+    for (index, (f, _, _)) in self.group.iter().enumerate() {
+      let sibling = Closure { group: self.group.clone(), index, env: self.env.clone() };
+      env = env.insert(f.clone(), EnvRecord::FunRecord(sibling));
+    }
+    for (x, v) in self.params().iter().zip(args) {
+      env = env.insert(x.clone(), EnvRecord::VarRecord(v));
+    }
+    env
+  }
+}
+
+/* Environments cannot be hashed, so a closure hashes as its code alone. Equal
+ * closures still have equal hashes, which is all Hash requires. */
+impl Hash for Closure {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.group.hash(state);
+    self.index.hash(state);
+  }
+}
+
+/* Values are programs that require no further computation, one for each Toi type
+ * (i.e. numerals, strings, booleans, functions and unit)
+ * BigNumeral is a number outside the range of i64, which only the BigInt
+ * arithmetic policy produces. Numbers that fit are always Numerals.
+ * Closure and Native are functions passed around as values, e.g. as arguments
+ * Unit is the result of a host function that returns nothing
+ * Thunk is an argument not evaluated yet, which is only ever bound to a parameter
+ *   of a call by name or by need (see strategy.rs) */
+#[derive(Hash,Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub enum Value {
     Numeral(i64),
+    BigNumeral(BigInt),
+    String(String),
+    Boolean(bool),
+    Closure(Closure),
+    Native(NativeFn),
+    Unit,
+    Thunk(Thunk),
 }
 
-/* Expressions are programs that we can evaluate. If they terminate,
-* they return a value. Expressions can be:
-* Id: identifiers (variable names)
-* Numeral: literal numbers
-* Times: e1 * e2
-* Plus: e1 + e2
-* Let: let d in e  (see Defn for the different kinds of d)
-* Call:  f(arg1, ..., argN)  (function calls, any number of args)
-*/
-#[derive(Hash, Eq, PartialEq, Debug, Clone)]
-pub enum Expr {
-    Id(String),
-    Numeral(i64),
-    Times(Box<Expr>,Box<Expr>),
-    Plus(Box<Expr>,Box<Expr>),
-    Minus(Box<Expr>,Box<Expr>),
-    Let(Box<Defn>,Box<Expr>),
-    Call(String, Vec<Expr>),
-} 
-
-/** Definitions are programs that, when we run them,
- * they define something, like a variable or function.
- * They can be:
- *   VarDefn(x,e) = defines x to equal the value of e
- *   FunDefn(f,[x1,...,xN],e) = defines function f(x1,...,xN)=e
- */
-#[derive(Hash, Eq, PartialEq, Debug, Clone)]
-pub enum Defn {
-    VarDefn(String, Box<Expr>),
-    FunDefn(String, Vec<String>, Box<Expr>),
-}
-
-pub fn eval_defn(env: &HashTrieMap<String,EnvRecord>, d: &Defn) -> HashTrieMap<String,EnvRecord> {
-  // match definition type to variable or function
-  match d {
-    Defn::VarDefn(var, val) => {
-      HashTrieMap::insert(env, var.to_string(), EnvRecord::VarRecord(eval_expr(env, &*val)))
-    },
-    Defn::FunDefn(func, param , val) => {
-      HashTrieMap::insert(env, func.to_string(), EnvRecord::FunRecord(param.to_vec(), val.clone()))
-    },
-  }
-}
-
-pub fn eval_expr(env: &HashTrieMap<String,EnvRecord>, e: &Expr) -> Value {
-  // match expression type to correct type
-  match e {
-    Expr::Id(s) => {
-      match HashTrieMap::get(env, s) {
-        Some(env_record) => {
-          match env_record {
-              EnvRecord::VarRecord(v) => {
-                v.clone()
-              },
-              EnvRecord::FunRecord(f, e) => {
-                Value::Numeral(0)
-              }
-          }
-        },
-        None => {
-          Value::Numeral(0)
+impl Value {
+  /* The Value of the number n */
+  pub fn number(n: BigInt) -> Value {
+    match n.to_i64() {
+      Some(small) => Value::Numeral(small),
+      None => Value::BigNumeral(n),
+    }
+  }
+
+  /* A number as a BigInt, or None for any other value */
+  pub fn to_bigint(&self) -> Option<BigInt> {
+    match self {
+      Value::Numeral(n) => Some(BigInt::from(*n)),
+      Value::BigNumeral(n) => Some(n.clone()),
+      _ => None,
+    }
+  }
+}
+
+/* How Times, Plus and Minus treat a result that does not fit in an i64
+ * Checked stops with EvalError::IntegerOverflow
+ * Wrapping wraps around, as two's-complement machine arithmetic does
+ * Saturating gives i64::MIN or i64::MAX, whichever is closer
+ * BigInt gives the exact result as a Value::BigNumeral (see bigint.rs) */
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum ArithPolicy {
+    #[default]
+    Checked,
+    Wrapping,
+    Saturating,
+    BigInt,
+}
+
+/* The arithmetic operators, named by their symbols in Toi */
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+pub enum ArithOp {
+    Times,
+    Plus,
+    Minus,
+}
+
+impl ArithOp {
+  pub fn symbol(self) -> &'static str {
+    match self {
+      ArithOp::Times => "*",
+      ArithOp::Plus => "+",
+      ArithOp::Minus => "-",
+    }
+  }
+
+  fn checked(self, l: i64, r: i64) -> Option<i64> {
+    match self {
+      ArithOp::Times => l.checked_mul(r),
+      ArithOp::Plus => l.checked_add(r),
+      ArithOp::Minus => l.checked_sub(r),
+    }
+  }
+
+  fn exact(self, l: &BigInt, r: &BigInt) -> BigInt {
+    match self {
+      ArithOp::Times => l * r,
+      ArithOp::Plus => l + r,
+      ArithOp::Minus => l - r,
+    }
+  }
+}
+
+impl ArithPolicy {
+  /* Applies op to two numbers, or gives None when the result overflows under the
+   * Checked policy (or l or r is not a number). Results that fit in an i64 are
+   * the same under every policy, and are computed without a BigInt. */
+  pub fn apply(self, op: ArithOp, l: &Value, r: &Value) -> Option<Value> {
+    if let (Value::Numeral(a), Value::Numeral(b)) = (l, r) {
+      if let Some(n) = op.checked(*a, *b) {
+        return Some(Value::Numeral(n))
+      }
+    }
+    let n = op.exact(&l.to_bigint()?, &r.to_bigint()?);
+    match self {
+      ArithPolicy::Checked => n.to_i64().map(Value::Numeral),
+      ArithPolicy::Wrapping => Some(Value::Numeral(n.wrapping_to_i64())),
+      ArithPolicy::Saturating => Some(Value::Numeral(n.saturating_to_i64())),
+      ArithPolicy::BigInt => Some(Value::number(n)),
+    }
+  }
+}
+
+impl FromStr for ArithPolicy {
+  type Err = String;
+  fn from_str(s: &str) -> Result<ArithPolicy, String> {
+    match s {
+      "checked" => Ok(ArithPolicy::Checked),
+      "wrapping" => Ok(ArithPolicy::Wrapping),
+      "saturating" => Ok(ArithPolicy::Saturating),
+      "bigint" => Ok(ArithPolicy::BigInt),
+      _ => Err(format!("unknown arithmetic policy '{}' (expected checked, wrapping, saturating or bigint)", s)),
+    }
+  }
+}
+
+/* Compares two numbers, however they are represented */
+pub(crate) fn compare_numbers(l: &Value, r: &Value) -> Ordering {
+  match (l, r) {
+    (Value::Numeral(a), Value::Numeral(b)) => a.cmp(b),
+    _ => l.to_bigint().cmp(&r.to_bigint()),
+  }
+}
+
+/* Whether the comparison c holds between two numbers in the given order */
+pub(crate) fn holds(c: &Comparison, order: Ordering) -> bool {
+  match c {
+    Comparison::LessEqual => order.is_le(),
+    Comparison::Less => order.is_lt(),
+    Comparison::Equal => order.is_eq(),
+    Comparison::NotEqual => order.is_ne(),
+    Comparison::Greater => order.is_gt(),
+    Comparison::GreaterEqual => order.is_ge(),
+  }
+}
+
+/* EvalError is why the evaluation of a program stopped. Each variant carries the
+ * offending name and the expression that raised it.
+ * UnboundVariable: an Id or Call whose name is not in the environment
+ * NotAFunction: a Call of a variable whose value is not a function
+ * ArityMismatch: a Call with the wrong number of arguments, which also carries the
+ *   function's declared parameters (their types, for a native function)
+ * IntegerOverflow: arithmetic whose result does not fit in an i64, named by its operator
+ * NativeCallFailed: a native function that could not produce a result
+ * TypeMismatch: an operand whose value does not have the expected type
+ * AssertionFailed carries the message of an assert whose condition was false
+ * ResourceExhausted: the evaluation went beyond one of its Limits
+ * At records the span of the source text the error was raised in
+ * Only AssertionFailed, IntegerOverflow, NativeCallFailed and ResourceExhausted
+ * can happen in a well-typed program; the others are ruled out by the type checker. */
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum EvalError {
+    UnboundVariable(String, Expr),
+    NotAFunction(String, Expr),
+    ArityMismatch(String, Vec<String>, Expr),
+    IntegerOverflow(String, Expr),
+    NativeCallFailed(String, Expr),
+    TypeMismatch(Type, Expr),
+    AssertionFailed(String),
+    ResourceExhausted(Resource),
+    FractionalNumeral(Expr),
+    /* A program was run by an engine other than the one it was checked against */
+    ContextMismatch,
+    At(Span, Box<EvalError>),
+}
+
+impl EvalError {
+  /* Places the error at span, unless a more precise span is already known */
+  pub fn at(self, span: Span) -> EvalError {
+    match self {
+      EvalError::At(_, _) => self,
+      e => EvalError::At(span, Box::new(e)),
+    }
+  }
+
+  pub fn span(&self) -> Option<Span> {
+    match self {
+      EvalError::At(s, _) => Some(*s),
+      _ => None,
+    }
+  }
+
+  /* The error itself, without the span it was raised at */
+  pub fn cause(&self) -> &EvalError {
+    match self {
+      EvalError::At(_, e) => e.cause(),
+      e => e,
+    }
+  }
+}
+
+impl fmt::Display for EvalError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      EvalError::UnboundVariable(x, _) => write!(f, "unbound variable {}", x),
+      EvalError::NotAFunction(x, _) => write!(f, "{} is not a function", x),
+      EvalError::ArityMismatch(x, params, call) => {
+        let given = match call {
+          Expr::Call(_, args) => args.len(),
+          _ => 0,
+        };
+        let plural = if params.len() == 1 { "" } else { "s" };
+        write!(f, "{}({}) takes {} argument{} but was called with {}", x, params.join(", "), params.len(), plural, given)
+      },
+      EvalError::IntegerOverflow(op, _) => write!(f, "integer overflow in {}", op),
+      EvalError::NativeCallFailed(x, _) => write!(f, "call to {} failed", x),
+      EvalError::TypeMismatch(t, _) => write!(f, "expected a value of type {}", t),
+      EvalError::AssertionFailed(m) => write!(f, "assertion failed: {}", m),
+      EvalError::ResourceExhausted(r) => write!(f, "evaluation stopped at {}", r),
+      EvalError::FractionalNumeral(_) => write!(f, "numbers are integers, so a fractional numeral has no value"),
+      EvalError::ContextMismatch => write!(f, "the program was type-checked against a different context than the engine's"),
+      EvalError::At(_, e) => write!(f, "{}", e),
+    }
+  }
+}
+
+/* Values print the way they would be written in a Toi program */
+impl fmt::Display for Value {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Value::Numeral(n) => write!(f, "{}", n),
+      Value::BigNumeral(n) => write!(f, "{}", n),
+      Value::String(s) => write!(f, "\"{}\"", s),
+      Value::Boolean(b) => write!(f, "{}", b),
+      Value::Closure(c) => write!(f, "<function {}>", c.name()),
+      Value::Native(n) => write!(f, "<native {}>", n.name()),
+      Value::Unit => write!(f, "()"),
+      Value::Thunk(t) => write!(f, "{}", t),
+    }
+  }
+}
+
+/* Expressions and definitions are the shared Toi syntax trees from toi-ast
+ * (see Ast/src/lib.rs), so parsed and type-checked programs run unchanged. */
+pub use toi_ast::{Expr, Defn, Comparison, Span, Type};
+use toi_typing::typer::CheckedProgram;
+use crate::bigint::BigInt;
+use crate::engine::{Engine, NativeFn};
+use crate::profiler::Recorder;
+use crate::memo::Cache;
+use crate::strategy::{Strategy, Thunk};
+
+/* Evaluates a program that has already passed the type checker, closed or
+ * checked against the prelude, with the default settings (checked arithmetic).
+ * This is the entry point for running programs: Evaluator::eval_expr, which is
+ * internal to the crate, accepts any expression and reports ill-typed ones, such
+ * as an unbound name or a call to something that is not a function, with an
+ * EvalError, while a CheckedProgram cannot contain them. Programs that call
+ * other host functions are run by their Engine instead, and here give
+ * EvalError::ContextMismatch. */
+pub fn eval_program(p: &CheckedProgram) -> Result<Value, EvalError> {
+  Engine::standard(p).run(p)
+}
+
+/* Evaluates a checked program like eval_program, stopping with
+ * EvalError::ResourceExhausted as soon as it goes beyond any of the limits.
+ * A program checked against an Engine's host functions is run within limits by
+ * engine.run_with(&Evaluator::new().with_limits(limits), p). */
+pub fn eval_with_limits(p: &CheckedProgram, limits: Limits) -> Result<Value, EvalError> {
+  Engine::standard(p).run_with(&Evaluator::new().with_limits(limits), p)
+}
+
+/* Bounds on the work an evaluation may do, for running programs that cannot be
+ * trusted to terminate
+ * max_steps: how many expressions may be evaluated in all
+ * max_depth: how many calls may be in progress at once
+ * max_env_size: how many names an environment may hold
+ * Each call in progress also takes up some of the Rust stack, so a server should
+ * pick a max_depth that the thread evaluating the program has room for. By
+ * default there are no limits. */
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Limits {
+  pub max_steps: u64,
+  pub max_depth: usize,
+  pub max_env_size: usize,
+}
+
+impl Default for Limits {
+  fn default() -> Limits {
+    Limits { max_steps: u64::MAX, max_depth: usize::MAX, max_env_size: usize::MAX }
+  }
+}
+
+/* The max_depth of the debugger, derivations and small steps, which make each
+ * call on the Rust stack, when the limits allow deeper calls, so that a program
+ * that recurses too deeply stops with EvalError::ResourceExhausted instead of
+ * overflowing the stack. A thread needs about 16 MB of stack for this many calls
+ * in a release build, and 256 MB in a debug build. */
+pub const STACK_DEPTH: usize = 1_000;
+
+/* The limit an evaluation ran out of, and its value */
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Resource {
+  Steps(u64),
+  Depth(usize),
+  EnvSize(usize),
+}
+
+impl fmt::Display for Resource {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Resource::Steps(n) => write!(f, "the limit of {} evaluation steps", n),
+      Resource::Depth(n) => write!(f, "the limit of {} nested calls", n),
+      Resource::EnvSize(n) => write!(f, "the limit of {} names in scope", n),
+    }
+  }
+}
+
+/* The settings a program is evaluated with, chosen before evaluation starts:
+ *   engine.run_with(&Evaluator::new().with_arith(ArithPolicy::BigInt), &program)
+ * memo caches the values of calls (see memo.rs), and strategy says when the
+ * arguments of a call are evaluated (see strategy.rs).
+ * It also counts the steps taken and calls in progress in the current evaluation,
+ * records the calls of a profiled one (see profiler.rs), and holds its cache. */
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct Evaluator {
+  pub arith: ArithPolicy,
+  pub limits: Limits,
+  pub memo: bool,
+  pub strategy: Strategy,
+  pub(crate) steps: Cell<u64>,
+  pub(crate) depth: Cell<usize>,
+  pub(crate) recorder: RefCell<Option<Recorder>>,
+  pub(crate) cache: RefCell<Cache>,
+}
+
+/* The result of evaluating an expression up to a call in tail position: a value,
+ * or a closure still to be called with its arguments, and the innermost span
+ * around the call if there is one */
+enum Tail {
+  Value(Value),
+  Call(Closure, Vec<Value>, Option<Span>),
+}
+
+/* Binds each closure to the name of its function */
+fn eval_closures(env: &HashTrieMap<String,EnvRecord>, closures: Vec<Closure>) -> HashTrieMap<String,EnvRecord> {
+  closures.into_iter().fold(env.clone(), |env, c| env.insert(c.name().to_string(), EnvRecord::FunRecord(c)))
+}
+
+impl Evaluator {
+  pub fn new() -> Evaluator {
+    Evaluator::default()
+  }
+
+  pub fn with_arith(mut self, arith: ArithPolicy) -> Evaluator {
+    self.arith = arith;
+    self
+  }
+
+  pub fn with_limits(mut self, limits: Limits) -> Evaluator {
+    self.limits = limits;
+    self
+  }
+
+  /* The number of steps taken by the last evaluation */
+  pub fn steps(&self) -> u64 {
+    self.steps.get()
+  }
+
+  /* The number e evaluates to. Arithmetic on anything else is ill-typed. */
+  fn eval_number(&self, env: &HashTrieMap<String,EnvRecord>, e: &Expr) -> Result<Value, EvalError> {
+    match self.eval(env, e)? {
+      v @ (Value::Numeral(_) | Value::BigNumeral(_)) => Ok(v),
+      _ => Err(EvalError::TypeMismatch(Type::Number, e.clone())),
+    }
+  }
+
+  /* The boolean e evaluates to */
+  fn eval_boolean(&self, env: &HashTrieMap<String,EnvRecord>, e: &Expr) -> Result<bool, EvalError> {
+    match self.eval(env, e)? {
+      Value::Boolean(b) => Ok(b),
+      _ => Err(EvalError::TypeMismatch(Type::Boolean, e.clone())),
+    }
+  }
+
+  /* Applies op to the operands of e under the arithmetic policy */
+  fn eval_arith(&self, env: &HashTrieMap<String,EnvRecord>, e: &Expr, l: &Expr, r: &Expr, op: ArithOp) -> Result<Value, EvalError> {
+    let l_ret = self.eval_number(env, l)?;
+    let r_ret = self.eval_number(env, r)?;
+    self.arith.apply(op, &l_ret, &r_ret).ok_or_else(|| EvalError::IntegerOverflow(op.symbol().to_string(), e.clone()))
+  }
+
+  /* Calls the function value callee, named f at the call e, with the arguments a
+   * evaluated in env. The body of a closure is left to the caller to evaluate
+   * (see eval), so that a call in tail position does not use up the stack. */
+  fn apply(&self, env: &HashTrieMap<String,EnvRecord>, e: &Expr, f: &str, callee: &Value, a: &[Expr]) -> Result<Tail, EvalError> {
+    match callee {
+      Value::Closure(c) => {
+        if c.params().len() != a.len() {
+          return Err(EvalError::ArityMismatch(f.to_string(), c.params().to_vec(), e.clone()))
         }
+        // Arguments are evaluated where the call is (now, or when the strategy
+        // says), the body where f was defined
+        let args = match self.delay(env, a) {
+          Some(thunks) => thunks,
+          None => a.iter().map(|ai| self.eval(env, ai)).collect::<Result<Vec<Value>, EvalError>>()?,
+        };
+        Ok(Tail::Call(c.clone(), args, None))
+      },
+      Value::Native(n) => {
+        if n.arity() != a.len() {
+          return Err(EvalError::ArityMismatch(f.to_string(), n.params().iter().map(Type::to_string).collect(), e.clone()))
+        }
+        let args = a.iter().map(|ai| self.eval(env, ai)).collect::<Result<Vec<Value>, EvalError>>()?;
+        n.call(&args).map(Tail::Value).ok_or_else(|| EvalError::NativeCallFailed(f.to_string(), e.clone()))
+      },
+      _ => Err(EvalError::NotAFunction(f.to_string(), e.clone())),
+    }
+  }
+
+  /* Evaluates e in env. Calls in tail position (the body of a let, a branch of an
+   * if, or the whole body of a function) come back to this loop as a Tail::Call
+   * instead of being evaluated inside the expression that makes them, so a chain
+   * of tail calls runs in one Rust stack frame and counts as one call in progress.
+   * An error in a called body is placed at the innermost span around the call. */
+  pub(crate) fn eval(&self, env: &HashTrieMap<String,EnvRecord>, e: &Expr) -> Result<Value, EvalError> {
+    let depth = self.depth.get();
+    let result = self.trampoline(depth, env, e);
+    self.depth.set(depth);
+    result
+  }
+
+  fn trampoline(&self, depth: usize, env: &HashTrieMap<String,EnvRecord>, e: &Expr) -> Result<Value, EvalError> {
+    let (mut c, mut args, mut span) = match self.eval_tail(env, e)? {
+      Tail::Value(v) => return Ok(v),
+      Tail::Call(c, args, span) => (c, args, span),
+    };
+    // The calls of the chain of tail calls so far, which all have its value
+    let mut calls = vec![];
+    loop {
+      match self.recall(&c, &args) {
+        Ok(v) => {
+          self.remember(calls, &v);
+          return Ok(v)
+        },
+        Err(key) => calls.extend(key),
       }
-    },
-    Expr::Numeral(n) => {
-      Value::Numeral(*n)
-    },
-    Expr::Times(l, r) => {
-      let Value::Numeral(l_ret) = eval_expr(env, &*l);
-      let Value::Numeral(r_ret) = eval_expr(env, &*r);
-      Value::Numeral(l_ret * r_ret)
-    },
-    Expr::Plus(l, r) => {
-      let Value::Numeral(l_ret) = eval_expr(env, &*l);
-      let Value::Numeral(r_ret) = eval_expr(env, &*r);
-      Value::Numeral(l_ret + r_ret)
-    },
-    Expr::Minus(l, r) => {
-      let Value::Numeral(l_ret) = eval_expr(env, &*l);
-      let Value::Numeral(r_ret) = eval_expr(env, &*r);
-      Value::Numeral(l_ret - r_ret)
-    },
-    Expr::Let(d, e) => {
-      let update_env = eval_defn(env, d);
-      eval_expr(&update_env, e)
-    },
-    Expr::Call(f, a) => {
-      match HashTrieMap::get(env, f) {
-        Some(env_record) => {
-          match env_record {
-              EnvRecord::VarRecord(v) => {
-                v.clone()
-              },
-              EnvRecord::FunRecord(p, e) => {
-                let mut update_env = env.clone();
-                for (pi, ai) in p.iter().zip(a.iter()) {
-                  update_env = eval_defn(&update_env, &Defn::VarDefn(pi.clone(), Box::new(ai.clone())))
-                }
-                eval_expr(&update_env, e)
-              }
-          }
+      let next = self.enter(depth, &c, args).and_then(|call_env| self.eval_tail(&call_env, c.body()));
+      match next.map_err(|err| match span { Some(s) => err.at(s), None => err })? {
+        Tail::Value(v) => {
+          self.remember(calls, &v);
+          return Ok(v)
+        },
+        Tail::Call(next_c, next_args, next_span) => {
+          c = next_c;
+          args = next_args;
+          span = next_span.or(span);
         },
-        None => {
-          Value::Numeral(0)
-        }
       }
-    },
+    }
+  }
+
+  /* The environment the body of c runs in. Only the first call made from a frame
+   * is a new call in progress; the tail calls after it take over its place. */
+  fn enter(&self, depth: usize, c: &Closure, args: Vec<Value>) -> Result<HashTrieMap<String,EnvRecord>, EvalError> {
+    if self.depth.get() > depth {
+      return self.check_env(c.call_env(args))
+    }
+    if depth >= self.limits.max_depth {
+      return Err(EvalError::ResourceExhausted(Resource::Depth(self.limits.max_depth)))
+    }
+    let call_env = self.check_env(c.call_env(args))?;
+    self.depth.set(depth + 1);
+    Ok(call_env)
+  }
+
+  /* Evaluates d in env, within this evaluator's limits */
+  pub(crate) fn eval_defn(&self, env: &HashTrieMap<String,EnvRecord>, d: &Defn) -> Result<HashTrieMap<String,EnvRecord>, EvalError> {
+    self.start();
+    self.defn(env, d)
+  }
+
+  /* Evaluates e in env, within this evaluator's limits */
+  pub(crate) fn eval_expr(&self, env: &HashTrieMap<String,EnvRecord>, e: &Expr) -> Result<Value, EvalError> {
+    self.start();
+    self.eval(env, e)
+  }
+
+  /* Resets the counts of steps and calls, which start from zero in each
+   * evaluation, and the cache */
+  pub(crate) fn start(&self) {
+    self.steps.set(0);
+    self.depth.set(0);
+    self.forget();
+  }
+
+  /* How many calls may be in progress at once when each is made on the Rust
+   * stack: max_depth, but no more than STACK_DEPTH */
+  pub(crate) fn stack_depth(&self) -> usize {
+    self.limits.max_depth.min(STACK_DEPTH)
+  }
+
+  /* Counts one step, which must stay within max_steps */
+  pub(crate) fn step(&self) -> Result<(), EvalError> {
+    let steps = self.steps.get() + 1;
+    if steps > self.limits.max_steps {
+      return Err(EvalError::ResourceExhausted(Resource::Steps(self.limits.max_steps)))
+    }
+    self.steps.set(steps);
+    Ok(())
+  }
+
+  /* An environment must stay within max_env_size names */
+  pub(crate) fn check_env(&self, env: HashTrieMap<String,EnvRecord>) -> Result<HashTrieMap<String,EnvRecord>, EvalError> {
+    if env.size() > self.limits.max_env_size {
+      return Err(EvalError::ResourceExhausted(Resource::EnvSize(self.limits.max_env_size)))
+    }
+    Ok(env)
+  }
+
+  pub(crate) fn defn(&self, env: &HashTrieMap<String,EnvRecord>, d: &Defn) -> Result<HashTrieMap<String,EnvRecord>, EvalError> {
+    self.check_env(self.bind(env, d)?)
+  }
+
+  fn bind(&self, env: &HashTrieMap<String,EnvRecord>, d: &Defn) -> Result<HashTrieMap<String,EnvRecord>, EvalError> {
+    // match definition type to variable, function or recursive group
+    match d {
+      Defn::VarDefn(var, val) => {
+        Ok(HashTrieMap::insert(env, var.to_string(), EnvRecord::VarRecord(self.eval(env, val)?)))
+      },
+      Defn::FunDefn(func, param, _ret, val) => {
+        let names = param.iter().map(|(x, _)| x.clone()).collect();
+        let closures = Closure::group(env, vec![(func.to_string(), names, (**val).clone())]);
+        Ok(eval_closures(env, closures))
+      },
+      Defn::RecDefn(funcs) => {
+        let group = funcs.iter()
+          .map(|(func, param, _ret, val)| (func.to_string(), param.iter().map(|(x, _)| x.clone()).collect(), (**val).clone()))
+          .collect();
+        Ok(eval_closures(env, Closure::group(env, group)))
+      },
+    }
+  }
+
+  /* Evaluates e in env, up to a call in tail position */
+  fn eval_tail(&self, env: &HashTrieMap<String,EnvRecord>, e: &Expr) -> Result<Tail, EvalError> {
+    // Every expression other than a span wrapper is one step
+    if !matches!(e, Expr::Spanned(_, _)) {
+      self.step()?;
+    }
+    // match expression type to correct type
+    match e {
+      Expr::Id(s) => {
+        match HashTrieMap::get(env, s) {
+          Some(env_record) => {
+            Ok(Tail::Value(self.force(env_record.value())?))
+          },
+          None => {
+            Err(EvalError::UnboundVariable(s.clone(), e.clone()))
+          }
+        }
+      },
+      Expr::Numeral(n) => {
+        Ok(Tail::Value(Value::Numeral(*n)))
+      },
+      Expr::Decimal(_, _) => {
+        Err(EvalError::FractionalNumeral(e.clone()))
+      },
+      Expr::StringLiteral(s) => {
+        Ok(Tail::Value(Value::String(s.clone())))
+      },
+      Expr::True => {
+        Ok(Tail::Value(Value::Boolean(true)))
+      },
+      Expr::False => {
+        Ok(Tail::Value(Value::Boolean(false)))
+      },
+      Expr::Compare(l, c, r) => {
+        let l_ret = self.eval_number(env, l)?;
+        let r_ret = self.eval_number(env, r)?;
+        Ok(Tail::Value(Value::Boolean(holds(c, compare_numbers(&l_ret, &r_ret)))))
+      },
+      Expr::Times(l, r) => {
+        self.eval_arith(env, e, l, r, ArithOp::Times).map(Tail::Value)
+      },
+      Expr::Plus(l, r) => {
+        self.eval_arith(env, e, l, r, ArithOp::Plus).map(Tail::Value)
+      },
+      Expr::Minus(l, r) => {
+        self.eval_arith(env, e, l, r, ArithOp::Minus).map(Tail::Value)
+      },
+      Expr::Let(d, body) => {
+        let update_env = self.defn(env, d)?;
+        self.eval_tail(&update_env, body)
+      },
+      Expr::Call(f, a) => {
+        match HashTrieMap::get(env, f) {
+          Some(env_record) => self.apply(env, e, f, &self.force(env_record.value())?, a),
+          None => {
+            Err(EvalError::UnboundVariable(f.clone(), e.clone()))
+          }
+        }
+      },
+      Expr::Assert(c, m) => {
+        if self.eval_boolean(env, c)? {
+          Ok(Tail::Value(Value::Boolean(true)))
+        } else {
+          match self.eval(env, m)? {
+            Value::String(message) => Err(EvalError::AssertionFailed(message)),
+            v => Err(EvalError::AssertionFailed(v.to_string())),
+          }
+        }
+      },
+      Expr::If(c, t, f) => {
+        if self.eval_boolean(env, c)? {
+          self.eval_tail(env, t)
+        } else {
+          self.eval_tail(env, f)
+        }
+      },
+      Expr::Spanned(s, inner) => {
+        match self.eval_tail(env, inner) {
+          Ok(Tail::Call(c, args, None)) => Ok(Tail::Call(c, args, Some(*s))),
+          r => r.map_err(|err| err.at(*s)),
+        }
+      },
+    }
   }
 }
 
@@ -0,0 +1,12 @@
+pub mod bigint;
+pub mod cek;
+pub mod vm;
+pub mod evaluator;
+pub mod engine;
+pub mod prelude;
+pub mod smallstep;
+pub mod derivation;
+pub mod debugger;
+pub mod profiler;
+pub mod memo;
+pub mod strategy;
@@ -1,42 +1,135 @@
-pub mod evaluator;
-use evaluator::*;
+use toi_evaluator::evaluator;
+use toi_evaluator::evaluator::*;
 use evaluator::Expr as ex;
 use evaluator::Defn as dc;
 use evaluator::Value as val;
+use toi_ast::Type as tp;
 use rpds::HashTrieMap;
+use toi_typing::typer::CheckedProgram;
+use toi_evaluator::engine::Engine;
+use toi_ast::sexp;
+use toi_evaluator::cek;
+use toi_evaluator::vm;
+use toi_evaluator::smallstep;
+use toi_evaluator::derivation;
+use toi_evaluator::profiler;
+use toi_evaluator::memo;
+use toi_evaluator::strategy::Strategy;
+use toi_evaluator::debugger::{Breakpoint, Command, Controller, Debugger, Pause, Reason};
 
-fn test_expr_internal(score: f64, in_env: HashTrieMap<String,EnvRecord>, in_e: Expr, out: Value) -> f64 {
-    match (evaluator::eval_expr(&in_env, &in_e), out) {
+/* A test program checked by engine, which every test expects to be well-typed */
+fn checked(engine: &Engine, in_e: &Expr) -> CheckedProgram {
+    engine.check(in_e.clone()).unwrap_or_else(|| panic!("test case {:?} is ill-typed", in_e))
+  }
+
+/* The CEK machine and the bytecode VM must agree with eval_expr on every
+ * expression test, errors included */
+fn others_agree(engine: &Engine, p: &CheckedProgram, e1: &Result<Value, EvalError>) -> bool {
+    let e2 = engine.run_cek(&Evaluator::new(), p);
+    if *e1 != e2 {
+      println!("CEK test case {:?} expected {:?} like eval_expr but got {:?}", p.expr(), e1, e2);
+    }
+    let e3 = engine.run_vm(&Evaluator::new(), p);
+    if *e1 != e3 {
+      println!("VM test case {:?} expected {:?} like eval_expr but got {:?}", p.expr(), e1, e3);
+    }
+    *e1 == e2 && *e1 == e3
+  }
+
+/* A debugger controller that gives the commands of a script, and then continues,
+ * recording why it paused, where, and how many calls were in progress */
+struct Script {
+    commands: Vec<Command>,
+    pauses: Vec<(Reason, Expr, usize)>,
+  }
+
+impl Controller for Script {
+    fn pause(&mut self, pause: &Pause, _: &mut Vec<Breakpoint>) -> Command {
+      self.pauses.push((pause.reason.clone(), pause.expr.strip_spans(), pause.stack.len()));
+      if self.commands.is_empty() { Command::Continue } else { self.commands.remove(0) }
+    }
+  }
+
+/* Runs f on a thread with as much stack as the toi driver, which has room for
+ * STACK_DEPTH calls made on the Rust stack */
+fn on_big_stack<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    std::thread::Builder::new().stack_size(256 << 20).spawn(f).expect("the thread starts")
+      .join().expect("the thread finishes")
+  }
+
+#[allow(clippy::needless_return, clippy::match_single_binding)]
+fn test_expr_internal(score: f64, engine: &Engine, in_e: Expr, out: Value) -> f64 {
+    let p = checked(engine, &in_e);
+    match (engine.run(&p), Ok(out)) {
       (e1, e2) =>
-        if e1 == e2 {
+        if e1 == e2 && others_agree(engine, &p, &e1) {
           return score
         } else {
-          {println!("Expression test case {:?}; {:?} expected expr {:?} but got {:?}", in_env,in_e, e2,e1); }
+          {println!("Expression test case {:?} expected expr {:?} but got {:?}", in_e, e2,e1); }
           return 0.0
         },      
     }
   }
 
-  fn test_defn_internal(score: f64, in_env: HashTrieMap<String,EnvRecord>, in_d: Defn, out: HashTrieMap<String,EnvRecord>) -> f64 {
-    match (evaluator::eval_defn(&in_env, &in_d), out) {
+  #[allow(clippy::needless_return, clippy::match_single_binding)]
+  fn test_defn_internal(score: f64, mut engine: Engine, in_d: Defn, out: HashTrieMap<String,EnvRecord>) -> f64 {
+    let defined = engine.define(&Evaluator::new(), &in_d).map(|r| r.map(|_| engine.environment().clone()));
+    match (defined, Ok(Ok(out))) {
       (e1, e2) =>
         if e1 == e2 {
           return score
         } else {
-          {println!("Definition test case {:?}; {:?} expected output environment {:?} but got {:?}", in_env,in_d, e2,e1); }
+          {println!("Definition test case {:?} expected output environment {:?} but got {:?}", in_d, e2,e1); }
           return 0.0
         },      
     }
   }
 
+  fn test_error(score: f64, engine: &Engine, in_e: Expr, out: EvalError) -> f64 {
+    let p = checked(engine, &in_e);
+    match engine.run(&p) {
+      Err(e1) if *e1.cause() == out && others_agree(engine, &p, &Err(e1.clone())) => score,
+      r => {println!("Error test case {:?} expected error {:?} but got {:?}", in_e, out, r); 0.0}
+    }
+  }
+
+  /* A program that eval_expr would stop with an error for being ill-typed never
+   * gets past the checker, so it is never run */
+  fn test_rejected(score: f64, engine: &Engine, in_e: Expr) -> f64 {
+    match engine.check(in_e.clone()) {
+      None => score,
+      Some(p) => {println!("Ill-typed test case {:?} expected no program but got {:?}", in_e, p); 0.0}
+    }
+  }
+
+  fn test_policy(score: f64, policy: ArithPolicy, in_e: Expr, out: Value) -> f64 {
+    let (engine, evaluator) = (Engine::new(), Evaluator::new().with_arith(policy));
+    let p = checked(&engine, &in_e);
+    match engine.run_with(&evaluator, &p) {
+      Ok(v) if v == out && engine.run_vm(&evaluator, &p) == Ok(v.clone()) => score,
+      r => {println!("Arithmetic test case {:?} under {:?} expected {:?} but got {:?}", in_e, policy, out, r); 0.0}
+    }
+  }
+
+  fn test_checked(score: f64, in_e: Expr, out: Value) -> f64 {
+    match CheckedProgram::check(in_e.clone()) {
+      Some(p) => match evaluator::eval_program(&p) {
+        Ok(v) if v == out && vm::eval_vm(&p) == Ok(v.clone()) => score,
+        r => {println!("Checked program test case {:?} expected {:?} but got {:?}", in_e, out, r); 0.0}
+      },
+      None => {println!("Checked program test case {:?} expected {:?} but it is ill-typed", in_e, out); 0.0}
+    }
+  }
+
   fn test_expr(score: f64, in_e: Expr, out: Value) -> f64 {
-    test_expr_internal(score, HashTrieMap::new(), in_e, out)
+    test_expr_internal(score, &Engine::new(), in_e, out)
   }
   fn test_defn(score: f64,  in_d: Defn, out: HashTrieMap<String,EnvRecord>) -> f64 {
-    test_defn_internal(score, HashTrieMap::new(), in_d, out)
+    test_defn_internal(score, Engine::new(), in_d, out)
   }
 
 fn main() {
+  let base = Engine::new();
   // 8 easy tests
   let r1 = test_expr(1.0, ex::Numeral(3), val::Numeral(3));
   let r2 = test_expr(1.0, ex::Times(Box::new(ex::Numeral(3)),Box::new(ex::Numeral(5))), val::Numeral(15));
@@ -51,36 +144,666 @@ fn main() {
   let r8 = test_defn(1.0, dc::VarDefn("z".to_string(), Box::new(ex::Plus(Box::new(ex::Numeral(2)),Box::new(ex::Numeral(2))))), HashTrieMap::new().insert("z".to_string(), EnvRecord::VarRecord(val::Numeral(4))));
   let r_easy = r1+r2+r3+r4+r5+r6+r7+r8;
   // 7 hard tests
-  let r9 = test_expr(1.0, ex::Let(Box::new(dc::FunDefn("sq".to_string(), vec!["x".to_string()], 
+  let r9 = test_expr(1.0, ex::Let(Box::new(dc::FunDefn("sq".to_string(), vec![("x".to_string(), tp::Number)], tp::Number, 
         Box::new(ex::Times(Box::new(ex::Id("x".to_string())),Box::new(ex::Id("x".to_string())))))),Box::new(ex::Call("sq".to_string(), vec![ex::Numeral(4)]))),
       val::Numeral(16));
-  let r10 = test_expr(1.0, ex::Let(Box::new(dc::FunDefn("sq".to_string(), vec!["x".to_string()], 
+  let r10 = test_expr(1.0, ex::Let(Box::new(dc::FunDefn("sq".to_string(), vec![("x".to_string(), tp::Number)], tp::Number, 
         Box::new(ex::Times(Box::new(ex::Id("x".to_string())),Box::new(ex::Id("x".to_string())))))),
         Box::new(ex::Call("sq".to_string(), vec![ex::Call("sq".to_string(), vec![ex::Numeral(4)])]))),
       val::Numeral(256));
-  let r11 = test_expr(1.0, ex::Let(Box::new(dc::FunDefn("f".to_string(), vec!["a".to_string()],
-    Box::new(ex::Let(Box::new(dc::FunDefn("g".to_string(), vec!["b".to_string()],
+  let r11 = test_expr(1.0, ex::Let(Box::new(dc::FunDefn("f".to_string(), vec![("a".to_string(), tp::Number)], tp::Number,
+    Box::new(ex::Let(Box::new(dc::FunDefn("g".to_string(), vec![("b".to_string(), tp::Number)], tp::Number,
       Box::new(ex::Minus(Box::new(ex::Numeral(0)),Box::new(ex::Id("b".to_string())))))),
       Box::new(ex::Call("g".to_string(), vec![ex::Times(Box::new(ex::Numeral(2)),Box::new(ex::Id("a".to_string())))])))))),
       Box::new(ex::Call("f".to_string(), vec![ex::Numeral(5)]))), val::Numeral(-10));
-  let r12 = test_expr(1.0,ex::Let(Box::new(dc::FunDefn("f".to_string(), vec!["a".to_string()], Box::new(ex::Plus(Box::new(ex::Id("a".to_string())), Box::new(ex::Numeral(2)))))),
+  let r12 = test_expr(1.0,ex::Let(Box::new(dc::FunDefn("f".to_string(), vec![("a".to_string(), tp::Number)], tp::Number, Box::new(ex::Plus(Box::new(ex::Id("a".to_string())), Box::new(ex::Numeral(2)))))),
     Box::new(ex::Times(Box::new(ex::Call("f".to_string(),vec![ex::Numeral(3)])),Box::new(ex::Call("f".to_string(),vec![ex::Numeral(5)]))))), val::Numeral(35));
-  let r13 = test_expr(1.0, ex::Times(Box::new(ex::Let(Box::new(dc::FunDefn("f".to_string(), vec!["c".to_string()], Box::new(ex::Plus(Box::new(ex::Numeral(5)),Box::new(ex::Id("c".to_string())))))),  Box::new(ex::Call("f".to_string(), vec![ex::Numeral(5)])))),Box::new(ex::Let(Box::new(dc::FunDefn("f".to_string(), vec!["c".to_string()], Box::new(ex::Plus(Box::new(ex::Numeral(5)),Box::new(ex::Id("c".to_string())))))),  Box::new(ex::Call("f".to_string(), vec![ex::Numeral(10)]))))), val::Numeral(150));
+  let r13 = test_expr(1.0, ex::Times(Box::new(ex::Let(Box::new(dc::FunDefn("f".to_string(), vec![("c".to_string(), tp::Number)], tp::Number, Box::new(ex::Plus(Box::new(ex::Numeral(5)),Box::new(ex::Id("c".to_string())))))),  Box::new(ex::Call("f".to_string(), vec![ex::Numeral(5)])))),Box::new(ex::Let(Box::new(dc::FunDefn("f".to_string(), vec![("c".to_string(), tp::Number)], tp::Number, Box::new(ex::Plus(Box::new(ex::Numeral(5)),Box::new(ex::Id("c".to_string())))))),  Box::new(ex::Call("f".to_string(), vec![ex::Numeral(10)]))))), val::Numeral(150));
   let r14 = test_expr(1.0, 
-  ex::Let(Box::new(dc::FunDefn("f".to_string(), vec!["c".to_string()], 
+  ex::Let(Box::new(dc::FunDefn("f".to_string(), vec![("c".to_string(), tp::Number)], tp::Number, 
   Box::new(ex::Plus(Box::new(ex::Numeral(5)),Box::new(ex::Id("c".to_string())))))),  
   Box::new(ex::Call("f".to_string(), vec![ex::Call("f".to_string(), vec![ex::Call("f".to_string(), vec![ex::Numeral(10)])])]))),
     val::Numeral(25));
-  let r15 = test_expr(1.0, ex::Let(Box::new(dc::FunDefn("f".to_string(), vec!["a".to_string()],
+  let r15 = test_expr(1.0, ex::Let(Box::new(dc::FunDefn("f".to_string(), vec![("a".to_string(), tp::Number)], tp::Number,
         Box::new(ex::Times(Box::new(ex::Numeral(2)),Box::new(ex::Id("a".to_string())))))),Box::new(
-        ex::Let(Box::new(dc::FunDefn("g".to_string(), vec!["b".to_string()],
+        ex::Let(Box::new(dc::FunDefn("g".to_string(), vec![("b".to_string(), tp::Number)], tp::Number,
           Box::new(ex::Minus(Box::new(ex::Numeral(0)),Box::new(ex::Id("b".to_string())))))), 
           Box::new(ex::Call("f".to_string(), vec![ex::Call("g".to_string(), vec![ex::Numeral(2)])]))))
         ), val::Numeral(-4));
   let r_hard = r9+r10+r11+r12+r13+r14+r15;
+  // 2 tests of checked programs
+  let r16 = match CheckedProgram::check(ex::Let(Box::new(dc::FunDefn("sq".to_string(), vec![("x".to_string(), tp::Number)], tp::Number,
+        Box::new(ex::Times(Box::new(ex::Id("x".to_string())),Box::new(ex::Id("x".to_string())))))),Box::new(ex::Call("sq".to_string(), vec![ex::Numeral(4)])))) {
+    Some(p) if evaluator::eval_program(&p) == Ok(val::Numeral(16)) => 1.0,
+    p => {println!("Checked program test case sq(4) expected value 16 but got {:?}", p.map(|p| evaluator::eval_program(&p))); 0.0}
+  };
+  // an unbound variable is an evaluation error, and never gets past the checker
+  let r17 = match CheckedProgram::check(ex::Id("x".to_string())) {
+    None => 1.0,
+    Some(p) => {println!("Checked program test case x expected no program but got {:?}", p); 0.0}
+  };
+  let r_checked = r16+r17;
+  // 4 tests of host functions
+  let mut engine = Engine::new();
+  engine.register_fn("double", |x: i64| 2 * x).register_fn("add", |x: i64, y: i64| x + y);
+  let r18 = match engine.check(ex::Call("add".to_string(), vec![ex::Call("double".to_string(), vec![ex::Numeral(3)]), ex::Numeral(4)])) {
+    Some(p) if engine.run(&p) == Ok(val::Numeral(10)) => 1.0,
+    p => {println!("Engine test case add(double(3),4) expected value 10 but got {:?}", p.map(|p| engine.run(&p))); 0.0}
+  };
+  let r19 = match engine.check(ex::Call("double".to_string(), vec![ex::Numeral(1), ex::Numeral(2)])) {
+    None => 1.0,
+    Some(p) => {println!("Engine test case double(1,2) expected no program but got {:?}", p); 0.0}
+  };
+  // Toi definitions shadow host functions of the same name
+  let r20 = match engine.check(ex::Let(Box::new(dc::FunDefn("double".to_string(), vec![("x".to_string(), tp::Number)], tp::Number,
+        Box::new(ex::Times(Box::new(ex::Numeral(3)),Box::new(ex::Id("x".to_string())))))), Box::new(ex::Call("double".to_string(), vec![ex::Numeral(5)])))) {
+    Some(p) if engine.run(&p) == Ok(val::Numeral(15)) => 1.0,
+    p => {println!("Engine test case shadowed double(5) expected value 15 but got {:?}", p.map(|p| engine.run(&p))); 0.0}
+  };
+  let r21 = test_expr_internal(1.0, &engine,
+    ex::Plus(Box::new(ex::Call("double".to_string(), vec![ex::Numeral(20)])), Box::new(ex::Numeral(2))), val::Numeral(42));
+  let r_engine = r18+r19+r20+r21;
+  // 7 tests of the prelude
+  let prelude = Engine::with_prelude();
+  let run_prelude = |in_e: Expr, out: Option<Value>| -> f64 {
+    let (e1, e2) = (prelude.check(in_e.clone()).map(|p| prelude.run(&p)), out.map(Ok));
+    if e1 == e2 {
+      1.0
+    } else {
+      println!("Prelude test case {:?} expected {:?} but got {:?}", in_e, e2, e1);
+      0.0
+    }
+  };
+  let call = |f: &str, args: Vec<Expr>| ex::Call(f.to_string(), args);
+  let r22 = run_prelude(ex::Plus(Box::new(call("abs", vec![ex::Numeral(-3)])), Box::new(call("max", vec![ex::Numeral(2), ex::Numeral(7)]))), Some(val::Numeral(10)));
+  let r23 = run_prelude(call("pow", vec![ex::Numeral(2), call("min", vec![ex::Numeral(10), ex::Numeral(12)])]), Some(val::Numeral(1024)));
+  let r24 = run_prelude(call("length", vec![call("concat", vec![ex::StringLiteral("ab".to_string()), ex::StringLiteral("cde".to_string())])]), Some(val::Numeral(5)));
+  let r25 = run_prelude(call("substring", vec![ex::StringLiteral("hello".to_string()), ex::Numeral(1), ex::Numeral(3)]), Some(val::String("ell".to_string())));
+  let r26 = run_prelude(call("parse_number", vec![call("to_string", vec![ex::Numeral(-42)])]), Some(val::Numeral(-42)));
+  let r27 = run_prelude(call("abs", vec![ex::StringLiteral("x".to_string())]), None);
+  let r28 = run_prelude(ex::Let(Box::new(dc::FunDefn("abs".to_string(), vec![("x".to_string(), tp::Number)], tp::Number, Box::new(ex::Id("x".to_string())))),
+    Box::new(call("abs", vec![ex::Numeral(-3)]))), Some(val::Numeral(-3)));
+  let r_prelude = r22+r23+r24+r25+r26+r27+r28;
+
+  // 4 tests of the JSON and S-expression encodings of environments
+  let encode_env = |env: &HashTrieMap<String, EnvRecord>, x: &str| -> (Option<String>, Option<String>) {
+    (serde_json::to_string(&env[x]).ok(), sexp::to_string(&env[x]).ok())
+  };
+  let round_trips = |env: &HashTrieMap<String, EnvRecord>, x: &str| -> f64 {
+    match encode_env(env, x) {
+      (Some(j), Some(s)) if serde_json::from_str::<EnvRecord>(&j).ok().as_ref() == Some(&env[x])
+        && sexp::from_str::<EnvRecord>(&s).ok().as_ref() == Some(&env[x]) => 1.0,
+      r => {println!("Round trip test case {:?} was encoded as {:?}", env[x], r); 0.0}
+    }
+  };
+  let mut defining = Engine::with_prelude();
+  for d in [dc::VarDefn("s".to_string(), Box::new(ex::StringLiteral("a \"b\"".to_string()))),
+    dc::FunDefn("f".to_string(), vec![("x".to_string(), tp::Number)], tp::Number, Box::new(ex::Plus(Box::new(ex::Id("x".to_string())), Box::new(ex::Numeral(-1)))))] {
+    defining.define(&Evaluator::new(), &d).expect("the definitions type-check").expect("the definitions evaluate");
+  }
+  let defined = defining.environment().clone();
+  let r29 = round_trips(&defined, "s");
+  let r30 = round_trips(&defined, "f");
+  let r31 = round_trips(&defined, "substring");
+  // host functions are not in the prelude, so they cannot be read back
+  let r32 = match encode_env(engine.environment(), "double") {
+    (Some(j), Some(s)) if serde_json::from_str::<EnvRecord>(&j).is_err() && sexp::from_str::<EnvRecord>(&s).is_err() => 1.0,
+    r => {println!("Host function test case was encoded as {:?} and expected not to decode", r); 0.0}
+  };
+  let r_encoding = r29+r30+r31+r32;
+
+  // 3 tests of booleans and assertions
+  let r33 = test_expr(1.0, ex::Compare(Box::new(ex::Numeral(2)), Comparison::Less, Box::new(ex::Numeral(3))), val::Boolean(true));
+  let r34 = test_expr(1.0, ex::Assert(Box::new(ex::True), Box::new(ex::StringLiteral("unused".to_string()))), val::Boolean(true));
+  // the innermost span around a failed assertion is where it is reported
+  let failing = ex::Spanned(Span::new(0, 20), Box::new(ex::Let(Box::new(dc::VarDefn("ok".to_string(), Box::new(ex::Spanned(Span::new(4, 20),
+    Box::new(ex::Assert(Box::new(ex::False), Box::new(ex::StringLiteral("nope".to_string())))))))), Box::new(ex::Numeral(1)))));
+  let r35 = match eval_program(&checked(&base, &failing)) {
+    Err(EvalError::At(s, e)) if s == Span::new(4, 20) && *e == EvalError::AssertionFailed("nope".to_string()) => 1.0,
+    r => {println!("Assertion test case {:?} expected a failure at 4..20 but got {:?}", failing, r); 0.0}
+  };
+  let r_assert = r33+r34+r35;
+
+  // 6 tests of evaluation errors, or of the ill-typed programs that eval_expr
+  // would stop with an error, which never get past the checker
+  let x = ex::Id("x".to_string());
+  let call_x = ex::Call("x".to_string(), vec![ex::Numeral(1)]);
+  let r36 = test_rejected(1.0, &base, x.clone());
+  let r37 = test_rejected(1.0, &base, ex::Let(Box::new(dc::VarDefn("x".to_string(), Box::new(ex::Numeral(1)))), Box::new(call_x.clone())));
+  let mut with_f = Engine::new();
+  with_f.define(&Evaluator::new(), &dc::FunDefn("x".to_string(), vec![("y".to_string(), tp::Number)], tp::Number, Box::new(ex::Id("y".to_string()))))
+    .expect("function x type-checks").expect("function x evaluates");
+  let r38 = test_rejected(1.0, &with_f, ex::Plus(Box::new(x.clone()), Box::new(ex::Numeral(1))));
+  let call_x2 = ex::Call("x".to_string(), vec![ex::Numeral(1), ex::Numeral(2)]);
+  let r39 = test_rejected(1.0, &with_f, call_x2.clone());
+  let big = ex::Times(Box::new(ex::Numeral(i64::MAX)), Box::new(ex::Numeral(2)));
+  let r40 = test_error(1.0, &base, big.clone(), EvalError::IntegerOverflow("*".to_string(), big.clone()));
+  let sub = ex::Call("substring".to_string(), vec![ex::StringLiteral("abc".to_string()), ex::Numeral(2), ex::Numeral(5)]);
+  let r41 = test_error(1.0, &prelude, sub.clone(), EvalError::NativeCallFailed("substring".to_string(), sub.clone()));
+  let r_errors = r36+r37+r38+r39+r40+r41;
+
+  // 4 tests of static scoping. Under the old dynamic scoping, where a body ran in
+  // its caller's environment, these would give 100, 6, 2 and 101.
+  let id = |x: &str| Box::new(ex::Id(x.to_string()));
+  let var = |x: &str, e: Expr| Box::new(dc::VarDefn(x.to_string(), Box::new(e)));
+  let fun = |f: &str, x: &str, e: Expr| Box::new(dc::FunDefn(f.to_string(), vec![(x.to_string(), tp::Number)], tp::Number, Box::new(e)));
+  let call = |f: &str, e: Expr| ex::Call(f.to_string(), vec![e]);
+  let r42 = test_expr(1.0, ex::Let(var("y", ex::Numeral(1)), Box::new(ex::Let(fun("f", "x", ex::Plus(id("x"), id("y"))),
+    Box::new(ex::Let(var("y", ex::Numeral(100)), Box::new(call("f", ex::Numeral(0)))))))), val::Numeral(1));
+  let r43 = test_rejected(1.0, &base, ex::Let(fun("f", "x", ex::Plus(id("x"), id("z"))),
+    Box::new(ex::Let(var("z", ex::Numeral(5)), Box::new(call("f", ex::Numeral(1)))))));
+  let r44 = test_expr(1.0, ex::Let(var("x", ex::Numeral(10)), Box::new(ex::Let(fun("f", "y", ex::Plus(id("x"), id("y"))),
+    Box::new(ex::Let(fun("g", "x", call("f", ex::Id("x".to_string()))), Box::new(call("g", ex::Numeral(1)))))))), val::Numeral(11));
+  let r45 = test_expr(1.0, ex::Let(fun("outer", "n", ex::Let(fun("inner", "x", ex::Plus(id("x"), id("n"))),
+      Box::new(ex::Let(var("n", ex::Numeral(100)), Box::new(call("inner", ex::Numeral(1))))))),
+    Box::new(call("outer", ex::Numeral(5)))), val::Numeral(6));
+  let r_scope = r42+r43+r44+r45;
+
+  // 2 tests of recursive groups: f calls g, which is defined after it
+  let rec_fg = |k: Expr| Box::new(dc::RecDefn(vec![
+    ("f".to_string(), vec![("x".to_string(), tp::Number)], tp::Number, Box::new(ex::Plus(Box::new(call("g", ex::Id("x".to_string()))), Box::new(k)))),
+    ("g".to_string(), vec![("y".to_string(), tp::Number)], tp::Number, Box::new(ex::Times(id("y"), Box::new(ex::Numeral(2))))),
+  ]));
+  let r46 = test_expr(1.0, ex::Let(rec_fg(ex::Numeral(1)), Box::new(call("f", ex::Numeral(3)))), val::Numeral(7));
+  let r47 = test_expr(1.0, ex::Let(var("k", ex::Numeral(10)), Box::new(ex::Let(rec_fg(ex::Id("k".to_string())),
+    Box::new(ex::Let(var("k", ex::Numeral(0)), Box::new(call("f", ex::Numeral(1)))))))), val::Numeral(12));
+  let r_rec = r46+r47;
+
+  // 2 tests of arity errors, which the checker rules out, and which are reported
+  // with the declared parameters at the call site
+  let abs2 = ex::Call("abs".to_string(), vec![ex::Numeral(1), ex::Numeral(2)]);
+  let r48 = test_rejected(1.0, &prelude, abs2.clone());
+  let at_call = ex::Spanned(Span::new(0, 12), Box::new(ex::Plus(Box::new(ex::Numeral(1)), Box::new(ex::Spanned(Span::new(4, 12), Box::new(call_x2.clone()))))));
+  let arity = EvalError::ArityMismatch("x".to_string(), vec!["y".to_string()], call_x2.clone()).at(Span::new(4, 12));
+  let r49 = match (with_f.check(at_call.clone()), arity) {
+    (None, e) if e.span() == Some(Span::new(4, 12)) && e.to_string() == "x(y) takes 1 argument but was called with 2" => 1.0,
+    r => {println!("Arity test case {:?} expected no program and an error at 4..12 but got {:?}", at_call, r); 0.0}
+  };
+  let r_arity = r48+r49;
+
+  // 6 tests of arithmetic policies on results outside the range of i64
+  let num = |n: i64| Box::new(ex::Numeral(n));
+  let max_sq = || ex::Times(num(i64::MAX), num(i64::MAX));
+  let r50 = test_policy(1.0, ArithPolicy::Wrapping, ex::Plus(num(i64::MAX), num(1)), val::Numeral(i64::MIN));
+  let r51 = test_policy(1.0, ArithPolicy::Saturating, ex::Minus(Box::new(ex::Minus(num(i64::MIN), num(1))), num(1)), val::Numeral(i64::MIN));
+  let r52 = test_policy(1.0, ArithPolicy::Saturating, ex::Minus(Box::new(ex::Times(num(i64::MAX), num(2))), num(1)), val::Numeral(i64::MAX - 1));
+  let r53 = test_policy(1.0, ArithPolicy::BigInt, ex::Plus(Box::new(ex::Minus(Box::new(max_sq()), Box::new(max_sq()))), num(1)), val::Numeral(1));
+  let r54 = match base.run_with(&Evaluator::new().with_arith(ArithPolicy::BigInt), &checked(&base, &ex::Times(num(i64::MIN), num(i64::MAX)))) {
+    Ok(v) if v.to_string() == "-85070591730234615856620279821087277056" => 1.0,
+    r => {println!("Arithmetic test case MIN * MAX under BigInt expected -85070591730234615856620279821087277056 but got {:?}", r); 0.0}
+  };
+  let r55 = test_policy(1.0, ArithPolicy::BigInt, ex::Compare(Box::new(max_sq()), Comparison::Greater, Box::new(ex::Times(num(i64::MIN), num(i64::MIN)))), val::Boolean(false));
+  let r_policy = r50+r51+r52+r53+r54+r55;
+
+  // 16 tests running the well-typed programs of Typing/src/main.rs
+  let cmp0 = |c: Comparison| ex::Compare(num(0), c, num(0));
+  let s = |x: &str| ex::StringLiteral(x.to_string());
+  let st = |x: &str| val::String(x.to_string());
+  let ssqr = |args: Vec<Expr>| ex::Let(Box::new(dc::FunDefn("ssqr".to_string(), vec![("x".to_string(), tp::Number), ("y".to_string(), tp::Number)], tp::Number,
+    Box::new(ex::Plus(Box::new(ex::Times(id("x"), id("x"))), Box::new(ex::Times(id("x"), id("x"))))))), Box::new(ex::Call("ssqr".to_string(), args)));
+  let r56 = test_checked(1.0, ex::Plus(num(1), num(2)), val::Numeral(3));
+  let r57 = test_checked(1.0, ex::Let(var("x", ex::Plus(num(1), num(2))), Box::new(ex::Minus(id("x"), num(3)))), val::Numeral(0));
+  let r58 = test_checked(1.0, s("OwO"), st("OwO"));
+  let r59 = test_checked(1.0, ex::True, val::Boolean(true));
+  let r60 = test_checked(1.0, ex::False, val::Boolean(false));
+  let r61 = test_checked(1.0, cmp0(Comparison::Equal), val::Boolean(true));
+  let r62 = test_checked(1.0, cmp0(Comparison::Less), val::Boolean(false));
+  let r63 = test_checked(1.0, cmp0(Comparison::LessEqual), val::Boolean(true));
+  let r64 = test_checked(1.0, cmp0(Comparison::Greater), val::Boolean(false));
+  let r65 = test_checked(1.0, cmp0(Comparison::GreaterEqual), val::Boolean(true));
+  let r66 = test_checked(1.0, cmp0(Comparison::NotEqual), val::Boolean(false));
+  let r67 = test_checked(1.0, ex::Let(Box::new(dc::FunDefn("f".to_string(), vec![], tp::Boolean, Box::new(cmp0(Comparison::Equal)))),
+    Box::new(ex::Call("f".to_string(), vec![]))), val::Boolean(true));
+  let r68 = test_checked(1.0, ex::Let(Box::new(dc::FunDefn("f".to_string(), vec![], tp::String, Box::new(s("brrr")))),
+    Box::new(ex::Call("f".to_string(), vec![]))), st("brrr"));
+  let r69 = test_checked(1.0, ssqr(vec![ex::Numeral(1), ex::Numeral(2)]), val::Numeral(2));
+  let r70 = test_checked(1.0, ex::Let(Box::new(dc::FunDefn("f".to_string(), vec![("y".to_string(), tp::Function(vec![tp::Boolean], Box::new(tp::String)))], tp::Number,
+    Box::new(ex::Call("f".to_string(), vec![ex::Id("y".to_string())])))), num(3)), val::Numeral(3));
+  let r71 = test_checked(1.0, ex::Assert(Box::new(ex::Compare(num(1), Comparison::Less, num(2))), Box::new(s("ordered"))), val::Boolean(true));
+  let r_typed = r56+r57+r58+r59+r60+r61+r62+r63+r64+r65+r66+r67+r68+r69+r70+r71;
+
+  // 6 tests of functions and unit as values
+  let num_fun = || tp::Function(vec![tp::Number], Box::new(tp::Number));
+  let inc = fun("inc", "x", ex::Plus(id("x"), num(1)));
+  let twice = |k: Expr| ex::Let(Box::new(dc::FunDefn("twice".to_string(), vec![("g".to_string(), num_fun()), ("x".to_string(), tp::Number)], tp::Number,
+    Box::new(call("g", call("g", ex::Id("x".to_string())))))), Box::new(k));
+  let twice_inc = ex::Call("twice".to_string(), vec![ex::Id("inc".to_string()), ex::Numeral(3)]);
+  let r72 = test_checked(1.0, ex::Let(inc.clone(), Box::new(twice(twice_inc))), val::Numeral(5));
+  let r73 = test_checked(1.0, ex::Let(inc.clone(), Box::new(ex::Let(var("g", ex::Id("inc".to_string())), Box::new(call("g", ex::Numeral(1)))))), val::Numeral(2));
+  let r74 = test_checked(1.0, ex::Let(inc.clone(), Box::new(ex::Let(Box::new(dc::FunDefn("get".to_string(), vec![], num_fun(), id("inc"))),
+    Box::new(ex::Let(var("h", ex::Call("get".to_string(), vec![])), Box::new(call("h", ex::Numeral(10)))))))), val::Numeral(11));
+  let r75 = match CheckedProgram::check(ex::Let(inc.clone(), id("inc"))).map(|p| eval_program(&p)) {
+    Some(Ok(v @ val::Closure(_))) if v.to_string() == "<function inc>" => 1.0,
+    r => {println!("Function value test case inc expected <function inc> but got {:?}", r); 0.0}
+  };
+  let twice_abs = twice(ex::Call("twice".to_string(), vec![ex::Id("abs".to_string()), ex::Numeral(-3)]));
+  let r76 = match prelude.check(twice_abs.clone()) {
+    Some(p) if prelude.run(&p) == Ok(val::Numeral(3)) => 1.0,
+    p => {println!("Function value test case {:?} expected value 3 but got {:?}", twice_abs, p.map(|p| prelude.run(&p))); 0.0}
+  };
+  let mut logger = Engine::new();
+  let logged = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+  let log = logged.clone();
+  logger.register_fn("log", move |m: String| log.borrow_mut().push(m));
+  let r77 = match logger.check(ex::Call("log".to_string(), vec![s("hello")])) {
+    Some(p) if *p.ty() == tp::Unit && logger.run(&p) == Ok(val::Unit) && *logged.borrow() == vec!["hello".to_string()] => 1.0,
+    p => {println!("Unit test case log(\"hello\") expected () but got {:?}", p.map(|p| logger.run(&p))); 0.0}
+  };
+  let r_values = r72+r73+r74+r75+r76+r77;
+
+  // 4 tests of resource limits, with functions that call themselves forever
+  let forever = ex::Let(fun("f", "x", call("f", ex::Id("x".to_string()))), Box::new(call("f", ex::Numeral(1))));
+  let deeper = ex::Let(fun("f", "x", ex::Plus(num(1), Box::new(call("f", ex::Id("x".to_string()))))), Box::new(call("f", ex::Numeral(1))));
+  let limits = |max_steps: u64, max_depth: usize, max_env_size: usize| Limits { max_steps, max_depth, max_env_size };
+  let test_limit = |l: Limits, e: &Expr, out: Resource| {
+    let (p, limited) = (checked(&base, e), Evaluator::new().with_limits(l));
+    match eval_with_limits(&p, l) {
+      Err(err) if *err.cause() == EvalError::ResourceExhausted(out)
+        && base.run_cek(&limited, &p) == Err(err.clone())
+        && (!matches!(out, Resource::Depth(_)) || base.run_vm(&limited, &p) == Err(err.clone())) => 1.0,
+      r => {println!("Limit test case {:?} under {:?} expected to run out of {:?} but got {:?}", e, l, out, r); 0.0}
+    }
+  };
+  let r78 = test_limit(limits(u64::MAX, 100, usize::MAX), &deeper, Resource::Depth(100));
+  let r79 = test_limit(limits(50, usize::MAX, usize::MAX), &forever, Resource::Steps(50));
+  let r80 = test_limit(limits(u64::MAX, usize::MAX, 2), &ex::Let(var("a", ex::Numeral(1)), Box::new(ex::Let(var("b", ex::Numeral(2)),
+    Box::new(ex::Let(var("c", ex::Numeral(3)), id("a")))))), Resource::EnvSize(2));
+  let limited = Evaluator::new().with_limits(limits(3, 0, 0));
+  let r81 = match base.run_with(&limited, &checked(&base, &ex::Plus(num(1), num(2)))) {
+    Ok(val::Numeral(3)) if limited.steps() == 3 => 1.0,
+    r => {println!("Limit test case 1+2 expected 3 in 3 steps but got {:?} in {} steps", r, limited.steps()); 0.0}
+  };
+  let r_limits = r78+r79+r80+r81;
+
+  // 5 tests of conditionals and of the CEK machine, which is checked against
+  // eval_expr in every test above as well
+  let r82 = test_expr(1.0, ex::If(Box::new(ex::Compare(num(1), Comparison::Less, num(2))), Box::new(s("yes")), Box::new(s("no"))), st("yes"));
+  let r83 = test_expr(1.0, ex::If(Box::new(ex::False), Box::new(ex::Assert(Box::new(ex::False), Box::new(s("not taken")))), Box::new(ex::True)), val::Boolean(true));
+  let r84 = test_rejected(1.0, &base, ex::If(num(1), num(2), num(3)));
+  // count(n) = 1 + count(n - 1) recurses a million calls deep, which eval_expr
+  // cannot do on the Rust stack
+  let count = |n: i64| ex::Let(fun("count", "n", ex::If(Box::new(ex::Compare(id("n"), Comparison::Equal, num(0))), num(0),
+    Box::new(ex::Plus(num(1), Box::new(call("count", ex::Minus(id("n"), num(1)))))))), Box::new(call("count", ex::Numeral(n))));
+  let r85 = match cek::eval_cek(&checked(&base, &count(1_000_000))) {
+    Ok(val::Numeral(1_000_000)) => 1.0,
+    r => {println!("CEK test case count(1000000) expected 1000000 but got {:?}", r); 0.0}
+  };
+  let r86 = match base.run_cek(&Evaluator::new().with_limits(limits(u64::MAX, 1000, usize::MAX)), &checked(&base, &count(2000))) {
+    Err(e) if *e.cause() == EvalError::ResourceExhausted(Resource::Depth(1000)) => 1.0,
+    r => {println!("CEK test case count(2000) expected to run out of 1000 nested calls but got {:?}", r); 0.0}
+  };
+  let r_cek = r82+r83+r84+r85+r86;
+
+  // 4 tests of tail calls, which eval_expr and the CEK machine make without
+  // using up the Rust stack or the limit on nested calls
+  let sum_to = |n: i64| ex::Let(Box::new(dc::FunDefn("loop".to_string(), vec![("n".to_string(), tp::Number), ("acc".to_string(), tp::Number)], tp::Number,
+    Box::new(ex::If(Box::new(ex::Compare(id("n"), Comparison::Equal, num(0))), id("acc"),
+      Box::new(ex::Let(var("m", ex::Minus(id("n"), num(1))),
+        Box::new(ex::Call("loop".to_string(), vec![ex::Id("m".to_string()), ex::Plus(id("acc"), id("n"))])))))))),
+    Box::new(ex::Call("loop".to_string(), vec![ex::Numeral(n), ex::Numeral(0)])));
+  let r87 = match eval_program(&checked(&base, &sum_to(1_000_000))) {
+    Ok(val::Numeral(500_000_500_000)) => 1.0,
+    r => {println!("Tail call test case loop(1000000, 0) expected 500000500000 but got {:?}", r); 0.0}
+  };
+  let shallow = Evaluator::new().with_limits(limits(u64::MAX, 1, usize::MAX));
+  let loop_1000 = checked(&base, &sum_to(1000));
+  let r88 = match (base.run_with(&shallow, &loop_1000), base.run_cek(&shallow, &loop_1000)) {
+    (Ok(val::Numeral(500_500)), Ok(val::Numeral(500_500))) => 1.0,
+    r => {println!("Tail call test case loop(1000, 0) expected 500500 in one call frame but got {:?}", r); 0.0}
+  };
+  let is_even = |n: i64| ex::Let(Box::new(dc::RecDefn(vec![
+    ("even".to_string(), vec![("n".to_string(), tp::Number)], tp::Boolean,
+      Box::new(ex::If(Box::new(ex::Compare(id("n"), Comparison::Equal, num(0))), Box::new(ex::True), Box::new(call("odd", ex::Minus(id("n"), num(1))))))),
+    ("odd".to_string(), vec![("n".to_string(), tp::Number)], tp::Boolean,
+      Box::new(ex::If(Box::new(ex::Compare(id("n"), Comparison::Equal, num(0))), Box::new(ex::False), Box::new(call("even", ex::Minus(id("n"), num(1))))))),
+  ])), Box::new(call("even", ex::Numeral(n))));
+  let r89 = test_expr(1.0, is_even(10_001), val::Boolean(false));
+  // a failed assert in a tail-called body is reported at the innermost span of the call
+  let failing_loop = ex::Let(Box::new(dc::FunDefn("g".to_string(), vec![("n".to_string(), tp::Number)], tp::Boolean,
+      Box::new(ex::If(Box::new(ex::Compare(id("n"), Comparison::Equal, num(0))),
+      Box::new(ex::Assert(Box::new(ex::False), Box::new(s("done")))), Box::new(ex::Spanned(Span::new(30, 40), Box::new(call("g", ex::Minus(id("n"), num(1)))))))))),
+    Box::new(ex::Spanned(Span::new(50, 54), Box::new(call("g", ex::Numeral(3))))));
+  let failing_p = checked(&base, &failing_loop);
+  let r90 = match (eval_program(&failing_p), cek::eval_cek(&failing_p), vm::eval_vm(&failing_p)) {
+    (Err(e1), Err(e2), Err(e3)) if e1 == e2 && e1 == e3 && e1.span() == Some(Span::new(30, 40)) => 1.0,
+    r => {println!("Tail call test case {:?} expected the same error at 30..40 from every evaluator but got {:?}", failing_loop, r); 0.0}
+  };
+  let r_tail = r87+r88+r89+r90;
+
+  // 5 tests of the bytecode VM, which is checked against eval_expr in every test
+  // above as well
+  let r91 = match vm::eval_vm(&checked(&base, &count(1_000_000))) {
+    Ok(val::Numeral(1_000_000)) => 1.0,
+    r => {println!("VM test case count(1000000) expected 1000000 but got {:?}", r); 0.0}
+  };
+  let r92 = match base.run_vm(&shallow, &loop_1000) {
+    Ok(val::Numeral(500_500)) => 1.0,
+    r => {println!("VM test case loop(1000, 0) expected 500500 in one call frame but got {:?}", r); 0.0}
+  };
+  // a closure made by the VM is the closure eval_expr makes, with the local names
+  // in scope where it was defined
+  let adder = ex::Let(var("y", ex::Numeral(1)), Box::new(ex::Let(Box::new(dc::FunDefn("outer".to_string(), vec![("n".to_string(), tp::Number)], num_fun(),
+    Box::new(ex::Let(fun("add", "x", ex::Plus(id("x"), Box::new(ex::Plus(id("n"), id("y"))))), id("add"))))), Box::new(ex::Let(var("g", call("outer", ex::Numeral(10))), Box::new(call("g", ex::Numeral(100))))))));
+  let r93 = test_expr(1.0, adder.clone(), val::Numeral(111));
+  let inc_p = checked(&with_f, &ex::Let(inc.clone(), id("inc")));
+  let r94 = match (with_f.run(&inc_p), with_f.run_vm(&Evaluator::new(), &inc_p)) {
+    (Ok(v1 @ val::Closure(_)), Ok(v2)) if v1 == v2 => 1.0,
+    r => {println!("VM test case inc expected the closure eval_expr gives but got {:?}", r); 0.0}
+  };
+  // a compiled program runs again under other settings without compiling it again
+  let overflowing = base.compile(&checked(&base, &ex::Plus(num(i64::MAX), num(1)))).expect("the program was checked by base");
+  let r95 = match (Evaluator::new().run_compiled(&overflowing), Evaluator::new().with_arith(ArithPolicy::Wrapping).run_compiled(&overflowing)) {
+    (Err(e), Ok(val::Numeral(i64::MIN))) if matches!(e.cause(), EvalError::IntegerOverflow(_, _)) => 1.0,
+    r => {println!("VM test case MAX + 1 expected an overflow and then MIN but got {:?}", r); 0.0}
+  };
+  let r_vm = r91+r92+r93+r94+r95;
+
+  // 5 tests of small-step reduction, which ends in the value or error eval_expr gives
+  let reduces_to = |ev: &Evaluator, in_e: &Expr| base.trace(ev, &checked(&base, in_e)).last().expect("a trace has the program in it");
+  let sq_sq = ex::Let(fun("sq", "x", ex::Times(id("x"), id("x"))), Box::new(call("sq", call("sq", ex::Numeral(4)))));
+  let steps: Result<Vec<Expr>, EvalError> = smallstep::trace(&checked(&base, &sq_sq)).map(|e| e.map(|e| e.strip_spans())).collect();
+  let expected = vec![sq_sq.clone(), call("sq", call("sq", ex::Numeral(4))), call("sq", ex::Times(num(4), num(4))),
+    call("sq", ex::Numeral(16)), ex::Times(num(16), num(16)), ex::Numeral(256)];
+  let r96 = match steps {
+    Ok(steps) if steps == expected => 1.0,
+    r => {println!("Small-step test case sq(sq(4)) expected the steps {:?} but got {:?}", expected, r); 0.0}
+  };
+  // a function body put in place of a call still sees the x it was defined with
+  let shadowed = ex::Let(var("x", ex::Numeral(1)), Box::new(ex::Let(fun("f", "y", ex::Plus(id("x"), id("y"))),
+    Box::new(ex::Let(var("x", ex::Numeral(10)), Box::new(call("f", ex::Id("x".to_string())))))))); 
+  let r97 = match reduces_to(&Evaluator::new(), &shadowed) {
+    Ok(ex::Numeral(11)) => 1.0,
+    r => {println!("Small-step test case {:?} expected 11 but got {:?}", shadowed, r); 0.0}
+  };
+  // the function passed as k is not captured by the local g it is put under
+  let twice_g = ex::Let(fun("g", "n", ex::Plus(id("n"), num(1))), Box::new(ex::Let(
+    Box::new(dc::FunDefn("h".to_string(), vec![("k".to_string(), num_fun())], tp::Number,
+      Box::new(ex::Let(fun("g", "n", ex::Times(id("n"), num(100))), Box::new(call("k", ex::Numeral(1))))))),
+    Box::new(call("h", ex::Id("g".to_string()))))));
+  let r98 = match (reduces_to(&Evaluator::new(), &twice_g), eval_program(&checked(&base, &twice_g))) {
+    (Ok(ex::Numeral(2)), Ok(val::Numeral(2))) => 1.0,
+    r => {println!("Small-step test case {:?} expected 2 like eval_expr but got {:?}", twice_g, r); 0.0}
+  };
+  let r99 = match (reduces_to(&Evaluator::new(), &failing_loop), eval_program(&checked(&base, &failing_loop))) {
+    (Err(e1), Err(e2)) if e1.span() == e2.span() && e1.cause() == e2.cause() => 1.0,
+    r => {println!("Small-step test case {:?} expected the error eval_expr gives but got {:?}", failing_loop, r); 0.0}
+  };
+  let r100 = match reduces_to(&Evaluator::new().with_arith(ArithPolicy::Wrapping), &ex::Plus(num(i64::MAX), num(1))) {
+    Ok(ex::Numeral(i64::MIN)) => 1.0,
+    r => {println!("Small-step test case MAX + 1 expected MIN when wrapping but got {:?}", r); 0.0}
+  };
+  let r_small = r96+r97+r98+r99+r100;
+
+  // 5 tests of derivation trees, whose root has the value eval_expr gives
+  let sq_4 = ex::Let(fun("sq", "x", ex::Times(id("x"), id("x"))), Box::new(call("sq", ex::Numeral(4))));
+  fn rules(d: &derivation::Derivation, out: &mut Vec<&'static str>) {
+    out.push(d.rule.name());
+    d.premises.iter().for_each(|p| rules(p, out));
+  }
+  let r101 = match derivation::derive(&checked(&base, &sq_4)) {
+    Ok(d) => {
+      let mut names = vec![];
+      rules(&d, &mut names);
+      if names == ["Let", "Fun", "Call", "Number", "Times", "Id", "Id"] && d.value() == Some(&val::Numeral(16)) { 1.0 }
+      else {println!("Derivation test case {:?} expected Let, Fun, Call, Number, Times, Id, Id but got {:?}", sq_4, names); 0.0}
+    },
+    r => {println!("Derivation test case {:?} expected a derivation but got {:?}", sq_4, r); 0.0}
+  };
+  let agreeing = [adder.clone(), shadowed.clone(), twice_g.clone(), is_even(10), sum_to(50)];
+  let r102 = if agreeing.iter().all(|e| derivation::derive(&checked(&base, e)).map(|d| d.value().cloned()) == eval_program(&checked(&base, e)).map(Some)) { 1.0 }
+    else {println!("Derivation test case {:?} expected the values eval_expr gives", agreeing); 0.0};
+  let sq_text = "[Let] {} ⊢ let function sq(x:number):number{x*x} in sq(4) ⇓ 16
+  [Fun] {} ⊢ function sq(x:number):number{x*x} ⇓ {sq ↦ <function sq>}
+  [Call] {sq ↦ <function sq>} ⊢ sq(4) ⇓ 16
+    [Number] {sq ↦ <function sq>} ⊢ 4 ⇓ 4
+    [Times] {sq ↦ <function sq>, x ↦ 4} ⊢ x*x ⇓ 16
+      [Id] {sq ↦ <function sq>, x ↦ 4} ⊢ x ⇓ 4
+      [Id] {sq ↦ <function sq>, x ↦ 4} ⊢ x ⇓ 4
+";
+  let r103 = match derivation::derive(&checked(&base, &sq_4)).map(|d| d.to_text()) {
+    Ok(text) if text == sq_text => 1.0,
+    r => {println!("Derivation test case {:?} expected the text {} but got {:?}", sq_4, sq_text, r); 0.0}
+  };
+  let let_latex = "\\begin{prooftree}
+\\AxiomC{}
+\\RightLabel{\\scriptsize String}
+\\UnaryInfC{$\\{\\} \\vdash \\texttt{\"a\\_b\"} \\Downarrow \\texttt{\"a\\_b\"}$}
+\\RightLabel{\\scriptsize Var}
+\\UnaryInfC{$\\{\\} \\vdash \\texttt{var s = \"a\\_b\"} \\Downarrow \\{\\texttt{s} \\mapsto \\texttt{\"a\\_b\"}\\}$}
+\\AxiomC{}
+\\RightLabel{\\scriptsize True}
+\\UnaryInfC{$\\{\\texttt{s} \\mapsto \\texttt{\"a\\_b\"}\\} \\vdash \\texttt{true} \\Downarrow \\texttt{true}$}
+\\RightLabel{\\scriptsize Let}
+\\BinaryInfC{$\\{\\} \\vdash \\texttt{let var s = \"a\\_b\" in true} \\Downarrow \\texttt{true}$}
+\\end{prooftree}
+";
+  let let_s = ex::Let(var("s", s("a_b")), Box::new(ex::True));
+  let r104 = match derivation::derive(&checked(&base, &let_s)).map(|d| d.to_latex()) {
+    Ok(latex) if latex == let_latex => 1.0,
+    r => {println!("Derivation test case {:?} expected the LaTeX {} but got {:?}", let_s, let_latex, r); 0.0}
+  };
+  let r105 = match (derivation::derive(&checked(&base, &failing_loop)), eval_program(&checked(&base, &failing_loop))) {
+    (Err(e1), Err(e2)) if e1 == e2 => 1.0,
+    r => {println!("Derivation test case {:?} expected the error eval_expr gives but got {:?}", failing_loop, r); 0.0}
+  };
+  let r_derive = r101+r102+r103+r104+r105;
+
+  // 5 tests of the debugger, run with a script of commands
+  let debug = |e: &Expr, breakpoints: Vec<Breakpoint>, commands: Vec<Command>| {
+    let mut debugger = Debugger::new(Evaluator::new());
+    debugger.breakpoints = breakpoints;
+    let mut script = Script { commands, pauses: vec![] };
+    let result = base.debug(&mut debugger, &checked(&base, e), &mut script);
+    (result, script.pauses)
+  };
+  let r106 = match debug(&sq_sq, vec![], vec![]) {
+    (Some(Ok(val::Numeral(256))), pauses) if pauses.len() == 1 && pauses[0].0 == Reason::Start => 1.0,
+    r => {println!("Debugger test case {:?} expected 256 after one pause but got {:?}", sq_sq, r); 0.0}
+  };
+  let on_sq = vec![Breakpoint::Function("sq".to_string())];
+  let r107 = match debug(&sq_sq, on_sq.clone(), vec![]) {
+    (Some(Ok(val::Numeral(256))), pauses) if pauses.iter().map(|(r, e, depth)| (r.clone(), e.clone(), *depth)).collect::<Vec<_>>() == vec![
+      (Reason::Start, sq_sq.clone(), 0),
+      (Reason::Breakpoint(on_sq[0].clone()), ex::Times(id("x"), id("x")), 1),
+      (Reason::Breakpoint(on_sq[0].clone()), ex::Times(id("x"), id("x")), 1)] => 1.0,
+    r => {println!("Debugger test case {:?} expected to stop in each call of sq but got {:?}", sq_sq, r); 0.0}
+  };
+  // stepping over the let goes on to its body, and stepping over that finishes
+  let r108 = match (debug(&sq_sq, vec![], vec![Command::StepOver; 2]), debug(&sq_sq, vec![], vec![Command::StepInto; 5])) {
+    ((Some(Ok(_)), over), (Some(Ok(_)), into)) if over.len() == 2 && into.len() == 5 && into[2].1 == call("sq", ex::Numeral(4)) => 1.0,
+    r => {println!("Debugger test case {:?} expected 2 pauses stepping over and 5 stepping into but got {:?}", sq_sq, r); 0.0}
+  };
+  let f_twice = ex::Let(fun("f", "x", ex::Plus(id("x"), num(1))), Box::new(ex::Times(Box::new(call("f", ex::Numeral(1))), Box::new(call("f", ex::Numeral(2))))));
+  let on_f = vec![Breakpoint::Function("f".to_string())];
+  let r109 = match debug(&f_twice, on_f, vec![Command::Continue, Command::StepOut]) {
+    (Some(Ok(val::Numeral(6))), pauses) if pauses.len() == 4 && pauses[2] == (Reason::Step, call("f", ex::Numeral(2)), 0) => 1.0,
+    r => {println!("Debugger test case {:?} expected to step out of f(1) to f(2) but got {:?}", f_twice, r); 0.0}
+  };
+  let r110 = match (debug(&sq_sq, vec![], vec![Command::Quit]), debug(&failing_loop, vec![], vec![])) {
+    ((None, _), (Some(Err(e)), _)) if Err(e.clone()) == eval_program(&checked(&base, &failing_loop)) => 1.0,
+    r => {println!("Debugger test case quit and {:?} expected nothing and the error eval_expr gives but got {:?}", failing_loop, r); 0.0}
+  };
+  let r_debug = r106+r107+r108+r109+r110;
+
+  // 5 tests of the profiler, which runs programs like eval_expr while counting calls
+  let stacks = |p: &profiler::Profile| p.folded().lines().map(|l| l.rsplit_once(' ').expect("a stack and a time").0.to_string()).collect::<Vec<String>>();
+  let r111 = match profiler::profile(&checked(&base, &is_even(10))) {
+    (Ok(val::Boolean(true)), p) if p.function("even").map(|f| f.calls) == Some(6) && p.function("odd").map(|f| f.calls) == Some(5) => 1.0,
+    r => {println!("Profiler test case even(10) expected 6 calls of even and 5 of odd but got {:?}", r); 0.0}
+  };
+  let r112 = match profiler::profile(&checked(&base, &count(3))) {
+    (Ok(val::Numeral(3)), p) if p.function("count").map(|f| f.calls) == Some(4)
+      && stacks(&p) == ["program", "program;count", "program;count;count", "program;count;count;count", "program;count;count;count;count"] => 1.0,
+    r => {println!("Profiler test case count(3) expected 4 nested calls of count but got {:?}", r); 0.0}
+  };
+  let r113 = match profiler::profile(&checked(&base, &f_twice)) {
+    (Ok(val::Numeral(6)), p) if p.function("f").map(|f| f.calls) == Some(2) && stacks(&p) == ["program", "program;f"] => 1.0,
+    r => {println!("Profiler test case {:?} expected 2 calls of f from the program but got {:?}", f_twice, r); 0.0}
+  };
+  // a function's own time is part of its inclusive time, which is part of the total
+  let r114 = match profiler::profile(&checked(&base, &sum_to(200))) {
+    (Ok(val::Numeral(20_100)), p) if p.functions().iter().all(|f| f.exclusive <= f.inclusive && f.inclusive <= p.total)
+      && p.report().lines().count() == 3 && p.report().starts_with("function") => 1.0,
+    r => {println!("Profiler test case loop(200, 0) expected consistent times but got {:?}", r); 0.0}
+  };
+  let r115 = match profiler::profile(&checked(&base, &failing_loop)) {
+    (Err(e), p) if Err(e.clone()) == eval_program(&checked(&base, &failing_loop)) && p.function("g").map(|f| f.calls) == Some(4) => 1.0,
+    r => {println!("Profiler test case {:?} expected the error eval_expr gives after 4 calls of g but got {:?}", failing_loop, r); 0.0}
+  };
+  let r_profile = r111+r112+r113+r114+r115;
+
+  // 5 tests of memoization, which gives the values of evaluation without it
+  let fib = |n: i64| ex::Let(Box::new(dc::RecDefn(vec![("fib".to_string(), vec![("n".to_string(), tp::Number)], tp::Number,
+    Box::new(ex::If(Box::new(ex::Compare(id("n"), Comparison::Less, num(2))), id("n"),
+      Box::new(ex::Plus(Box::new(call("fib", ex::Minus(id("n"), num(1)))), Box::new(call("fib", ex::Minus(id("n"), num(2)))))))))])),
+    Box::new(call("fib", ex::Numeral(n))));
+  let memo = Evaluator::new().with_memo(true);
+  let stats = |hits, misses, entries| memo::MemoStats { hits, misses, entries };
+  let r116 = match (base.run_with(&memo, &checked(&base, &fib(15))), memo.memo_stats(), eval_program(&checked(&base, &fib(15)))) {
+    (Ok(v1), s, Ok(v2)) if v1 == v2 && s == stats(13, 16, 16) => 1.0,
+    r => {println!("Memo test case fib(15) expected 610 after 13 hits and 16 misses but got {:?}", r); 0.0}
+  };
+  let r117 = match (base.run_cek(&memo, &checked(&base, &fib(90))), memo.memo_stats()) {
+    (Ok(val::Numeral(2_880_067_194_370_816_120)), s) if s == stats(88, 91, 91) => 1.0,
+    r => {println!("Memo test case fib(90) on the CEK machine expected 2880067194370816120 after 88 hits but got {:?}", r); 0.0}
+  };
+  // the two adders are the same code with different captured values
+  let adders = ex::Let(Box::new(dc::FunDefn("adder".to_string(), vec![("n".to_string(), tp::Number)], num_fun(),
+    Box::new(ex::Let(fun("add", "m", ex::Plus(id("n"), id("m"))), id("add"))))),
+    Box::new(ex::Let(var("a", call("adder", ex::Numeral(1))), Box::new(ex::Let(var("b", call("adder", ex::Numeral(2))),
+      Box::new(ex::Plus(Box::new(call("a", ex::Numeral(10))), Box::new(call("b", ex::Numeral(10))))))))));
+  let r118 = match (base.run_with(&memo, &checked(&base, &adders)), base.run_cek(&memo, &checked(&base, &adders))) {
+    (Ok(val::Numeral(23)), Ok(val::Numeral(23))) => 1.0,
+    r => {println!("Memo test case {:?} expected 23 from both evaluators but got {:?}", adders, r); 0.0}
+  };
+  // every call of a chain of tail calls has the value of the last one
+  let r119 = match (base.run_with(&memo, &checked(&base, &sum_to(100))), memo.memo_stats(), base.run_cek(&memo, &checked(&base, &sum_to(100))), memo.memo_stats()) {
+    (Ok(val::Numeral(5050)), s1, Ok(val::Numeral(5050)), s2) if s1 == stats(0, 101, 101) && s1 == s2 => 1.0,
+    r => {println!("Memo test case loop(100, 0) expected 101 cached calls but got {:?}", r); 0.0}
+  };
+  let plain = Evaluator::new();
+  let r120 = match (base.run_with(&plain, &checked(&base, &fib(10))), plain.memo_stats(), base.run_with(&memo, &checked(&base, &fib(10))), memo.memo_stats()) {
+    (Ok(val::Numeral(55)), s1, Ok(val::Numeral(55)), s2) if s1 == stats(0, 0, 0) && s2 == stats(8, 11, 11) => 1.0,
+    r => {println!("Memo test case fib(10) expected no cache without memo and a fresh one with it but got {:?}", r); 0.0}
+  };
+  let r_memo = r116+r117+r118+r119+r120;
+
+  // 5 tests of evaluation strategies, which only differ on arguments that are unused or used twice
+  let by = |strategy| Evaluator::new().with_strategy(strategy);
+  let (by_value, by_name, by_need) = (by(Strategy::ByValue), by(Strategy::ByName), by(Strategy::ByNeed));
+  let on_both = |e: &Evaluator, x: &Expr| {
+    let p = checked(&base, x);
+    (base.run_with(e, &p), base.run_cek(e, &p))
+  };
+  let unused = |arg: Expr| ex::Let(fun("k", "x", ex::Numeral(1)), Box::new(call("k", arg)));
+  let overflowing = unused(ex::Times(num(i64::MAX), num(2)));
+  let r121 = match (on_both(&by_value, &overflowing), on_both(&by_name, &overflowing), on_both(&by_need, &overflowing)) {
+    ((Err(EvalError::IntegerOverflow(_, _)), Err(EvalError::IntegerOverflow(_, _))), (Ok(val::Numeral(1)), Ok(val::Numeral(1))), (Ok(val::Numeral(1)), Ok(val::Numeral(1)))) => 1.0,
+    r => {println!("Strategy test case {:?} expected an overflow by value and 1 by name and by need but got {:?}", overflowing, r); 0.0}
+  };
+  let diverging = ex::Let(fun("f", "x", call("f", ex::Id("x".to_string()))), Box::new(unused(call("f", ex::Numeral(1)))));
+  let bounded = |strategy| by(strategy).with_limits(limits(1000, usize::MAX, usize::MAX));
+  let r122 = match (on_both(&bounded(Strategy::ByValue), &diverging), on_both(&bounded(Strategy::ByName), &diverging), on_both(&bounded(Strategy::ByNeed), &diverging)) {
+    ((Err(EvalError::ResourceExhausted(Resource::Steps(1000))), Err(EvalError::ResourceExhausted(Resource::Steps(1000)))),
+      (Ok(val::Numeral(1)), Ok(val::Numeral(1))), (Ok(val::Numeral(1)), Ok(val::Numeral(1)))) => 1.0,
+    r => {println!("Strategy test case {:?} expected to run out of steps by value and give 1 by name and by need but got {:?}", diverging, r); 0.0}
+  };
+  // by name evaluates the argument of sq twice, by need once like by value
+  let used_twice = ex::Let(fun("sq", "x", ex::Times(id("x"), id("x"))), Box::new(call("sq", ex::Plus(Box::new(ex::Plus(num(1), num(2))), num(3)))));
+  let used_twice_p = checked(&base, &used_twice);
+  let steps_of = |e: &Evaluator| (base.run_with(e, &used_twice_p), e.steps(), base.run_cek(e, &used_twice_p), e.steps());
+  let r123 = match (steps_of(&by_value), steps_of(&by_name), steps_of(&by_need)) {
+    ((Ok(val::Numeral(36)), v1, Ok(val::Numeral(36)), v2), (Ok(val::Numeral(36)), n1, Ok(val::Numeral(36)), n2), (Ok(val::Numeral(36)), l1, Ok(val::Numeral(36)), l2))
+      if n1 > l1 && n2 > l2 && (l1, l2) == (v1, v2) => 1.0,
+    r => {println!("Strategy test case {:?} expected 36 in as many steps by need as by value and more by name but got {:?}", used_twice, r); 0.0}
+  };
+  // the adders capture the thunks of their parameters
+  let r124 = match (on_both(&by_name, &adders), on_both(&by_need, &adders)) {
+    ((Ok(val::Numeral(23)), Ok(val::Numeral(23))), (Ok(val::Numeral(23)), Ok(val::Numeral(23)))) => 1.0,
+    r => {println!("Strategy test case {:?} expected 23 by name and by need but got {:?}", adders, r); 0.0}
+  };
+  let agree = |x: &Expr| [&by_value, &by_name, &by_need].iter().all(|e| on_both(e, x) == (eval_program(&checked(&base, x)), eval_program(&checked(&base, x))));
+  let r125 = match (agree(&fib(10)), agree(&sq_sq), agree(&sum_to(100)), agree(&shadowed)) {
+    (true, true, true, true) => 1.0,
+    r => {println!("Strategy test cases expected every strategy to give the values of eval_expr but got {:?}", r); 0.0}
+  };
+  let r_strategy = r121+r122+r123+r124+r125;
+
+  // 1 test of a fractional numeral, which parses but has no type
+  let decimal = ex::Plus(Box::new(ex::Decimal(12, 1)), Box::new(ex::Numeral(1)));
+  let r126 = test_rejected(1.0, &base, decimal);
+
+  // 1 test of a program run by an engine it was not checked against, which is an error rather than a panic
+  let elsewhere = Engine::with_prelude().check(ex::Call("abs".to_string(), vec![ex::Numeral(-1)])).expect("abs(-1) type-checks");
+  let bare = Engine::new();
+  let r127 = match (bare.run(&elsewhere), bare.run_cek(&Evaluator::new(), &elsewhere), bare.trace(&Evaluator::new(), &elsewhere).collect::<Vec<_>>(),
+    bare.profile(&Evaluator::new(), &elsewhere).0) {
+    (Err(EvalError::ContextMismatch), Err(EvalError::ContextMismatch), t, Err(EvalError::ContextMismatch)) if t == [Err(EvalError::ContextMismatch)] => 1.0,
+    r => {println!("Engine test case abs(-1) checked with the prelude expected a context mismatch without it but got {:?}", r); 0.0}
+  };
+
+  // 1 test of eval_program, which runs a program checked with the prelude against the prelude, and one checked elsewhere not at all
+  let mut double = Engine::with_prelude();
+  double.register_fn("double", |x: i64| 2 * x);
+  let custom = double.check(ex::Call("double".to_string(), vec![ex::Numeral(21)])).expect("double(21) type-checks");
+  let r128 = match (eval_program(&elsewhere), eval_program(&custom)) {
+    (Ok(val::Numeral(1)), Err(EvalError::ContextMismatch)) => 1.0,
+    r => {println!("eval_program test case expected abs(-1) to give 1 and double(21) a context mismatch but got {:?}", r); 0.0}
+  };
+
+  // 1 test of small-step reduction with operands nested on the left, printed as toi run --trace prints it
+  let nested = toi_parser::parser::parser::expr("((10-4)-3)*2").expect("((10-4)-3)*2 parses");
+  let printed: Result<Vec<String>, EvalError> = smallstep::trace(&checked(&base, &nested)).map(|e| e.map(toi_parser::parser::expr_to_string)).collect();
+  let expected = vec!["((10-4)-3)*2", "(6-3)*2", "3*2", "6"];
+  let r129 = match printed {
+    Ok(steps) if steps == expected => 1.0,
+    r => {println!("Small-step test case ((10-4)-3)*2 expected the printed steps {:?} but got {:?}", expected, r); 0.0}
+  };
+
+  // 1 test of memoization with calls by need, whose thunk arguments are not cached
+  let lazy_memo = by(Strategy::ByNeed).with_memo(true);
+  let r130 = match (on_both(&lazy_memo, &fib(15)), lazy_memo.memo_stats()) {
+    ((Ok(val::Numeral(610)), Ok(val::Numeral(610))), s) if s == stats(0, 0, 0) => 1.0,
+    r => {println!("Memo test case fib(15) by need expected 610 without caching thunks but got {:?}", r); 0.0}
+  };
+
+  // 1 test of the debugger with no limits on a program that recurses without end, which stops at STACK_DEPTH calls
+  let endless = deeper.clone();
+  let r131 = on_big_stack(move || {
+    let base = Engine::new();
+    let mut debugger = Debugger::new(Evaluator::new());
+    match base.debug(&mut debugger, &checked(&base, &endless), &mut Script { commands: vec![], pauses: vec![] }) {
+      Some(Err(e)) if *e.cause() == EvalError::ResourceExhausted(Resource::Depth(STACK_DEPTH)) => 1.0,
+      r => {println!("Debugger test case {:?} expected to stop at {} nested calls but got {:?}", endless, STACK_DEPTH, r); 0.0}
+    }
+  });
+
+  // 1 test of a derivation with no limits of a program that recurses without end, which stops at STACK_DEPTH calls
+  let endless = deeper.clone();
+  let r132 = on_big_stack(move || {
+    match derivation::derive(&checked(&Engine::new(), &endless)) {
+      Err(e) if *e.cause() == EvalError::ResourceExhausted(Resource::Depth(STACK_DEPTH)) => 1.0,
+      r => {println!("Derivation test case {:?} expected to stop at {} nested calls but got {:?}", endless, STACK_DEPTH, r); 0.0}
+    }
+  });
+
+  // 1 test of a single small step, which moves sq into the environment, and of a value, which takes none
+  let r133 = match (smallstep::step(&checked(&base, &sq_sq)), smallstep::step(&checked(&base, &ex::Numeral(256)))) {
+    (Ok(Some((env, e))), Ok(None)) if env.contains_key("sq") && e.strip_spans() == call("sq", call("sq", ex::Numeral(4))) => 1.0,
+    r => {println!("Small-step test case {:?} expected one step to sq(sq(4)) and none from 256 but got {:?}", sq_sq, r); 0.0}
+  };
+
+  // 1 test of a trace with no limits of a program that recurses without end, which stops at STACK_DEPTH nested calls
+  let endless = deeper.clone();
+  let r134 = on_big_stack(move || {
+    match smallstep::trace(&checked(&Engine::new(), &endless)).last() {
+      Some(Err(e)) if *e.cause() == EvalError::ResourceExhausted(Resource::Depth(STACK_DEPTH)) => 1.0,
+      r => {println!("Small-step test case {:?} expected to stop at {} nested calls but got {:?}", endless, STACK_DEPTH, r); 0.0}
+    }
+  });
 
-  let r = r_easy + r_hard;
-  println!("Results: {}/15 tests succesfully completed", r)
+  let r = r_easy + r_hard + r_checked + r_engine + r_prelude + r_encoding + r_assert + r_errors + r_scope + r_rec + r_arity + r_policy + r_typed + r_values + r_limits + r_cek + r_tail + r_vm + r_small + r_derive + r_debug + r_profile + r_memo + r_strategy + r126 + r127 + r128 + r129 + r130 + r131 + r132 + r133 + r134;
+  println!("Results: {}/134 tests succesfully completed", r)
 
   }
   
\ No newline at end of file
@@ -0,0 +1,123 @@
+/* Memoization of calls of Toi functions. Toi has no side effects, so a function
+ * called again with the same arguments gives the same value, and an evaluator
+ * with memoization on looks it up instead of running the body again:
+ *   let evaluator = Evaluator::new().with_memo(true);
+ *   engine.run_with(&evaluator, &p)   // fib(80) in linear time
+ *   evaluator.memo_stats()            // hits, misses and entries of the cache
+ *
+ * The cache is keyed by the closure called and the values of its arguments. A
+ * closure is its code together with the environment it was defined in, so two
+ * functions made by the same definition with different captured values, such as
+ * the adders returned by adder(1) and adder(2), are cached apart. Native
+ * functions are host code that may not be pure, so their calls are not cached.
+ * A call in tail position has the value of the call it is made from, so both are
+ * cached once the last of a chain of tail calls returns.
+ *
+ * eval_expr and the CEK machine memoize; the other evaluators ignore the setting.
+ * A call by name or by need (see strategy.rs) passes thunks rather than values,
+ * which could only be compared by their code and environments, so such a call
+ * is neither looked up nor cached.
+ * Each evaluation starts with an empty cache, which grows with every distinct
+ * call until the evaluation ends.
+ */
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use crate::evaluator::{Closure, Evaluator, Value};
+
+/* A call: the closure called and its arguments. The closure is identified by the
+ * group it was made with, by address, rather than by the code of the group, which
+ * would be hashed and compared on every call. Holding the closure keeps the group
+ * alive, so its address is not reused by another group while the key is cached. */
+#[derive(Debug, Clone)]
+pub(crate) struct Key {
+  callee: Closure,
+  args: Vec<Value>,
+}
+
+impl Hash for Key {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    Rc::as_ptr(&self.callee.group).hash(state);
+    self.callee.index.hash(state);
+    self.args.hash(state);
+  }
+}
+
+/* The environment is compared last, so only on a call that is otherwise cached */
+impl PartialEq for Key {
+  fn eq(&self, other: &Key) -> bool {
+    Rc::ptr_eq(&self.callee.group, &other.callee.group) && self.callee.index == other.callee.index
+      && self.args == other.args && self.callee.env == other.callee.env
+  }
+}
+
+impl Eq for Key {}
+
+/* How a memoizing evaluation used its cache: the calls found in it, the calls
+ * that had to be evaluated, and how many values it holds */
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub struct MemoStats {
+  pub hits: u64,
+  pub misses: u64,
+  pub entries: usize,
+}
+
+/* The values of the calls made so far, and how often they were looked up */
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub(crate) struct Cache {
+  values: HashMap<Key, Value>,
+  hits: u64,
+  misses: u64,
+}
+
+impl Evaluator {
+  /* Turns memoization of calls on or off */
+  pub fn with_memo(mut self, memo: bool) -> Evaluator {
+    self.memo = memo;
+    self
+  }
+
+  /* How the last evaluation used its cache */
+  pub fn memo_stats(&self) -> MemoStats {
+    let cache = self.cache.borrow();
+    MemoStats { hits: cache.hits, misses: cache.misses, entries: cache.values.len() }
+  }
+
+  /* The value of calling c with args if it is known. Otherwise the key to
+   * remember the value under once it is, or None if memoization is off or an
+   * argument is a thunk. */
+  pub(crate) fn recall(&self, c: &Closure, args: &[Value]) -> Result<Value, Option<Key>> {
+    if !self.memo || args.iter().any(|v| matches!(v, Value::Thunk(_))) {
+      return Err(None)
+    }
+    let mut cache = self.cache.borrow_mut();
+    let key = Key { callee: c.clone(), args: args.to_vec() };
+    match cache.values.get(&key) {
+      Some(v) => {
+        let v = v.clone();
+        cache.hits += 1;
+        Ok(v)
+      },
+      None => {
+        cache.misses += 1;
+        Err(Some(key))
+      },
+    }
+  }
+
+  /* Caches v as the value of each of the calls keys */
+  pub(crate) fn remember(&self, keys: Vec<Key>, v: &Value) {
+    if keys.is_empty() {
+      return
+    }
+    let mut cache = self.cache.borrow_mut();
+    for key in keys {
+      cache.values.insert(key, v.clone());
+    }
+  }
+
+  /* Empties the cache, which each evaluation starts without */
+  pub(crate) fn forget(&self) {
+    *self.cache.borrow_mut() = Cache::default();
+  }
+}
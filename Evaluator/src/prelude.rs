@@ -0,0 +1,74 @@
+/* The standard prelude: builtin functions available to every Toi program run by
+ * the toi driver or an Engine::with_prelude().
+ *
+ *   abs(n: number): number                  absolute value
+ *   min(a: number, b: number): number       smaller of a and b
+ *   max(a: number, b: number): number       larger of a and b
+ *   pow(b: number, e: number): number       b to the power e, for e >= 0
+ *   length(s: string): number               number of characters in s
+ *   concat(s: string, t: string): string    s followed by t
+ *   substring(s: string, start: number, len: number): string
+ *                                           the len characters of s from position start
+ *   to_string(n: number): string            decimal digits of n
+ *   parse_number(s: string): number         the number written in s
+ *
+ * The builtins are ordinary entries of the initial typing context and evaluation
+ * environment, so a Toi definition with the same name shadows them. A call that
+ * cannot produce a result (a negative exponent, an overflow, a substring out of
+ * range, or text that is not a number) fails like any other native call.
+ */
+use rpds::HashTrieMap;
+use toi_ast::Type;
+use crate::engine::{Engine, NativeFn};
+use crate::evaluator::EnvRecord;
+
+thread_local! {
+  /* The prelude, built once. Native functions hold Rc closures, so each thread
+   * has its own. */
+  static PRELUDE: Engine = Engine::with_prelude();
+}
+
+pub fn register(engine: &mut Engine) {
+  engine
+    .register_fn("abs", |n: i64| n.checked_abs())
+    .register_fn("min", |a: i64, b: i64| a.min(b))
+    .register_fn("max", |a: i64, b: i64| a.max(b))
+    .register_fn("pow", |b: i64, e: i64| b.checked_pow(u32::try_from(e).ok()?))
+    .register_fn("length", |s: String| s.chars().count() as i64)
+    .register_fn("concat", |s: String, t: String| s + &t)
+    .register_fn("substring", substring)
+    .register_fn("to_string", |n: i64| n.to_string())
+    .register_fn("parse_number", |s: String| s.trim().parse::<i64>().ok());
+}
+
+fn substring(s: String, start: i64, len: i64) -> Option<String> {
+  let start = usize::try_from(start).ok()?;
+  let len = usize::try_from(len).ok()?;
+  if start.checked_add(len)? > s.chars().count() {
+    return None
+  }
+  Some(s.chars().skip(start).take(len).collect())
+}
+
+/* An engine with the prelude, shared with every other one made on this thread */
+pub(crate) fn engine() -> Engine {
+  PRELUDE.with(Engine::clone)
+}
+
+/* The initial typing context Γ, holding the type of every builtin */
+pub fn prelude_context() -> HashTrieMap<String, Type> {
+  PRELUDE.with(|engine| engine.context().clone())
+}
+
+/* The initial evaluation environment E, holding the code of every builtin */
+pub fn prelude_env() -> HashTrieMap<String, EnvRecord> {
+  PRELUDE.with(|engine| engine.environment().clone())
+}
+
+/* The builtin called name, if there is one */
+pub(crate) fn builtin(name: &str) -> Option<NativeFn> {
+  PRELUDE.with(|engine| match engine.environment().get(name) {
+    Some(EnvRecord::NativeRecord(native)) => Some(native.clone()),
+    _ => None,
+  })
+}
@@ -0,0 +1,172 @@
+/* A profiler for Toi programs, which runs a program on the CEK machine while
+ * timing each call of a Toi function (one defined by a let function or let rec;
+ * native functions are part of their caller):
+ *   let (result, profile) = engine.profile(&Evaluator::new(), &program);
+ *   print!("{}", profile.report());   // calls and times, most expensive first
+ *   print!("{}", profile.folded());   // for flamegraph.pl or inferno
+ *
+ * Each function has a number of calls, an inclusive time (from entering its body
+ * to returning, counted once for a function that recurses) and an exclusive time
+ * (the inclusive time without the calls it makes). A call in tail position takes
+ * the place of its caller (see cek.rs), so the caller's time stops there.
+ *
+ * The folded stacks have one line for each chain of calls the program made, from
+ * the top level ("program") to the innermost function, with the nanoseconds spent
+ * in that function alone, e.g. "program;fact;fact 1200".
+ */
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use rpds::HashTrieMap;
+use toi_ast::Expr;
+use toi_typing::typer::CheckedProgram;
+use crate::engine::Engine;
+use crate::evaluator::{EnvRecord, EvalError, Evaluator, Value};
+
+type Env = HashTrieMap<String, EnvRecord>;
+
+/* The name of the frame of the top level of a program in folded stacks */
+const PROGRAM: &str = "program";
+
+/* The calls and time of one function */
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct FunctionProfile {
+  pub name: String,
+  pub calls: u64,
+  pub inclusive: Duration,
+  pub exclusive: Duration,
+}
+
+/* What a profiled evaluation spent its time on */
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct Profile {
+  pub total: Duration,
+  functions: HashMap<String, FunctionProfile>,
+  stacks: HashMap<Vec<String>, Duration>,
+}
+
+/* A call in progress: the function, when it started and how long the calls it
+ * made have taken so far */
+#[derive(Eq, PartialEq, Debug, Clone)]
+struct Open {
+  name: String,
+  start: Instant,
+  children: Duration,
+}
+
+/* The calls in progress in a profiled evaluation, and what the finished ones took */
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub(crate) struct Recorder {
+  open: Vec<Open>,
+  profile: Profile,
+}
+
+impl Recorder {
+  fn new() -> Recorder {
+    Recorder { open: vec![Open { name: PROGRAM.to_string(), start: Instant::now(), children: Duration::ZERO }], profile: Profile::default() }
+  }
+
+  /* A call of the function name starts */
+  pub(crate) fn enter(&mut self, name: &str) {
+    let f = self.profile.functions.entry(name.to_string())
+      .or_insert_with(|| FunctionProfile { name: name.to_string(), calls: 0, inclusive: Duration::ZERO, exclusive: Duration::ZERO });
+    f.calls += 1;
+    self.open.push(Open { name: name.to_string(), start: Instant::now(), children: Duration::ZERO });
+  }
+
+  /* The innermost call returns */
+  pub(crate) fn exit(&mut self) {
+    let Some(call) = self.open.pop() else { return };
+    let elapsed = call.start.elapsed();
+    let exclusive = elapsed.saturating_sub(call.children);
+    let mut stack: Vec<String> = self.open.iter().map(|o| o.name.clone()).collect();
+    let outermost = !stack.contains(&call.name);
+    stack.push(call.name.clone());
+    *self.profile.stacks.entry(stack).or_default() += exclusive;
+    match self.open.last_mut() {
+      Some(caller) => caller.children += elapsed,
+      None => {
+        self.profile.total = elapsed;
+        return
+      },
+    }
+    let f = self.profile.functions.get_mut(&call.name).expect("a function is counted when it is entered");
+    f.exclusive += exclusive;
+    if outermost {
+      f.inclusive += elapsed;
+    }
+  }
+
+  /* The innermost call makes a call in tail position, which takes its place */
+  pub(crate) fn tail(&mut self, name: &str) {
+    self.exit();
+    self.enter(name);
+  }
+
+  /* Ends every call still in progress, as when the program stops with an error,
+   * and the top level */
+  fn finish(mut self) -> Profile {
+    while !self.open.is_empty() {
+      self.exit();
+    }
+    self.profile
+  }
+}
+
+impl Profile {
+  /* The functions that were called, those that took the most time on their own first */
+  pub fn functions(&self) -> Vec<&FunctionProfile> {
+    let mut functions: Vec<&FunctionProfile> = self.functions.values().collect();
+    functions.sort_by(|a, b| b.exclusive.cmp(&a.exclusive).then_with(|| a.name.cmp(&b.name)));
+    functions
+  }
+
+  /* The profile of the function name, if it was called */
+  pub fn function(&self, name: &str) -> Option<&FunctionProfile> {
+    self.functions.get(name)
+  }
+
+  /* A table of the functions, as given by functions, and the total time */
+  pub fn report(&self) -> String {
+    let width = self.functions.keys().map(String::len).max().unwrap_or(0).max("function".len());
+    let mut out = format!("{:<width$} {:>10} {:>12} {:>12}\n", "function", "calls", "inclusive", "exclusive");
+    for f in self.functions() {
+      out.push_str(&format!("{:<width$} {:>10} {:>12} {:>12}\n", f.name, f.calls, format!("{:.3?}", f.inclusive), format!("{:.3?}", f.exclusive)));
+    }
+    out.push_str(&format!("total {:.3?}\n", self.total));
+    out
+  }
+
+  /* The folded stacks, one line per chain of calls, in order */
+  pub fn folded(&self) -> String {
+    let mut lines: Vec<String> = self.stacks.iter()
+      .map(|(stack, time)| format!("{} {}", stack.join(";"), time.as_nanos()))
+      .collect();
+    lines.sort();
+    lines.into_iter().map(|l| l + "\n").collect()
+  }
+}
+
+/* Runs a checked program with the default settings, as eval_program runs it,
+ * giving its profile with its value */
+pub fn profile(p: &CheckedProgram) -> (Result<Value, EvalError>, Profile) {
+  Engine::standard(p).profile(&Evaluator::default(), p)
+}
+
+impl Evaluator {
+  /* Evaluates e in env on the CEK machine, within this evaluator's limits,
+   * recording the calls it makes. The profile covers the calls made up to an
+   * error as well. */
+  pub(crate) fn profile(&self, env: &Env, e: &Expr) -> (Result<Value, EvalError>, Profile) {
+    *self.recorder.borrow_mut() = Some(Recorder::new());
+    let result = self.eval_cek(env, e);
+    let recorder = self.recorder.borrow_mut().take().expect("the recorder is only taken here");
+    (result, recorder.finish())
+  }
+
+  /* Tells the recorder of a profiled evaluation about a call */
+  pub(crate) fn record(&self, event: impl FnOnce(&mut Recorder)) {
+    if let Some(recorder) = self.recorder.borrow_mut().as_mut() {
+      event(recorder);
+    }
+  }
+}
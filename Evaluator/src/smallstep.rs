@@ -0,0 +1,701 @@
+/* A small-step semantics for Toi, which shows how a program reduces one step at
+ * a time rather than just its final value:
+ *   let function sq(x) { x*x } in sq(sq(4))
+ *   → sq(sq(4)) → sq(4*4) → sq(16) → 16*16 → 256
+ *
+ * step(E, e) reduces the leftmost innermost redex of e, in the order eval_expr
+ * evaluates subexpressions, and gives the expression after it, or None if e is
+ * already a value. The values are
+ *   numerals, strings, true and false
+ *   names bound in E to values that have no literal: functions, unit (written
+ *   "()", a name no program can bind) and numbers outside the range of i64
+ * The redexes are
+ *   E(x)                            x → its value in E
+ *   v1 + v2 (and *, -, comparisons)  → the result
+ *   let var x = v in e              → e[x := v]
+ *   let function f(x) {b} in e      → e, with f bound in E
+ *   f(v1,...,vN)                    → b[x1 := v1, ..., xN := vN] where E(f) = ⟨x, b⟩
+ *   f(v1,...,vN)                    → g(v1,...,vN) where E(f) is the native function g
+ *   if v then e1 else e2            → e1 or e2
+ *   assert(v, m)                    → true, or an error once m is a value
+ * Substitution renames any binder that would capture a name it puts in place,
+ * by adding primes (x', x'', ...), so the reductions keep Toi's static scoping.
+ *
+ * Functions are moved out of the expression into E when their let is reduced,
+ * renamed with primes if E already binds their name to something else, so that a
+ * body put in place of a call still means what it did where it was written. E
+ * only ever gains names this way, and step gives the new E with the expression.
+ *
+ * A program reduces to the value eval_expr gives for it, or stops with the same
+ * error at the same span, though the expression an error carries is the one
+ * reduced so far rather than the one written. Finding a step recurses on the Rust
+ * stack into the subexpressions around the redex, so a program also stops with
+ * EvalError::ResourceExhausted once the redex is nested in max_depth of them, or
+ * STACK_DEPTH, as when that many calls are waiting for their values.
+ */
+use std::collections::HashSet;
+use std::rc::Rc;
+use rpds::HashTrieMap;
+use toi_ast::{Defn, Expr, Type};
+use toi_typing::typer::CheckedProgram;
+use crate::engine::Engine;
+use crate::evaluator::{compare_numbers, holds, ArithOp, Closure, EnvRecord, EvalError, Evaluator, Resource, Value};
+
+type Env = HashTrieMap<String, EnvRecord>;
+
+/* The name the unit value is written as */
+pub const UNIT: &str = "()";
+
+/* What a step gives: the environment and expression after it, or None if the
+ * expression was already a value */
+pub type Step = Option<(Env, Expr)>;
+
+/* Reduces a checked program one step with the default settings, giving the
+ * environment and expression after the step, or None if it is a value */
+pub fn step(p: &CheckedProgram) -> Result<Step, EvalError> {
+  Engine::standard(p).step(&Evaluator::default(), p)
+}
+
+/* The expressions a checked program reduces through with the default settings,
+ * run as eval_program runs it */
+pub fn trace(p: &CheckedProgram) -> Trace {
+  Engine::standard(p).trace(&Evaluator::default(), p)
+}
+
+/* An iterator over the expressions a program reduces through: the program
+ * itself, then each one after a step, ending with a value or an error */
+pub struct Trace {
+  evaluator: Evaluator,
+  env: Env,
+  next: Option<Result<Expr, EvalError>>,
+}
+
+impl Trace {
+  /* A trace that stops with err before the first expression */
+  pub(crate) fn failed(err: EvalError) -> Trace {
+    Trace { evaluator: Evaluator::default(), env: Env::new(), next: Some(Err(err)) }
+  }
+
+  /* The environment of the last expression given, with the functions that have
+   * been moved into it */
+  pub fn env(&self) -> &Env {
+    &self.env
+  }
+}
+
+impl Iterator for Trace {
+  type Item = Result<Expr, EvalError>;
+
+  fn next(&mut self) -> Option<Result<Expr, EvalError>> {
+    let current = self.next.take()?;
+    if let Ok(e) = &current {
+      self.next = match self.evaluator.small_step(&self.env, e) {
+        Ok(Some((env, next))) => {
+          self.env = env;
+          Some(Ok(next))
+        },
+        Ok(None) => None,
+        Err(err) => Some(Err(err)),
+      };
+    }
+    Some(current)
+  }
+}
+
+/* The literal for v, if it has one */
+fn literal(v: &Value) -> Option<Expr> {
+  match v {
+    Value::Numeral(n) => Some(Expr::Numeral(*n)),
+    Value::String(s) => Some(Expr::StringLiteral(s.clone())),
+    Value::Boolean(true) => Some(Expr::True),
+    Value::Boolean(false) => Some(Expr::False),
+    _ => None,
+  }
+}
+
+/* The value e stands for, if e is a value */
+fn value(env: &Env, e: &Expr) -> Option<Value> {
+  match e {
+    Expr::Numeral(n) => Some(Value::Numeral(*n)),
+    Expr::StringLiteral(s) => Some(Value::String(s.clone())),
+    Expr::True => Some(Value::Boolean(true)),
+    Expr::False => Some(Value::Boolean(false)),
+    Expr::Id(x) if x == UNIT => Some(Value::Unit),
+    Expr::Id(x) => env.get(x).map(EnvRecord::value).filter(|v| literal(v).is_none()),
+    Expr::Spanned(_, inner) => value(env, inner),
+    _ => None,
+  }
+}
+
+/* e without the Spanned wrappers around it */
+fn unspanned(e: &Expr) -> &Expr {
+  match e {
+    Expr::Spanned(_, inner) => unspanned(inner),
+    e => e,
+  }
+}
+
+/* Whether two records hold the same value. Closures are the same when they are
+ * the same function of the same group, which was defined in one environment. */
+fn same(a: &EnvRecord, b: &EnvRecord) -> bool {
+  match (a.value(), b.value()) {
+    (Value::Closure(c1), Value::Closure(c2)) => Rc::ptr_eq(&c1.group, &c2.group) && c1.index == c2.index,
+    (v1, v2) => v1 == v2,
+  }
+}
+
+/* x with primes added until it is not taken */
+fn fresh(x: &str, taken: impl Fn(&str) -> bool) -> String {
+  let mut name = x.to_string();
+  while taken(&name) {
+    name.push('\'');
+  }
+  name
+}
+
+/* An expression for the value v: its literal, or a name bound to it in env,
+ * which is added to env if need be under a name that is not in avoid */
+fn express(env: &Env, v: Value, avoid: &HashSet<String>) -> (Env, Expr) {
+  if let Some(e) = literal(&v) {
+    return (env.clone(), e)
+  }
+  let (base, record) = match v {
+    Value::Unit => return (env.clone(), Expr::Id(UNIT.to_string())),
+    Value::Closure(c) => (c.name().to_string(), EnvRecord::FunRecord(c)),
+    Value::Native(n) => (n.name().to_string(), EnvRecord::NativeRecord(n)),
+    v => (v.to_string(), EnvRecord::VarRecord(v)),
+  };
+  if let Some(existing) = env.get(&base) {
+    if same(existing, &record) {
+      return (env.clone(), Expr::Id(base))
+    }
+  }
+  let name = fresh(&base, |x| env.contains_key(x) || avoid.contains(x));
+  (env.insert(name.clone(), record), Expr::Id(name))
+}
+
+/* Every name that occurs in e, bound or free */
+fn names(e: &Expr, out: &mut HashSet<String>) {
+  match e {
+    Expr::Id(x) => {
+      out.insert(x.clone());
+    },
+    Expr::Numeral(_) | Expr::Decimal(_, _) | Expr::StringLiteral(_) | Expr::True | Expr::False => {},
+    Expr::Compare(l, _, r) | Expr::Times(l, r) | Expr::Plus(l, r) | Expr::Minus(l, r) | Expr::Assert(l, r) => {
+      names(l, out);
+      names(r, out);
+    },
+    Expr::Let(d, body) => {
+      match &**d {
+        Defn::VarDefn(x, v) => {
+          out.insert(x.clone());
+          names(v, out);
+        },
+        Defn::FunDefn(f, params, _, b) => {
+          out.insert(f.clone());
+          out.extend(params.iter().map(|(x, _)| x.clone()));
+          names(b, out);
+        },
+        Defn::RecDefn(fs) => for (f, params, _, b) in fs {
+          out.insert(f.clone());
+          out.extend(params.iter().map(|(x, _)| x.clone()));
+          names(b, out);
+        },
+      }
+      names(body, out);
+    },
+    Expr::Call(f, args) => {
+      out.insert(f.clone());
+      args.iter().for_each(|a| names(a, out));
+    },
+    Expr::If(c, t, f) => {
+      names(c, out);
+      names(t, out);
+      names(f, out);
+    },
+    Expr::Spanned(_, inner) => names(inner, out),
+  }
+}
+
+/* The names that occur free in e, as values or as functions called */
+fn free(e: &Expr, bound: &mut Vec<String>, out: &mut HashSet<String>) {
+  match e {
+    Expr::Id(x) if !bound.contains(x) => {
+      out.insert(x.clone());
+    },
+    Expr::Id(_) | Expr::Numeral(_) | Expr::Decimal(_, _) | Expr::StringLiteral(_) | Expr::True | Expr::False => {},
+    Expr::Compare(l, _, r) | Expr::Times(l, r) | Expr::Plus(l, r) | Expr::Minus(l, r) | Expr::Assert(l, r) => {
+      free(l, bound, out);
+      free(r, bound, out);
+    },
+    Expr::Let(d, body) => {
+      let outer = bound.len();
+      match &**d {
+        Defn::VarDefn(x, v) => {
+          free(v, bound, out);
+          bound.push(x.clone());
+        },
+        Defn::FunDefn(..) | Defn::RecDefn(..) => {
+          let group = group_of(d);
+          bound.extend(group.iter().map(|(f, _, _, _)| f.clone()));
+          for (_, params, _, b) in &group {
+            let inner = bound.len();
+            bound.extend(params.iter().map(|(x, _)| x.clone()));
+            free(b, bound, out);
+            bound.truncate(inner);
+          }
+        },
+      }
+      free(body, bound, out);
+      bound.truncate(outer);
+    },
+    Expr::Call(f, args) => {
+      if !bound.contains(f) {
+        out.insert(f.clone());
+      }
+      args.iter().for_each(|a| free(a, bound, out));
+    },
+    Expr::If(c, t, f) => {
+      free(c, bound, out);
+      free(t, bound, out);
+      free(f, bound, out);
+    },
+    Expr::Spanned(_, inner) => free(inner, bound, out),
+  }
+}
+
+fn free_names(e: &Expr) -> HashSet<String> {
+  let mut out = HashSet::new();
+  free(e, &mut vec![], &mut out);
+  out
+}
+
+type Group = Vec<(String, Vec<(String, Type)>, Type, Expr)>;
+
+/* The functions a let fun or let rec defines */
+fn group_of(d: &Defn) -> Group {
+  match d {
+    Defn::FunDefn(f, params, t, b) => vec![(f.clone(), params.clone(), t.clone(), (**b).clone())],
+    Defn::RecDefn(fs) => fs.iter().map(|(f, params, t, b)| (f.clone(), params.clone(), t.clone(), (**b).clone())).collect(),
+    Defn::VarDefn(_, _) => unreachable!("a var defines no functions"),
+  }
+}
+
+/* The definition of group, as the same kind of definition as d */
+fn defn_like(d: &Defn, mut group: Group) -> Defn {
+  match d {
+    Defn::FunDefn(..) => {
+      let (f, params, t, b) = group.remove(0);
+      Defn::FunDefn(f, params, t, Box::new(b))
+    },
+    _ => Defn::RecDefn(group.into_iter().map(|(f, params, t, b)| (f, params, t, Box::new(b))).collect()),
+  }
+}
+
+/* A substitution of the value v for the name x. Calls of x become calls of
+ * callee, the name v is bound to when v is a function. */
+struct Subst<'a> {
+  x: &'a str,
+  v: &'a Expr,
+  callee: &'a str,
+}
+
+impl Subst<'_> {
+  /* Whether binding y would capture a name the substitution puts in place */
+  fn captures(&self, y: &str) -> bool {
+    y == self.callee || matches!(self.v, Expr::Id(g) if g == y)
+  }
+
+  fn taken(&self, e: &Expr) -> HashSet<String> {
+    let mut taken = HashSet::new();
+    names(e, &mut taken);
+    names(self.v, &mut taken);
+    taken.insert(self.callee.to_string());
+    taken.insert(self.x.to_string());
+    taken
+  }
+
+  fn apply(&self, e: &Expr) -> Expr {
+    let sub = |e: &Expr| Box::new(self.apply(e));
+    match e {
+      Expr::Id(y) if y == self.x => self.v.clone(),
+      Expr::Id(_) | Expr::Numeral(_) | Expr::Decimal(_, _) | Expr::StringLiteral(_) | Expr::True | Expr::False => e.clone(),
+      Expr::Compare(l, c, r) => Expr::Compare(sub(l), c.clone(), sub(r)),
+      Expr::Times(l, r) => Expr::Times(sub(l), sub(r)),
+      Expr::Plus(l, r) => Expr::Plus(sub(l), sub(r)),
+      Expr::Minus(l, r) => Expr::Minus(sub(l), sub(r)),
+      Expr::Assert(c, m) => Expr::Assert(sub(c), sub(m)),
+      Expr::If(c, t, f) => Expr::If(sub(c), sub(t), sub(f)),
+      Expr::Call(f, args) => {
+        let f = if f == self.x { self.callee.to_string() } else { f.clone() };
+        Expr::Call(f, args.iter().map(|a| self.apply(a)).collect())
+      },
+      Expr::Spanned(s, inner) => Expr::Spanned(*s, sub(inner)),
+      Expr::Let(d, body) => match &**d {
+        Defn::VarDefn(y, v) => {
+          let v = sub(v);
+          if y == self.x {
+            return Expr::Let(Box::new(Defn::VarDefn(y.clone(), v)), body.clone())
+          }
+          let (y, body) = self.avoid_capture(y, body, e);
+          Expr::Let(Box::new(Defn::VarDefn(y, v)), sub(&body))
+        },
+        _ => {
+          let mut group = group_of(d);
+          let mut body = (**body).clone();
+          if group.iter().any(|(f, _, _, _)| f == self.x) {
+            return e.clone()
+          }
+          // Rename the functions, then the parameters, that would capture
+          let x_free = free_names(&body).contains(self.x)
+            || group.iter().any(|(_, params, _, b)| !params.iter().any(|(p, _)| p == self.x) && free_names(b).contains(self.x));
+          let taken = self.taken(e);
+          let targets: Vec<String> = group.iter()
+            .map(|(f, _, _, _)| if x_free && self.captures(f) { fresh(f, |y| taken.contains(y)) } else { f.clone() })
+            .collect();
+          (group, body) = rename_group(group, body, &targets);
+          for (_, params, _, b) in group.iter_mut() {
+            if params.iter().any(|(p, _)| p == self.x) {
+              continue
+            }
+            for p in params.iter_mut() {
+              if self.captures(&p.0) && free_names(b).contains(self.x) {
+                let q = fresh(&p.0, |y| taken.contains(y));
+                *b = Subst { x: &p.0, v: &Expr::Id(q.clone()), callee: &q }.apply(b);
+                p.0 = q;
+              }
+            }
+            *b = self.apply(b);
+          }
+          Expr::Let(Box::new(defn_like(d, group)), sub(&body))
+        },
+      },
+    }
+  }
+
+  /* Renames the binder y of body if it would capture a name put in place */
+  fn avoid_capture(&self, y: &str, body: &Expr, whole: &Expr) -> (String, Expr) {
+    if !self.captures(y) || !free_names(body).contains(self.x) {
+      return (y.to_string(), body.clone())
+    }
+    let taken = self.taken(whole);
+    let z = fresh(y, |n| taken.contains(n));
+    let renamed = Subst { x: y, v: &Expr::Id(z.clone()), callee: &z }.apply(body);
+    (z, renamed)
+  }
+}
+
+/* The operand of an expression: a value of the type it must have, or the
+ * operand after a step */
+enum Operand {
+  Value(Value),
+  Step(Env, Expr),
+}
+
+/* The operands of a binary operator: their values, or the operator after one
+ * of them takes a step */
+enum Operands {
+  Values(Value, Value),
+  Step(Env, Expr),
+}
+
+impl Evaluator {
+  /* Reduces e one step in env, under this evaluator's arithmetic policy */
+  pub(crate) fn small_step(&self, env: &Env, e: &Expr) -> Result<Step, EvalError> {
+    if value(env, e).is_some() {
+      return Ok(None)
+    }
+    // Names free in the whole expression must not be bound by moving a
+    // function into the environment
+    let avoid = free_names(e);
+    self.depth.set(0);
+    self.reduce(env, e, &avoid).map(Some)
+  }
+
+  /* The expressions e reduces through in env, under this evaluator's arithmetic policy */
+  pub(crate) fn trace(&self, env: &Env, e: &Expr) -> Trace {
+    Trace { evaluator: self.clone(), env: env.clone(), next: Some(Ok(e.clone())) }
+  }
+
+  /* o as a value of type t, or o after a step */
+  fn operand(&self, env: &Env, o: &Expr, t: Type, avoid: &HashSet<String>) -> Result<Operand, EvalError> {
+    match value(env, o) {
+      Some(v @ (Value::Numeral(_) | Value::BigNumeral(_))) if t == Type::Number => Ok(Operand::Value(v)),
+      Some(v @ Value::Boolean(_)) if t == Type::Boolean => Ok(Operand::Value(v)),
+      Some(_) => Err(EvalError::TypeMismatch(t, o.clone())),
+      None => {
+        let (env, o) = self.reduce(env, o, avoid)?;
+        Ok(Operand::Step(env, o))
+      },
+    }
+  }
+
+  /* The numbers l and r, or the operator (rebuilt with make) after one of them
+   * takes a step */
+  fn binary(&self, env: &Env, l: &Expr, r: &Expr, make: impl Fn(Expr, Expr) -> Expr, avoid: &HashSet<String>) -> Result<Operands, EvalError> {
+    let l_val = match self.operand(env, l, Type::Number, avoid)? {
+      Operand::Step(env, l) => return Ok(Operands::Step(env, make(l, r.clone()))),
+      Operand::Value(v) => v,
+    };
+    match self.operand(env, r, Type::Number, avoid)? {
+      Operand::Step(env, r) => Ok(Operands::Step(env, make(l.clone(), r))),
+      Operand::Value(r_val) => Ok(Operands::Values(l_val, r_val)),
+    }
+  }
+
+  /* One step of e, which is not a value. The step is found by recursion into
+   * the subexpression it takes place in, which must be nested in no more than
+   * stack_depth others (spans aside) so that the recursion fits on the stack. */
+  fn reduce(&self, env: &Env, e: &Expr, avoid: &HashSet<String>) -> Result<(Env, Expr), EvalError> {
+    if let Expr::Spanned(s, inner) = e {
+      return match self.reduce(env, inner, avoid) {
+        Ok((env, inner)) => Ok((env, Expr::Spanned(*s, Box::new(inner)))),
+        Err(err) => Err(err.at(*s)),
+      }
+    }
+    let depth = self.depth.get();
+    if depth >= self.stack_depth() {
+      return Err(EvalError::ResourceExhausted(Resource::Depth(self.stack_depth())))
+    }
+    self.depth.set(depth + 1);
+    let result = self.contract(env, e, avoid);
+    self.depth.set(depth);
+    result
+  }
+
+  /* One step of e, which is neither a value nor spanned */
+  fn contract(&self, env: &Env, e: &Expr, avoid: &HashSet<String>) -> Result<(Env, Expr), EvalError> {
+    match e {
+      Expr::Id(x) => match env.get(x) {
+        Some(env_record) => Ok(express(env, env_record.value(), avoid)),
+        None => Err(EvalError::UnboundVariable(x.clone(), e.clone())),
+      },
+      Expr::Numeral(_) | Expr::StringLiteral(_) | Expr::True | Expr::False => unreachable!("literals are values"),
+      Expr::Decimal(_, _) => Err(EvalError::FractionalNumeral(e.clone())),
+      Expr::Compare(l, c, r) => {
+        let make = |l, r| Expr::Compare(Box::new(l), c.clone(), Box::new(r));
+        match self.binary(env, l, r, make, avoid)? {
+          Operands::Step(env, e) => Ok((env, e)),
+          Operands::Values(l_val, r_val) => Ok(express(env, Value::Boolean(holds(c, compare_numbers(&l_val, &r_val))), avoid)),
+        }
+      },
+      Expr::Times(l, r) | Expr::Plus(l, r) | Expr::Minus(l, r) => {
+        let op = match e {
+          Expr::Times(_, _) => ArithOp::Times,
+          Expr::Plus(_, _) => ArithOp::Plus,
+          _ => ArithOp::Minus,
+        };
+        let make = |l, r| match op {
+          ArithOp::Times => Expr::Times(Box::new(l), Box::new(r)),
+          ArithOp::Plus => Expr::Plus(Box::new(l), Box::new(r)),
+          ArithOp::Minus => Expr::Minus(Box::new(l), Box::new(r)),
+        };
+        match self.binary(env, l, r, make, avoid)? {
+          Operands::Step(env, e) => Ok((env, e)),
+          Operands::Values(l_val, r_val) => match self.arith.apply(op, &l_val, &r_val) {
+            Some(v) => Ok(express(env, v, avoid)),
+            None => Err(EvalError::IntegerOverflow(op.symbol().to_string(), e.clone())),
+          },
+        }
+      },
+      Expr::Let(d, body) => match &**d {
+        Defn::VarDefn(x, v) => match value(env, v) {
+          Some(_) => Ok(bind(env, &[(x.clone(), unspanned(v).clone())], body, avoid)),
+          None => {
+            let (env, v) = self.reduce(env, v, avoid)?;
+            Ok((env, Expr::Let(Box::new(Defn::VarDefn(x.clone(), Box::new(v))), body.clone())))
+          },
+        },
+        _ => Ok(define(env, d, body, avoid)),
+      },
+      Expr::Call(f, args) => {
+        let callee = match env.get(f) {
+          Some(env_record) => env_record.value(),
+          None => return Err(EvalError::UnboundVariable(f.clone(), e.clone())),
+        };
+        match &callee {
+          Value::Closure(c) if c.params().len() != args.len() =>
+            return Err(EvalError::ArityMismatch(f.clone(), c.params().to_vec(), e.clone())),
+          Value::Native(n) if n.arity() != args.len() =>
+            return Err(EvalError::ArityMismatch(f.clone(), n.params().iter().map(Type::to_string).collect(), e.clone())),
+          Value::Closure(_) | Value::Native(_) => {},
+          _ => return Err(EvalError::NotAFunction(f.clone(), e.clone())),
+        }
+        if let Some(i) = args.iter().position(|a| value(env, a).is_none()) {
+          let (env, a) = self.reduce(env, &args[i], avoid)?;
+          let mut args = args.clone();
+          args[i] = a;
+          return Ok((env, Expr::Call(f.clone(), args)))
+        }
+        match callee {
+          Value::Closure(c) => Ok(instantiate(env, &c, args, avoid)),
+          Value::Native(n) => {
+            let vals: Vec<Value> = args.iter().map(|a| value(env, a).expect("the arguments are values")).collect();
+            match n.call(&vals) {
+              Some(v) => Ok(express(env, v, avoid)),
+              None => Err(EvalError::NativeCallFailed(f.clone(), e.clone())),
+            }
+          },
+          _ => unreachable!("the callee was checked to be a function"),
+        }
+      },
+      Expr::Assert(c, m) => match self.operand(env, c, Type::Boolean, avoid)? {
+        Operand::Step(env, c) => Ok((env, Expr::Assert(Box::new(c), m.clone()))),
+        Operand::Value(Value::Boolean(true)) => Ok((env.clone(), Expr::True)),
+        Operand::Value(_) => match value(env, m) {
+          Some(Value::String(message)) => Err(EvalError::AssertionFailed(message)),
+          Some(v) => Err(EvalError::AssertionFailed(v.to_string())),
+          None => {
+            let (env, m) = self.reduce(env, m, avoid)?;
+            Ok((env, Expr::Assert(c.clone(), Box::new(m))))
+          },
+        },
+      },
+      Expr::If(c, t, f) => match self.operand(env, c, Type::Boolean, avoid)? {
+        Operand::Step(env, c) => Ok((env, Expr::If(Box::new(c), t.clone(), f.clone()))),
+        Operand::Value(Value::Boolean(true)) => Ok((env.clone(), (**t).clone())),
+        Operand::Value(_) => Ok((env.clone(), (**f).clone())),
+      },
+      Expr::Spanned(_, _) => unreachable!("spans are taken off in reduce"),
+    }
+  }
+}
+
+/* body with each name of subs replaced by its value all at once. A value with
+ * no name to call it by is bound in env when body calls it, so that the call
+ * fails as it should. */
+fn bind(env: &Env, subs: &[(String, Expr)], body: &Expr, avoid: &HashSet<String>) -> (Env, Expr) {
+  let mut env = env.clone();
+  let mut taken = HashSet::new();
+  names(body, &mut taken);
+  subs.iter().for_each(|(_, v)| names(v, &mut taken));
+  taken.extend(avoid.iter().cloned());
+  // First give each name a fresh one, so that no value put in place is replaced again
+  let mut body = body.clone();
+  let mut renamed = vec![];
+  for (x, v) in subs {
+    let y = fresh(x, |n| taken.contains(n) || env.contains_key(n));
+    taken.insert(y.clone());
+    body = Subst { x, v: &Expr::Id(y.clone()), callee: &y }.apply(&body);
+    renamed.push((y, v));
+  }
+  for (y, v) in renamed {
+    let callee = match v {
+      Expr::Id(g) => g,
+      _ => &y,
+    };
+    body = Subst { x: &y, v, callee }.apply(&body);
+    // y is still called only if v is not a function, and such a call must fail
+    if callee == &y && free_names(&body).contains(&y) {
+      if let Some(val) = value(&env, v) {
+        env = env.insert(y.clone(), EnvRecord::VarRecord(val));
+      }
+    }
+  }
+  (env, body)
+}
+
+/* The functions of group renamed to targets, in their bodies (where their
+ * parameters do not hide them) and in body */
+fn rename_group(mut group: Group, mut body: Expr, targets: &[String]) -> (Group, Expr) {
+  for (i, g) in targets.iter().enumerate() {
+    let f = group[i].0.clone();
+    if f == *g {
+      continue
+    }
+    let rename = Subst { x: &f, v: &Expr::Id(g.clone()), callee: g };
+    for entry in group.iter_mut() {
+      if !entry.1.iter().any(|(x, _)| *x == f) {
+        entry.3 = rename.apply(&entry.3);
+      }
+    }
+    body = rename.apply(&body);
+    group[i].0 = g.clone();
+  }
+  (group, body)
+}
+
+/* The name, parameter names and body of each function of group, as in Closure */
+fn source(group: &Group) -> Vec<(String, Vec<String>, Expr)> {
+  group.iter().map(|(f, params, _, b)| (f.clone(), params.iter().map(|(x, _)| x.clone()).collect(), b.clone())).collect()
+}
+
+/* Moves the functions of d into env and gives body, renaming any of them that
+ * env already binds to something else */
+fn define(env: &Env, d: &Defn, body: &Expr, avoid: &HashSet<String>) -> (Env, Expr) {
+  let group = group_of(d);
+  // A function defined again with the same text, as in each call of a function
+  // with a local function, is the one already moved into env (perhaps renamed)
+  let earlier: Option<Vec<String>> = group.iter().enumerate().map(|(i, (f, _, _, _))| {
+    let mut name = f.clone();
+    loop {
+      match env.get(&name) {
+        Some(EnvRecord::FunRecord(c)) if c.index == i && c.group.len() == group.len() => return Some(name),
+        Some(_) => name.push('\''),
+        None => return None,
+      }
+    }
+  }).collect();
+  if let Some(targets) = earlier {
+    let (renamed, body) = rename_group(group.clone(), body.clone(), &targets);
+    let closures: Vec<&Closure> = targets.iter().map(|g| match env.get(g) {
+      Some(EnvRecord::FunRecord(c)) => c,
+      _ => unreachable!("the targets are functions"),
+    }).collect();
+    if closures.iter().all(|c| Rc::ptr_eq(&c.group, &closures[0].group)) && *closures[0].group == source(&renamed) {
+      return (env.clone(), body)
+    }
+  }
+  let mut taken = HashSet::new();
+  names(body, &mut taken);
+  group.iter().for_each(|(_, _, _, b)| names(b, &mut taken));
+  let mut targets = vec![];
+  for (f, _, _, _) in &group {
+    let g = if env.contains_key(f) || avoid.contains(f) {
+      fresh(f, |n| taken.contains(n) || env.contains_key(n) || avoid.contains(n))
+    } else {
+      f.clone()
+    };
+    taken.insert(g.clone());
+    targets.push(g);
+  }
+  let (group, body) = rename_group(group, body.clone(), &targets);
+  let env = Closure::group(env, source(&group)).into_iter()
+    .fold(env.clone(), |env, c| env.insert(c.name().to_string(), EnvRecord::FunRecord(c)));
+  (env, body)
+}
+
+/* The body of c with its parameters replaced by args. Names the body uses that
+ * mean something else in env than where c was defined, which can only happen
+ * for functions env was given with, are replaced by what they meant there. */
+fn instantiate(env: &Env, c: &Closure, args: &[Expr], avoid: &HashSet<String>) -> (Env, Expr) {
+  let mut env = env.clone();
+  let params = c.params();
+  let mut subs: Vec<(String, Expr)> = params.iter().cloned().zip(args.iter().map(|a| unspanned(a).clone())).collect();
+  let body = c.body();
+  let siblings: Vec<String> = c.group.iter().map(|(f, _, _)| f.clone()).collect();
+  for x in free_names(body) {
+    if params.contains(&x) {
+      continue
+    }
+    let meant = match siblings.iter().rposition(|f| *f == x) {
+      Some(j) => Some(EnvRecord::FunRecord(Closure { group: c.group.clone(), index: j, env: c.env.clone() })),
+      None => c.env.get(&x).cloned(),
+    };
+    match (meant, env.get(&x)) {
+      (Some(m), Some(now)) if same(&m, now) => {},
+      (Some(m), _) => {
+        let (next, v) = express(&env, m.value(), avoid);
+        env = next;
+        subs.push((x, v));
+      },
+      // Unbound where c was defined, so it must stay unbound
+      (None, Some(_)) => {
+        let y = fresh(&x, |n| env.contains_key(n) || avoid.contains(n));
+        subs.push((x, Expr::Id(y)));
+      },
+      (None, None) => {},
+    }
+  }
+  bind(&env, &subs, body, avoid)
+}
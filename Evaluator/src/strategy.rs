@@ -0,0 +1,144 @@
+/* Evaluation strategies: when the arguments of a call of a Toi function are
+ * evaluated.
+ *   ByValue  before the body runs, as in the rules in the header of evaluator.rs
+ *   ByName   each time the body uses the parameter, in the environment of the call
+ *   ByNeed   the first time the body uses the parameter, after which its value is
+ *            kept for every later use (lazy evaluation)
+ *
+ *   engine.run_with(&Evaluator::new().with_strategy(Strategy::ByNeed), &program)
+ *
+ * Toi has no side effects, so the strategies only differ on arguments that are
+ * not used, or used more than once. An argument that the body never uses is never
+ * evaluated by name or by need, so a call can give a value where by value it
+ * would stop with the argument's error, or never finish. An argument used many
+ * times is evaluated that many times by name, and once by need.
+ *
+ * Under ByName and ByNeed a parameter is bound to a Value::Thunk holding the
+ * argument's expression and the environment of the call, which is forced (turned
+ * into the argument's value) when the parameter is used: as a name, or called as
+ * a function. Arguments of native functions, let var definitions and everything
+ * else are evaluated as before, so a thunk never is the value of an expression.
+ *
+ * eval_expr and the CEK machine follow the strategy; the other evaluators always
+ * evaluate by value.
+ */
+use std::cell::RefCell;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use std::str::FromStr;
+use rpds::HashTrieMap;
+use serde::{Deserialize, Serialize};
+use toi_ast::Expr;
+use crate::evaluator::{EnvRecord, EvalError, Evaluator, Value};
+
+type Env = HashTrieMap<String, EnvRecord>;
+
+/* When the arguments of a call are evaluated */
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum Strategy {
+  #[default]
+  ByValue,
+  ByName,
+  ByNeed,
+}
+
+impl FromStr for Strategy {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Strategy, String> {
+    match s {
+      "value" => Ok(Strategy::ByValue),
+      "name" => Ok(Strategy::ByName),
+      "need" => Ok(Strategy::ByNeed),
+      _ => Err(format!("unknown evaluation strategy '{}' (expected value, name or need)", s)),
+    }
+  }
+}
+
+/* An argument not evaluated yet: its expression and the environment of the
+ * call. The thunk of a call by need has a place for its value, which its copies
+ * share, so that it is evaluated at most once. */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Thunk {
+  pub expr: Expr,
+  pub env: Env,
+  pub value: Option<Rc<RefCell<Option<Value>>>>,
+}
+
+impl Thunk {
+  /* The thunk of the argument expr of a call in env, under strategy */
+  pub fn new(expr: &Expr, env: &Env, strategy: Strategy) -> Thunk {
+    let value = match strategy {
+      Strategy::ByNeed => Some(Rc::new(RefCell::new(None))),
+      _ => None,
+    };
+    Thunk { expr: expr.clone(), env: env.clone(), value }
+  }
+
+  /* The value of a thunk by need that has been forced */
+  pub fn forced(&self) -> Option<Value> {
+    self.value.as_ref().and_then(|v| v.borrow().clone())
+  }
+
+  /* Keeps v as the value of a thunk by need */
+  pub(crate) fn keep(&self, v: &Value) {
+    if let Some(value) = &self.value {
+      *value.borrow_mut() = Some(v.clone());
+    }
+  }
+}
+
+/* Thunks are the same argument when they have the same expression and
+ * environment, whether or not either has been forced */
+impl PartialEq for Thunk {
+  fn eq(&self, other: &Thunk) -> bool {
+    self.expr == other.expr && self.env == other.env
+  }
+}
+
+impl Eq for Thunk {}
+
+/* Environments cannot be hashed, so a thunk hashes as its expression alone */
+impl Hash for Thunk {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.expr.hash(state);
+  }
+}
+
+impl fmt::Display for Thunk {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.forced() {
+      Some(v) => write!(f, "{}", v),
+      None => write!(f, "<thunk>"),
+    }
+  }
+}
+
+impl Evaluator {
+  pub fn with_strategy(mut self, strategy: Strategy) -> Evaluator {
+    self.strategy = strategy;
+    self
+  }
+
+  /* The values of the arguments a of a call of a Toi function in env, which are
+   * thunks unless the strategy is ByValue, or None under ByValue */
+  pub(crate) fn delay(&self, env: &Env, a: &[Expr]) -> Option<Vec<Value>> {
+    match self.strategy {
+      Strategy::ByValue => None,
+      strategy => Some(a.iter().map(|ai| Value::Thunk(Thunk::new(ai, env, strategy))).collect()),
+    }
+  }
+
+  /* v, with a thunk replaced by the value of its argument, which eval_expr
+   * evaluates as part of the current evaluation (the CEK machine forces thunks
+   * on its own stack) */
+  pub(crate) fn force(&self, v: Value) -> Result<Value, EvalError> {
+    let Value::Thunk(t) = v else { return Ok(v) };
+    if let Some(v) = t.forced() {
+      return Ok(v)
+    }
+    let v = self.eval(&t.env, &t.expr)?;
+    t.keep(&v);
+    Ok(v)
+  }
+}
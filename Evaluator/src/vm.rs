@@ -0,0 +1,658 @@
+/* A bytecode compiler and stack virtual machine for Toi.
+ *
+ * compile translates an expression into a Program: one list of instructions per
+ * function, in which every variable has already been resolved to where its value
+ * will be at run time, so running it never looks a name up in a HashTrieMap:
+ *   Local(i)    slot i of the current call (a parameter or a let var or fun)
+ *   Capture(i)  value i captured by the current closure when it was made
+ *   Sibling(j)  function j of the current closure's group, which may call itself
+ *               and the rest of its group
+ *   Const(i)    a name of the environment compile was given, looked up once
+ * A name that is bound nowhere compiles to an instruction that raises
+ * UnboundVariable when (and only if) it runs, as eval_expr would.
+ *
+ * A call pushes the callee and its arguments on the stack, and the arguments
+ * become the first slots of the callee. A closure captures the value of every
+ * local name in scope where it is defined, so that it can be turned back into the
+ * Closure eval_expr would give for it (see to_value).
+ *
+ * The machine gives the same value or error, at the same span, as eval_expr under
+ * the same arithmetic policy, and makes proper tail calls in the same places. Of
+ * the Limits it keeps max_depth as eval_expr does and counts each instruction as
+ * one step; it has no environments, so max_env_size does not apply.
+ */
+use rpds::HashTrieMap;
+use std::collections::HashMap;
+use std::rc::Rc;
+use toi_ast::{Comparison, Defn, Expr, Span, Type};
+use toi_typing::typer::CheckedProgram;
+use crate::engine::Engine;
+use crate::evaluator::{compare_numbers, holds, ArithOp, Closure, EnvRecord, EvalError, Evaluator, Resource, Value};
+
+type Env = HashTrieMap<String, EnvRecord>;
+
+/* The name, parameter names and body of each function of a group, as in Closure */
+type Source = Vec<(String, Vec<String>, Expr)>;
+
+/* A value on the machine's stack: a Value, or a closure compiled to bytecode.
+ * Closures of the environment given to compile are compiled along with the
+ * program, so a Value is never itself a Closure. */
+#[derive(Debug, Clone)]
+enum Slot {
+  Value(Value),
+  Closure(Rc<VmClosure>),
+}
+
+#[derive(Debug)]
+struct VmClosure {
+  group: usize,
+  index: usize,
+  captures: Rc<Vec<Slot>>,
+}
+
+/* Where a name resolves to, in the function being compiled */
+#[derive(Debug, Clone, Copy)]
+enum Place {
+  Local(usize),
+  Capture(usize),
+  Sibling(usize),
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+  Numeral(i64),
+  Boolean(bool),
+  Const(usize),
+  Local(usize),
+  Capture(usize),
+  Sibling(usize),
+  /* Raises UnboundVariable for the Id or Call expression i */
+  Unbound(usize),
+  /* Raises FractionalNumeral for the Decimal expression i */
+  Fractional(usize),
+  /* Pops a value into a slot */
+  SetLocal(usize),
+  /* Makes the closures of group g, storing them in the slots from start on */
+  MakeGroup(usize, usize),
+  /* Checks that the value on top is a number, before the second operand of
+   * expression i runs */
+  ExpectNumber(usize),
+  Arith(ArithOp, usize),
+  Compare(Comparison, usize),
+  Jump(usize),
+  /* Pops a boolean, and jumps if it is false */
+  JumpUnless(usize, usize),
+  /* Pops the message of a failed assert */
+  Fail,
+  /* Checks that the callee on top can be called with n arguments, before the
+   * arguments of call i run */
+  Callee(usize, usize),
+  Call(usize, usize),
+  TailCall(usize, usize),
+  Return,
+}
+
+#[derive(Debug)]
+struct Function {
+  params: Vec<String>,
+  slots: usize,
+  code: Vec<Op>,
+  /* The innermost span around the expression each instruction came from */
+  spans: Vec<Option<Span>>,
+}
+
+/* The functions defined together by one let fun or let rec (or one closure of
+ * the environment given to compile). source and env are what eval_expr would
+ * make the group's closures from, and captured names the values a closure
+ * captures, with whether each was bound as a function. */
+#[derive(Debug)]
+struct Group {
+  source: Rc<Source>,
+  env: Env,
+  captured: Vec<(String, bool)>,
+  places: Vec<Place>,
+  functions: Vec<usize>,
+}
+
+/* A compiled expression, which can be run any number of times */
+#[derive(Debug)]
+pub struct Program {
+  main: usize,
+  functions: Vec<Function>,
+  groups: Vec<Group>,
+  consts: Vec<Slot>,
+  /* The expressions errors are reported with */
+  exprs: Vec<Expr>,
+}
+
+/* The names in scope in the function being compiled */
+struct Scope {
+  env: Env,
+  captured: Vec<(String, bool)>,
+  siblings: Vec<String>,
+  locals: Vec<(String, bool)>,
+  slots: usize,
+  code: Vec<Op>,
+  spans: Vec<Option<Span>>,
+  span: Option<Span>,
+}
+
+impl Scope {
+  fn new(env: &Env, captured: Vec<(String, bool)>, siblings: Vec<String>, params: &[String]) -> Scope {
+    let locals: Vec<(String, bool)> = params.iter().map(|x| (x.clone(), false)).collect();
+    Scope { env: env.clone(), captured, siblings, slots: locals.len(), locals, code: vec![], spans: vec![], span: None }
+  }
+
+  fn emit(&mut self, op: Op) -> usize {
+    self.code.push(op);
+    self.spans.push(self.span);
+    self.code.len() - 1
+  }
+
+  /* Points the jump at i to the next instruction */
+  fn patch(&mut self, i: usize) {
+    let target = self.code.len();
+    match &mut self.code[i] {
+      Op::Jump(t) | Op::JumpUnless(t, _) => *t = target,
+      _ => unreachable!("only jumps are patched"),
+    }
+  }
+
+  fn bind(&mut self, x: &str, is_fun: bool) -> usize {
+    self.locals.push((x.to_string(), is_fun));
+    self.slots = self.slots.max(self.locals.len());
+    self.locals.len() - 1
+  }
+
+  /* Where x is bound in this function, as the environment of eval_expr would
+   * have it: locals shadow the group's functions, which shadow captures */
+  fn lookup(&self, x: &str) -> Option<Place> {
+    if let Some(i) = self.locals.iter().rposition(|(y, _)| y == x) {
+      return Some(Place::Local(i))
+    }
+    if let Some(j) = self.siblings.iter().rposition(|y| y == x) {
+      return Some(Place::Sibling(j))
+    }
+    self.captured.iter().position(|(y, _)| y == x).map(Place::Capture)
+  }
+
+  /* Every local name in scope, each once, with where its value is */
+  fn visible(&self) -> Vec<(String, bool, Place)> {
+    let mut visible: Vec<(String, bool, Place)> = vec![];
+    let captured = self.captured.iter().enumerate().map(|(i, (x, f))| (x, *f, Place::Capture(i)));
+    let siblings = self.siblings.iter().enumerate().map(|(j, x)| (x, true, Place::Sibling(j)));
+    let locals = self.locals.iter().enumerate().map(|(i, (x, f))| (x, *f, Place::Local(i)));
+    for (x, is_fun, place) in captured.chain(siblings).chain(locals) {
+      match visible.iter_mut().find(|(y, _, _)| y == x) {
+        Some(entry) => *entry = (x.clone(), is_fun, place),
+        None => visible.push((x.clone(), is_fun, place)),
+      }
+    }
+    visible
+  }
+}
+
+/* Whether e can neither fail nor do anything but give its value, so that the
+ * checks before it may wait until after it */
+fn is_simple(scope: &Scope, e: &Expr) -> bool {
+  match e {
+    Expr::Numeral(_) | Expr::StringLiteral(_) | Expr::True | Expr::False => true,
+    Expr::Id(x) => scope.lookup(x).is_some() || scope.env.contains_key(x),
+    Expr::Spanned(_, inner) => is_simple(scope, inner),
+    _ => false,
+  }
+}
+
+fn arith_op(e: &Expr) -> Option<(ArithOp, &Expr, &Expr)> {
+  match e {
+    Expr::Times(l, r) => Some((ArithOp::Times, l, r)),
+    Expr::Plus(l, r) => Some((ArithOp::Plus, l, r)),
+    Expr::Minus(l, r) => Some((ArithOp::Minus, l, r)),
+    _ => None,
+  }
+}
+
+struct Compiler {
+  functions: Vec<Option<Function>>,
+  groups: Vec<Group>,
+  consts: Vec<Slot>,
+  exprs: Vec<Expr>,
+  /* The groups already compiled for closures of an environment */
+  compiled: HashMap<*const Source, usize>,
+}
+
+/* Compiles e, to be run with the names of env in scope */
+pub(crate) fn compile(env: &Env, e: &Expr) -> Program {
+  let mut compiler = Compiler { functions: vec![], groups: vec![], consts: vec![], exprs: vec![], compiled: HashMap::new() };
+  let main = compiler.function(Scope::new(env, vec![], vec![], &[]), e, false);
+  Program {
+    main,
+    functions: compiler.functions.into_iter().map(|f| f.expect("every function is compiled")).collect(),
+    groups: compiler.groups,
+    consts: compiler.consts,
+    exprs: compiler.exprs,
+  }
+}
+
+impl Compiler {
+  fn expr(&mut self, e: &Expr) -> usize {
+    self.exprs.push(e.clone());
+    self.exprs.len() - 1
+  }
+
+  /* Compiles a function body. Only the bodies of Toi functions make tail calls,
+   * as the expression being evaluated is not itself a call in progress. */
+  fn function(&mut self, mut scope: Scope, body: &Expr, tail: bool) -> usize {
+    let id = self.functions.len();
+    self.functions.push(None);
+    let params = scope.locals.iter().map(|(x, _)| x.clone()).collect();
+    self.compile(&mut scope, body, tail);
+    scope.emit(Op::Return);
+    self.functions[id] = Some(Function { params, slots: scope.slots, code: scope.code, spans: scope.spans });
+    id
+  }
+
+  /* Compiles the functions of source, defined in env with the local names
+   * visible in scope */
+  fn group(&mut self, env: &Env, visible: Vec<(String, bool, Place)>, source: Rc<Source>) -> usize {
+    let captured: Vec<(String, bool)> = visible.iter().map(|(x, f, _)| (x.clone(), *f)).collect();
+    let siblings: Vec<String> = source.iter().map(|(f, _, _)| f.clone()).collect();
+    let functions = source.iter()
+      .map(|(_, params, body)| self.function(Scope::new(env, captured.clone(), siblings.clone(), params), body, true))
+      .collect();
+    let places = visible.into_iter().map(|(_, _, place)| place).collect();
+    self.groups.push(Group { source, env: env.clone(), captured, places, functions });
+    self.groups.len() - 1
+  }
+
+  /* The constant for a value of the environment given to compile */
+  fn constant(&mut self, v: Value) -> usize {
+    let slot = match v {
+      Value::Closure(c) => {
+        let key = Rc::as_ptr(&c.group);
+        let group = match self.compiled.get(&key) {
+          Some(g) => *g,
+          None => {
+            let g = self.group(&c.env, vec![], c.group.clone());
+            self.compiled.insert(key, g);
+            g
+          },
+        };
+        Slot::Closure(Rc::new(VmClosure { group, index: c.index, captures: Rc::new(vec![]) }))
+      },
+      v => Slot::Value(v),
+    };
+    self.consts.push(slot);
+    self.consts.len() - 1
+  }
+
+  /* Emits the instruction that loads x, or raises UnboundVariable for e */
+  fn load(&mut self, scope: &mut Scope, x: &str, e: &Expr) {
+    let op = match scope.lookup(x) {
+      Some(Place::Local(i)) => Op::Local(i),
+      Some(Place::Capture(i)) => Op::Capture(i),
+      Some(Place::Sibling(j)) => Op::Sibling(j),
+      None => match scope.env.get(x) {
+        Some(env_record) => Op::Const(self.constant(env_record.value())),
+        None => Op::Unbound(self.expr(e)),
+      },
+    };
+    scope.emit(op);
+  }
+
+  fn compile(&mut self, scope: &mut Scope, e: &Expr, tail: bool) {
+    match e {
+      Expr::Id(x) => self.load(scope, x, e),
+      Expr::Numeral(n) => {
+        scope.emit(Op::Numeral(*n));
+      },
+      Expr::Decimal(_, _) => {
+        let i = self.expr(e);
+        scope.emit(Op::Fractional(i));
+      },
+      Expr::StringLiteral(s) => {
+        let i = self.constant(Value::String(s.clone()));
+        scope.emit(Op::Const(i));
+      },
+      Expr::True => {
+        scope.emit(Op::Boolean(true));
+      },
+      Expr::False => {
+        scope.emit(Op::Boolean(false));
+      },
+      Expr::Compare(l, c, r) => {
+        self.operands(scope, l, r);
+        let i = self.expr(e);
+        scope.emit(Op::Compare(c.clone(), i));
+      },
+      Expr::Times(l, r) | Expr::Plus(l, r) | Expr::Minus(l, r) => {
+        self.operands(scope, l, r);
+        let (op, _, _) = arith_op(e).expect("e is arithmetic");
+        let i = self.expr(e);
+        scope.emit(Op::Arith(op, i));
+      },
+      Expr::Let(d, body) => {
+        let bound = scope.locals.len();
+        match &**d {
+          Defn::VarDefn(x, v) => {
+            self.compile(scope, v, false);
+            let slot = scope.bind(x, false);
+            scope.emit(Op::SetLocal(slot));
+          },
+          Defn::FunDefn(f, params, _, fbody) => self.let_group(scope, vec![(f.clone(), params.iter().map(|(x, _)| x.clone()).collect(), (**fbody).clone())]),
+          Defn::RecDefn(funcs) => self.let_group(scope, funcs.iter()
+            .map(|(f, params, _, fbody)| (f.clone(), params.iter().map(|(x, _)| x.clone()).collect(), (**fbody).clone()))
+            .collect()),
+        }
+        self.compile(scope, body, tail);
+        scope.locals.truncate(bound);
+      },
+      Expr::Call(f, a) => {
+        self.load(scope, f, e);
+        let i = self.expr(e);
+        if !a.iter().all(|ai| is_simple(scope, ai)) {
+          scope.emit(Op::Callee(a.len(), i));
+        }
+        for ai in a {
+          self.compile(scope, ai, false);
+        }
+        scope.emit(if tail { Op::TailCall(a.len(), i) } else { Op::Call(a.len(), i) });
+      },
+      Expr::Assert(c, m) => {
+        self.compile(scope, c, false);
+        let i = self.expr(c);
+        let unless = scope.emit(Op::JumpUnless(0, i));
+        scope.emit(Op::Boolean(true));
+        let jump = scope.emit(Op::Jump(0));
+        scope.patch(unless);
+        self.compile(scope, m, false);
+        scope.emit(Op::Fail);
+        scope.patch(jump);
+      },
+      Expr::If(c, t, f) => {
+        self.compile(scope, c, false);
+        let i = self.expr(c);
+        let unless = scope.emit(Op::JumpUnless(0, i));
+        self.compile(scope, t, tail);
+        let jump = scope.emit(Op::Jump(0));
+        scope.patch(unless);
+        self.compile(scope, f, tail);
+        scope.patch(jump);
+      },
+      Expr::Spanned(s, inner) => {
+        let outer = scope.span.replace(*s);
+        self.compile(scope, inner, tail);
+        scope.span = outer;
+      },
+    }
+  }
+
+  /* Compiles the operands of a binary operator. The first must be a number
+   * before the second runs, unless nothing the second does could be seen. */
+  fn operands(&mut self, scope: &mut Scope, l: &Expr, r: &Expr) {
+    self.compile(scope, l, false);
+    if !is_simple(scope, r) {
+      let i = self.expr(l);
+      scope.emit(Op::ExpectNumber(i));
+    }
+    self.compile(scope, r, false);
+  }
+
+  /* Compiles the functions of a let fun or let rec, and binds them */
+  fn let_group(&mut self, scope: &mut Scope, source: Source) {
+    let env = scope.env.clone();
+    let g = self.group(&env, scope.visible(), Rc::new(source));
+    let start = scope.locals.len();
+    for (f, _, _) in self.groups[g].source.clone().iter() {
+      scope.bind(f, true);
+    }
+    scope.emit(Op::MakeGroup(g, start));
+  }
+}
+
+/* A call in progress */
+struct Frame {
+  function: usize,
+  closure: Option<Rc<VmClosure>>,
+  /* Where the caller continues, and the number of calls in progress before it */
+  ret: usize,
+  depth: usize,
+  /* Where the callee's slots start on the stack, just above the callee */
+  base: usize,
+  /* The span errors in the body are reported at when no span in the body is
+   * around them: the innermost one around the call, or around the last tail
+   * call that replaced it */
+  span: Option<Span>,
+}
+
+struct Machine {
+  stack: Vec<Slot>,
+  frames: Vec<Frame>,
+  pc: usize,
+}
+
+fn is_number(v: &Slot) -> bool {
+  matches!(v, Slot::Value(Value::Numeral(_) | Value::BigNumeral(_)))
+}
+
+/* Evaluates a checked program with the default settings on the virtual machine,
+ * as eval_program does with eval_expr */
+pub fn eval_vm(p: &CheckedProgram) -> Result<Value, EvalError> {
+  Engine::standard(p).run_vm(&Evaluator::default(), p)
+}
+
+impl Program {
+  /* The Value of a slot: a compiled closure becomes the Closure eval_expr would
+   * have made, in the environment it was defined in */
+  fn to_value(&self, v: &Slot) -> Value {
+    match v {
+      Slot::Value(v) => v.clone(),
+      Slot::Closure(c) => {
+        let group = &self.groups[c.group];
+        let env = group.captured.iter().zip(c.captures.iter()).fold(group.env.clone(), |env, ((x, is_fun), v)| {
+          let record = match self.to_value(v) {
+            Value::Closure(c) if *is_fun => EnvRecord::FunRecord(c),
+            v => EnvRecord::VarRecord(v),
+          };
+          env.insert(x.clone(), record)
+        });
+        Value::Closure(Closure { group: group.source.clone(), index: c.index, env })
+      },
+    }
+  }
+
+  fn callee_name(&self, i: usize) -> &str {
+    match &self.exprs[i] {
+      Expr::Call(f, _) | Expr::Id(f) => f,
+      _ => unreachable!("only names are looked up"),
+    }
+  }
+
+  /* Checks that callee can be called at call i with n arguments */
+  fn check_callee(&self, callee: &Slot, n: usize, i: usize) -> Result<(), EvalError> {
+    let f = self.callee_name(i).to_string();
+    match callee {
+      Slot::Closure(c) => {
+        let params = &self.functions[self.groups[c.group].functions[c.index]].params;
+        if params.len() != n {
+          return Err(EvalError::ArityMismatch(f, params.clone(), self.exprs[i].clone()))
+        }
+      },
+      Slot::Value(Value::Native(native)) => {
+        if native.arity() != n {
+          return Err(EvalError::ArityMismatch(f, native.params().iter().map(Type::to_string).collect(), self.exprs[i].clone()))
+        }
+      },
+      _ => return Err(EvalError::NotAFunction(f, self.exprs[i].clone())),
+    }
+    Ok(())
+  }
+}
+
+impl Evaluator {
+  /* Compiles e and runs it on the virtual machine, within this evaluator's limits */
+  pub(crate) fn eval_vm(&self, env: &Env, e: &Expr) -> Result<Value, EvalError> {
+    self.run_compiled(&compile(env, e))
+  }
+
+  /* Runs a compiled program on the virtual machine */
+  pub fn run_compiled(&self, p: &Program) -> Result<Value, EvalError> {
+    self.start();
+    let main = &p.functions[p.main];
+    let mut m = Machine {
+      stack: vec![Slot::Value(Value::Unit); 1 + main.slots],
+      frames: vec![Frame { function: p.main, closure: None, ret: 0, depth: 0, base: 1, span: None }],
+      pc: 0,
+    };
+    match self.run_machine(p, &mut m) {
+      Ok(v) => Ok(p.to_value(&v)),
+      Err(err) => {
+        // The innermost span around the failed instruction, in its own body or
+        // around the calls it was made from
+        let top = m.frames.last().expect("a call is in progress");
+        let at = p.functions[top.function].spans[m.pc - 1].or_else(|| m.frames.iter().rev().find_map(|f| f.span));
+        Err(match at {
+          Some(s) => err.at(s),
+          None => err,
+        })
+      },
+    }
+  }
+
+  fn run_machine(&self, p: &Program, m: &mut Machine) -> Result<Slot, EvalError> {
+    // The function, closure and slots of the call in progress
+    let mut function = &p.functions[p.main];
+    let mut closure: Option<Rc<VmClosure>> = None;
+    let mut base = 1;
+    loop {
+      let op = &function.code[m.pc];
+      m.pc += 1;
+      self.step()?;
+      match op {
+        Op::Numeral(n) => m.stack.push(Slot::Value(Value::Numeral(*n))),
+        Op::Boolean(b) => m.stack.push(Slot::Value(Value::Boolean(*b))),
+        Op::Const(i) => m.stack.push(p.consts[*i].clone()),
+        Op::Local(i) => m.stack.push(m.stack[base + i].clone()),
+        Op::Capture(i) => m.stack.push(closure.as_ref().expect("only closures capture").captures[*i].clone()),
+        Op::Sibling(j) => m.stack.push(sibling(closure.as_ref().expect("only closures have siblings"), *j)),
+        Op::Unbound(i) => return Err(EvalError::UnboundVariable(p.callee_name(*i).to_string(), p.exprs[*i].clone())),
+        Op::Fractional(i) => return Err(EvalError::FractionalNumeral(p.exprs[*i].clone())),
+        Op::SetLocal(i) => {
+          let v = m.stack.pop().expect("a value to bind");
+          m.stack[base + i] = v;
+        },
+        Op::MakeGroup(g, start) => {
+          let group = &p.groups[*g];
+          let captures = Rc::new(group.places.iter().map(|place| match place {
+            Place::Local(i) => m.stack[base + i].clone(),
+            Place::Capture(i) => closure.as_ref().expect("only closures capture").captures[*i].clone(),
+            Place::Sibling(j) => sibling(closure.as_ref().expect("only closures have siblings"), *j),
+          }).collect::<Vec<Slot>>());
+          for index in 0..group.functions.len() {
+            m.stack[base + start + index] = Slot::Closure(Rc::new(VmClosure { group: *g, index, captures: captures.clone() }));
+          }
+        },
+        Op::ExpectNumber(i) => {
+          if !is_number(m.stack.last().expect("an operand")) {
+            return Err(EvalError::TypeMismatch(Type::Number, p.exprs[*i].clone()))
+          }
+        },
+        Op::Arith(op, i) => {
+          let r = m.stack.pop().expect("a second operand");
+          let l = m.stack.pop().expect("a first operand");
+          let (_, l_expr, r_expr) = arith_op(&p.exprs[*i]).expect("an arithmetic expression");
+          let v = match (&l, &r) {
+            (Slot::Value(lv), Slot::Value(rv)) if is_number(&l) && is_number(&r) => self.arith.apply(*op, lv, rv)
+              .ok_or_else(|| EvalError::IntegerOverflow(op.symbol().to_string(), p.exprs[*i].clone()))?,
+            _ if !is_number(&l) => return Err(EvalError::TypeMismatch(Type::Number, l_expr.clone())),
+            _ => return Err(EvalError::TypeMismatch(Type::Number, r_expr.clone())),
+          };
+          m.stack.push(Slot::Value(v));
+        },
+        Op::Compare(c, i) => {
+          let r = m.stack.pop().expect("a second operand");
+          let l = m.stack.pop().expect("a first operand");
+          let Expr::Compare(l_expr, _, r_expr) = &p.exprs[*i] else { unreachable!("a comparison") };
+          let order = match (&l, &r) {
+            (Slot::Value(lv), Slot::Value(rv)) if is_number(&l) && is_number(&r) => compare_numbers(lv, rv),
+            _ if !is_number(&l) => return Err(EvalError::TypeMismatch(Type::Number, (**l_expr).clone())),
+            _ => return Err(EvalError::TypeMismatch(Type::Number, (**r_expr).clone())),
+          };
+          m.stack.push(Slot::Value(Value::Boolean(holds(c, order))));
+        },
+        Op::Jump(t) => m.pc = *t,
+        Op::JumpUnless(t, i) => match m.stack.pop() {
+          Some(Slot::Value(Value::Boolean(true))) => {},
+          Some(Slot::Value(Value::Boolean(false))) => m.pc = *t,
+          _ => return Err(EvalError::TypeMismatch(Type::Boolean, p.exprs[*i].clone())),
+        },
+        Op::Fail => match m.stack.pop().expect("a message") {
+          Slot::Value(Value::String(message)) => return Err(EvalError::AssertionFailed(message)),
+          v => return Err(EvalError::AssertionFailed(p.to_value(&v).to_string())),
+        },
+        Op::Callee(n, i) => p.check_callee(m.stack.last().expect("a callee"), *n, *i)?,
+        Op::Call(n, i) | Op::TailCall(n, i) => {
+          let at = m.stack.len() - n - 1;
+          p.check_callee(&m.stack[at], *n, *i)?;
+          match m.stack[at].clone() {
+            Slot::Closure(c) => {
+              let id = p.groups[c.group].functions[c.index];
+              let span = function.spans[m.pc - 1];
+              if let Op::TailCall(_, _) = op {
+                // The callee takes the place of the current call on the stack
+                m.stack.drain(base - 1..at);
+                let top = m.frames.last_mut().expect("a call is in progress");
+                top.function = id;
+                top.closure = Some(c.clone());
+                top.span = span.or(top.span);
+              } else {
+                let depth = self.depth.get();
+                if depth >= self.limits.max_depth {
+                  return Err(EvalError::ResourceExhausted(Resource::Depth(self.limits.max_depth)))
+                }
+                self.depth.set(depth + 1);
+                base = at + 1;
+                m.frames.push(Frame { function: id, closure: Some(c.clone()), ret: m.pc, depth, base, span });
+              }
+              function = &p.functions[id];
+              closure = Some(c);
+              m.stack.resize(base + function.slots, Slot::Value(Value::Unit));
+              m.pc = 0;
+            },
+            Slot::Value(Value::Native(native)) => {
+              let args: Vec<Value> = m.stack.drain(at + 1..).map(|v| p.to_value(&v)).collect();
+              m.stack.pop();
+              let f = p.callee_name(*i).to_string();
+              let v = native.call(&args).ok_or_else(|| EvalError::NativeCallFailed(f, p.exprs[*i].clone()))?;
+              m.stack.push(Slot::Value(v));
+            },
+            _ => unreachable!("the callee was checked"),
+          }
+        },
+        Op::Return => {
+          let v = m.stack.pop().expect("a result");
+          if m.frames.len() == 1 {
+            return Ok(v)
+          }
+          let done = m.frames.pop().expect("a call is in progress");
+          self.depth.set(done.depth);
+          m.stack.truncate(done.base - 1);
+          m.stack.push(v);
+          m.pc = done.ret;
+          let caller = m.frames.last().expect("a caller");
+          function = &p.functions[caller.function];
+          closure = caller.closure.clone();
+          base = caller.base;
+        },
+      }
+    }
+  }
+}
+
+/* Function j of the group of closure c */
+fn sibling(c: &Rc<VmClosure>, j: usize) -> Slot {
+  if j == c.index {
+    return Slot::Closure(c.clone())
+  }
+  Slot::Closure(Rc::new(VmClosure { group: c.group, index: j, captures: c.captures.clone() }))
+}
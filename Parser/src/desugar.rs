@@ -0,0 +1,68 @@
+/* Desugaring translates the surface AST produced by the parser into the small core
+ * language (toi_ast::Expr and toi_ast::Defn) that the rest of the toolchain consumes.
+ *
+ * Every core node produced here is wrapped in Expr::Spanned with the span of the
+ * surface syntax it came from, so that a problem found in desugared code can still
+ * be reported at the text the user actually wrote.
+ *
+ *  desugar(Let([d], e))            = Let(d, desugar(e))
+ *  desugar(Let([d1,d2,...,dN], e)) = Let(d1, desugar(Let([d2,...,dN], e)))
+ *  desugar(Pipe(e, f, [a1,...]))   = Call(f, [desugar(e), desugar(a1), ...])
+ */
+use toi_ast::{Defn, Expr, Item, Span};
+use crate::surface;
+use crate::surface::{DeclKind, ExprKind};
+
+pub fn desugar_expr(e: &surface::Expr) -> Expr {
+  let core = match &e.kind {
+    ExprKind::Id(x) => Expr::Id(x.clone()),
+    ExprKind::Numeral(n) => Expr::Numeral(*n),
+    ExprKind::Decimal(d, p) => Expr::Decimal(*d, *p),
+    ExprKind::StringLiteral(s) => Expr::StringLiteral(s.clone()),
+    ExprKind::True => Expr::True,
+    ExprKind::False => Expr::False,
+    ExprKind::Compare(l, c, r) => Expr::Compare(Box::new(desugar_expr(l)), c.clone(), Box::new(desugar_expr(r))),
+    ExprKind::Times(l, r) => Expr::Times(Box::new(desugar_expr(l)), Box::new(desugar_expr(r))),
+    ExprKind::Plus(l, r) => Expr::Plus(Box::new(desugar_expr(l)), Box::new(desugar_expr(r))),
+    ExprKind::Minus(l, r) => Expr::Minus(Box::new(desugar_expr(l)), Box::new(desugar_expr(r))),
+    ExprKind::Let(ds, body) => {
+      // Nest the declarations from the inside out. Each inner let covers the text
+      // from its own declaration to the end of the whole let-expression.
+      let mut acc = desugar_expr(body);
+      for d in ds.iter().skip(1).rev() {
+        let inner = Expr::Let(Box::new(desugar_decl(d)), Box::new(acc));
+        acc = Expr::Spanned(Span::new(d.span.start, e.span.end), Box::new(inner));
+      }
+      match ds.first() {
+        Some(d) => Expr::Let(Box::new(desugar_decl(d)), Box::new(acc)),
+        None => return acc,
+      }
+    },
+    ExprKind::Call(f, args) => Expr::Call(f.clone(), args.iter().map(desugar_expr).collect()),
+    ExprKind::Pipe(lhs, f, args) => {
+      let mut all_args = vec![desugar_expr(lhs)];
+      all_args.extend(args.iter().map(desugar_expr));
+      Expr::Call(f.clone(), all_args)
+    },
+    ExprKind::Assert(c, m) => Expr::Assert(Box::new(desugar_expr(c)), Box::new(desugar_expr(m))),
+    ExprKind::If(c, t, f) => Expr::If(Box::new(desugar_expr(c)), Box::new(desugar_expr(t)), Box::new(desugar_expr(f))),
+  };
+  Expr::Spanned(e.span, Box::new(core))
+}
+
+pub fn desugar_decl(d: &surface::Decl) -> Defn {
+  match &d.kind {
+    DeclKind::VarDecl(x, e) => Defn::VarDefn(x.clone(), Box::new(desugar_expr(e))),
+    DeclKind::FunDecl(f, params, t, body) => Defn::FunDefn(f.clone(), params.clone(), t.clone(), Box::new(desugar_expr(body))),
+    DeclKind::RecDecl(fs) => Defn::RecDefn(fs.iter()
+      .map(|(f, params, t, body)| (f.clone(), params.clone(), t.clone(), Box::new(desugar_expr(body))))
+      .collect()),
+  }
+}
+
+pub fn desugar_item(i: &surface::Item) -> Item {
+  match i {
+    surface::Item::Decl(d) => Item::Defn(desugar_decl(d)),
+    surface::Item::Test(name, body) => Item::Test(name.clone(), Box::new(desugar_expr(body))),
+  }
+}
@@ -1,13 +1,25 @@
-pub mod parser;
-use parser::*;
-use parser::Expr as ex;
-use parser::Decl as dc;
+use toi_parser::parser;
+use toi_parser::parser::*;
+use toi_parser::parser::Expr as ex;
+use toi_parser::parser::Defn as dc;
+use toi_parser::parser::Type as tp;
+use toi_ast::sexp;
+
+// The number a parsed numeral stands for
+fn numeral_value(e: &parser::Expr) -> Option<f64> {
+  match e {
+    ex::Numeral(n) => Some(*n as f64),
+    ex::Decimal(d, p) => Some(*d as f64 / 10f64.powi(*p as i32)),
+    _ => None,
+  }
+}
 
 // Tests the parsing of numerals
+#[allow(clippy::needless_return)]
 fn test_numeral(score: f64, input: String, out: Option<f64>) -> f64 {
   match (parser::parser::numeral(&input), out) {
     (Ok(f1), Some(f2)) =>
-      if parser::expr_eq(f1, parser::Expr::Numeral(f2))  {
+      if numeral_value(&f1) == Some(f2)  {
         return score
       } else {
         println!("bad");
@@ -20,6 +32,7 @@ fn test_numeral(score: f64, input: String, out: Option<f64>) -> f64 {
 }
 
 // Tests the parsing of ids
+#[allow(clippy::needless_return)]
 fn test_id(score: f64, input: String, out: Option<String>) -> f64 {
   match (parser::parser::id(&input), out) {
     (Ok(s1), Some(s2)) =>
@@ -36,6 +49,7 @@ fn test_id(score: f64, input: String, out: Option<String>) -> f64 {
 }
 
 // Tests the parsing of expressions
+#[allow(clippy::needless_return)]
 fn test_expr(score: f64, input: String, out: Option<parser::Expr>) -> f64 {
   match (parser::parser::expr(&input), out) {
     (Ok(e1), Some(e2)) =>
@@ -52,7 +66,8 @@ fn test_expr(score: f64, input: String, out: Option<parser::Expr>) -> f64 {
 }
 
 // Tests the parsing of declarations
-fn test_decl(score: f64, input: String, out: Option<parser::Decl>) -> f64 {
+#[allow(clippy::needless_return)]
+fn test_decl(score: f64, input: String, out: Option<parser::Defn>) -> f64 {
   match (parser::parser::decl(&input), out) {
     (Ok(d1), Some(d2)) =>
       if parser::decl_eq(d1.clone(),d2.clone()) {
@@ -68,6 +83,54 @@ fn test_decl(score: f64, input: String, out: Option<parser::Decl>) -> f64 {
   }
 }
 
+// Tests the span recorded for the outermost node of a parsed expression
+fn test_span(score: f64, input: String, out: Span) -> f64 {
+  match parser::parser::expr(&input) {
+    Ok(ex::Spanned(s, _)) if s == out => score,
+    Ok(ex::Spanned(s, _)) => {println!("Span test case {} expected span {:?} but got {:?}", input, out, s); 0.0},
+    Ok(e) => {println!("Span test case {} expected span {:?} but got unspanned {}", input, out, expr_to_string(e)); 0.0},
+    Err(_) => {println!("Span test case {} expected span {:?} but did not parse", input, out); 0.0}
+  }
+}
+
+// Tests the span recorded on an already parsed expression
+fn test_span_of(score: f64, e: parser::Expr, out: Span) -> f64 {
+  match e {
+    ex::Spanned(s, _) if s == out => score,
+    e => {println!("Span test case {} expected span {:?}", expr_to_string(e), out); 0.0}
+  }
+}
+
+// Tests that an expression survives a round trip through JSON and S-expressions
+fn test_round_trip(score: f64, e: parser::Expr) -> f64 {
+  let json = serde_json::to_string(&e).ok().and_then(|t| serde_json::from_str::<parser::Expr>(&t).ok());
+  let sexp = sexp::to_string(&e).ok().and_then(|t| sexp::from_str::<parser::Expr>(&t).ok());
+  if json.as_ref() == Some(&e) && sexp.as_ref() == Some(&e) {
+    score
+  } else {
+    println!("Round trip test case {:?} came back as {:?} from JSON and {:?} from S-expressions", e, json, sexp);
+    0.0
+  }
+}
+
+// Tests the S-expression encoding of a parsed expression, without its spans
+fn test_sexp(score: f64, input: String, out: &str) -> f64 {
+  match parser::parser::expr(&input).map(|e| sexp::to_string(&e.strip_spans())) {
+    Ok(Ok(t)) if t == out => score,
+    r => {println!("S-expression test case {} expected {} but got {:?}", input, out, r); 0.0}
+  }
+}
+
+// Tests that an expression is printed as the given text, which parses back to it
+fn test_print(score: f64, input: &str, out: &str) -> f64 {
+  let printed = parser::parser::expr(input).map(expr_to_string);
+  let reparsed = printed.as_ref().ok().map(|t| parser::parser::expr(t));
+  match (parser::parser::expr(input), reparsed) {
+    (Ok(e1), Some(Ok(e2))) if printed.as_deref() == Ok(out) && parser::expr_eq(e1.clone(), e2.clone()) => score,
+    (_, r) => {println!("Printing test case {} expected {} but got {:?}, which parses as {:?}", input, out, printed, r); 0.0}
+  }
+}
+
 pub fn main() {
   // 10 tests of ids
   let r1 = test_id(1.0, "a".to_string(), Some("a".to_string()));
@@ -95,55 +158,165 @@ pub fn main() {
   let r_num = r11+r12+r13+r14+r15+r16+r17+r18+r19+r20;
   // 12 tests of expressions
   let r21 = test_expr(1.0, "xyzzy".to_string(), Some(ex::Id("xyzzy".to_string())));
-  let r22 = test_expr(1.0, "234".to_string(), Some(ex::Numeral(234.0)));
+  let r22 = test_expr(1.0, "234".to_string(), Some(ex::Numeral(234)));
   let r23 = test_expr(1.0, "x*y".to_string(), Some(ex::Times(Box::new(ex::Id("x".to_string())), Box::new(ex::Id("y".to_string())))));
-  let r24 = test_expr(1.0, "1.2+z_3".to_string(), Some(ex::Plus(Box::new(ex::Numeral(1.2)),Box::new(ex::Id("z_3".to_string())))));
-  let r25 = test_expr(1.0, "3-1".to_string(), Some(ex::Minus(Box::new(ex::Numeral(3.0)),Box::new(ex::Numeral(1.0)))));
-  let r26 = test_expr(1.0, "(2-(y))".to_string(), Some(ex::Minus(Box::new(ex::Numeral(2.0)),Box::new(ex::Id("y".to_string())))));
+  let r24 = test_expr(1.0, "1.2+z_3".to_string(), Some(ex::Plus(Box::new(ex::Decimal(12, 1)),Box::new(ex::Id("z_3".to_string())))));
+  let r25 = test_expr(1.0, "3-1".to_string(), Some(ex::Minus(Box::new(ex::Numeral(3)),Box::new(ex::Numeral(1)))));
+  let r26 = test_expr(1.0, "(2-(y))".to_string(), Some(ex::Minus(Box::new(ex::Numeral(2)),Box::new(ex::Id("y".to_string())))));
   let r27 = test_expr(1.0, "((2-(y))".to_string(), None);
   let r28 = test_expr(1.0, "+32".to_string(), None);
   let r29 = test_expr(1.0, "1*2+3*4".to_string(), 
-  Some(ex::Plus(Box::new(ex::Times(Box::new(ex::Numeral(1.0)),Box::new(ex::Numeral(2.0)))),
-                    Box::new(ex::Times(Box::new(ex::Numeral(3.0)),Box::new(ex::Numeral(4.0)))))));
+  Some(ex::Plus(Box::new(ex::Times(Box::new(ex::Numeral(1)),Box::new(ex::Numeral(2)))),
+                    Box::new(ex::Times(Box::new(ex::Numeral(3)),Box::new(ex::Numeral(4)))))));
   let r30 = test_expr(1.0, "1*2-3*4".to_string(), 
-  Some(ex::Minus(Box::new(ex::Times(Box::new(ex::Numeral(1.0)),Box::new(ex::Numeral(2.0)))),
-                    Box::new(ex::Times(Box::new(ex::Numeral(3.0)),Box::new(ex::Numeral(4.0)))))));
+  Some(ex::Minus(Box::new(ex::Times(Box::new(ex::Numeral(1)),Box::new(ex::Numeral(2)))),
+                    Box::new(ex::Times(Box::new(ex::Numeral(3)),Box::new(ex::Numeral(4)))))));
   let r31 = test_expr(1.0, "-1*-2--3*-4".to_string(), 
-  Some(ex::Minus(Box::new(ex::Times(Box::new(ex::Numeral(-1.0)),Box::new(ex::Numeral(-2.0)))),
-                    Box::new(ex::Times(Box::new(ex::Numeral(-3.0)),Box::new(ex::Numeral(-4.0)))))));
+  Some(ex::Minus(Box::new(ex::Times(Box::new(ex::Numeral(-1)),Box::new(ex::Numeral(-2)))),
+                    Box::new(ex::Times(Box::new(ex::Numeral(-3)),Box::new(ex::Numeral(-4)))))));
   let r32 = test_expr(1.0, "--1".to_string(), None);
   let r_expr = r21+r22+r23+r24+r25+r26+r27+r28+r29+r30+r31+r32;
   // 8 tests of expressions and declarations
   let r33 = test_expr(1.0, "let var x = y in x".to_string(),
-   Some(ex::Let(Box::new(dc::VarDecl("x".to_string(), Box::new(ex::Id("y".to_string())))),Box::new(ex::Id("x".to_string())))));
+   Some(ex::Let(Box::new(dc::VarDefn("x".to_string(), Box::new(ex::Id("y".to_string())))),Box::new(ex::Id("x".to_string())))));
   let r34a = test_expr(1.0, "let function f(x){y*x} in z".to_string(),
-   Some(ex::Let(Box::new(dc::FunDecl("f".to_string(), vec!["x".to_string()], 
+   Some(ex::Let(Box::new(dc::FunDefn("f".to_string(), vec![("x".to_string(), tp::Number)], tp::Number,
     Box::new(ex::Times(Box::new(ex::Id("y".to_string())), Box::new(ex::Id("x".to_string())))))),
     Box::new(ex::Id("z".to_string())))));
   let r34b = test_expr(1.0, "let function f(x){y*x} in f(2)".to_string(),
-    Some(ex::Let(Box::new(dc::FunDecl("f".to_string(), vec!["x".to_string()], 
+    Some(ex::Let(Box::new(dc::FunDefn("f".to_string(), vec![("x".to_string(), tp::Number)], tp::Number,
      Box::new(ex::Times(Box::new(ex::Id("y".to_string())), Box::new(ex::Id("x".to_string())))))),
-     Box::new(ex::FunCall("f".to_string(), vec![ex::Numeral(2.0)])))));
+     Box::new(ex::Call("f".to_string(), vec![ex::Numeral(2)])))));
    let r35 = test_expr(1.0, "let var x = let var y = 1 in y in let var y = x in x".to_string(),
    Some(ex::Let(Box::new(
-    dc::VarDecl("x".to_string(),  
-      Box::new(ex::Let(Box::new(dc::VarDecl("y".to_string(),Box::new(ex::Numeral(1.0)))),
+    dc::VarDefn("x".to_string(),  
+      Box::new(ex::Let(Box::new(dc::VarDefn("y".to_string(),Box::new(ex::Numeral(1)))),
                Box::new(ex::Id("y".to_string())))))),
-   Box::new(ex::Let(Box::new(dc::VarDecl("y".to_string(),Box::new(ex::Id("x".to_string())))),Box::new(ex::Id("x".to_string())))))));
+   Box::new(ex::Let(Box::new(dc::VarDefn("y".to_string(),Box::new(ex::Id("x".to_string())))),Box::new(ex::Id("x".to_string())))))));
   let r36 = test_expr(1.0, "(let var x = y in x)+(let var x = y in x)".to_string(),
-   Some(ex::Plus(Box::new(ex::Let(Box::new(dc::VarDecl("x".to_string(),Box::new(ex::Id("y".to_string())))),Box::new(ex::Id("x".to_string())))),
-   Box::new(ex::Let(Box::new(dc::VarDecl("x".to_string(), Box::new(ex::Id("y".to_string())))),Box::new(ex::Id("x".to_string())))))));
+   Some(ex::Plus(Box::new(ex::Let(Box::new(dc::VarDefn("x".to_string(),Box::new(ex::Id("y".to_string())))),Box::new(ex::Id("x".to_string())))),
+   Box::new(ex::Let(Box::new(dc::VarDefn("x".to_string(), Box::new(ex::Id("y".to_string())))),Box::new(ex::Id("x".to_string())))))));
   let r37 = test_decl(1.0, "var x = y".to_string(),
-   Some(dc::VarDecl("x".to_string(), Box::new(ex::Id("y".to_string())))));
+   Some(dc::VarDefn("x".to_string(), Box::new(ex::Id("y".to_string())))));
   let r38 = test_decl(1.0, "function f(x){y*x}".to_string(),
-   Some(dc::FunDecl("f".to_string(), vec!["x".to_string()], 
+   Some(dc::FunDefn("f".to_string(), vec![("x".to_string(), tp::Number)], tp::Number,
     Box::new(ex::Times(Box::new(ex::Id("y".to_string())), Box::new(ex::Id("x".to_string())))))));
   let r39 = test_decl(1.0, "var x = let var y = 1 in y".to_string(),
-   Some(dc::VarDecl("x".to_string(),  
-      Box::new(ex::Let(Box::new(dc::VarDecl("y".to_string(),Box::new(ex::Numeral(1.0)))),
+   Some(dc::VarDefn("x".to_string(),  
+      Box::new(ex::Let(Box::new(dc::VarDefn("y".to_string(),Box::new(ex::Numeral(1)))),
                Box::new(ex::Id("y".to_string())))))));
   let r_decl = r33+r34a+r34b+r35+r36+r37+r38+r39;
+  // 6 tests of desugaring and spans
+  let r40 = test_expr(1.0, "let var x = 1, var y = x in y".to_string(),
+   Some(ex::Let(Box::new(dc::VarDefn("x".to_string(), Box::new(ex::Numeral(1)))),
+    Box::new(ex::Let(Box::new(dc::VarDefn("y".to_string(), Box::new(ex::Id("x".to_string())))), Box::new(ex::Id("y".to_string())))))));
+  let r41 = test_expr(1.0, "x|>f(y,2)".to_string(),
+   Some(ex::Call("f".to_string(), vec![ex::Id("x".to_string()), ex::Id("y".to_string()), ex::Numeral(2)])));
+  let r42 = test_expr(1.0, "x+1|>f|>g(y)".to_string(),
+   Some(ex::Call("g".to_string(), vec![ex::Call("f".to_string(),
+    vec![ex::Plus(Box::new(ex::Id("x".to_string())), Box::new(ex::Numeral(1)))]), ex::Id("y".to_string())])));
+  let r43 = test_span(1.0, "(1+2)*3".to_string(), Span::new(0, 7));
+  let r44 = test_span(1.0, "let var x = 1, var y = 2 in y".to_string(), Span::new(0, 29));
+  let r45 = match parser::parser::expr("let var x = 1, var y = 2 in y") {
+    Ok(ex::Spanned(_, e)) => match *e {
+      ex::Let(_, inner) => test_span_of(1.0, *inner, Span::new(15, 29)),
+      _ => 0.0,
+    },
+    _ => 0.0,
+  };
+  let r_sugar = r40+r41+r42+r43+r44+r45;
+  // 6 tests of strings, booleans, comparisons and type annotations
+  let r46 = test_expr(1.0, "\"hi there\"".to_string(), Some(ex::StringLiteral("hi there".to_string())));
+  let r47 = test_expr(1.0, "true".to_string(), Some(ex::True));
+  let r48 = test_expr(1.0, "trueish".to_string(), Some(ex::Id("trueish".to_string())));
+  let r49 = test_expr(1.0, "x+1<=2*y".to_string(),
+   Some(ex::Compare(Box::new(ex::Plus(Box::new(ex::Id("x".to_string())), Box::new(ex::Numeral(1)))), parser::Comparison::LessEqual,
+    Box::new(ex::Times(Box::new(ex::Numeral(2)), Box::new(ex::Id("y".to_string())))))));
+  let r50 = test_decl(1.0, "function f(x:number,g:(number)->boolean):boolean{g(x)}".to_string(),
+   Some(dc::FunDefn("f".to_string(), vec![("x".to_string(), tp::Number), ("g".to_string(), tp::Function(vec![tp::Number], Box::new(tp::Boolean)))],
+    tp::Boolean, Box::new(ex::Call("g".to_string(), vec![ex::Id("x".to_string())])))));
+  let r51 = test_expr(1.0, "1==2==3".to_string(), None);
+  let r_typed = r46+r47+r48+r49+r50+r51;
+
+  // 5 tests of the JSON and S-expression encodings
+  let r52 = test_round_trip(1.0, parser::parser::expr("let function f(x:number,g:(number)->boolean):boolean{g(x)} in f(1,h)").unwrap());
+  let r53 = test_round_trip(1.0, ex::StringLiteral("say \"hi\"\n\\ (twice)".to_string()));
+  let r54 = test_sexp(1.0, "x+1|>f".to_string(), "(Call \"f\" ((Plus (Id \"x\") (Numeral 1))))");
+  let r55 = match sexp::from_str::<parser::Expr>("(Let (VarDefn \"x\" (Numeral -1)) ; x is -1\n  (Compare (Id \"x\") Less True))") {
+    Ok(e) if expr_eq(e.clone(), parser::parser::expr("let var x = -1 in x < true").unwrap()) => 1.0,
+    r => {println!("S-expression decoding test case got {:?}", r); 0.0}
+  };
+  let r56 = match sexp::from_str::<parser::Expr>("(Plus (Numeral 1))") {
+    Err(_) => 1.0,
+    Ok(e) => {println!("S-expression decoding test case expected an error but got {:?}", e); 0.0}
+  };
+  let r_encoding = r52+r53+r54+r55+r56;
+
+  // 4 tests of assertions and test items
+  let r57 = test_expr(1.0, "assert(x==1, \"x is one\")".to_string(),
+    Some(ex::Assert(Box::new(ex::Compare(Box::new(ex::Id("x".to_string())), Comparison::Equal, Box::new(ex::Numeral(1)))),
+      Box::new(ex::StringLiteral("x is one".to_string())))));
+  let r58 = test_id(1.0, "assert".to_string(), None);
+  let r59 = match parser::parser::items("function sq(x){x*x}\ntest \"sq\" { sq(2) == 4 } // four\nvar y = 1") {
+    Ok(items) => match &items[..] {
+      [Item::Defn(dc::FunDefn(f, _, _, _)), Item::Test(name, body), Item::Defn(dc::VarDefn(y, _))]
+        if f == "sq" && name == "sq" && y == "y" && expr_to_string(*body.clone()) == "sq(2)==4" => 1.0,
+      _ => {println!("Test item test case expected a function, a test and a variable but got {:?}", items); 0.0}
+    },
+    Err(e) => {println!("Test item test case did not parse: {}", e); 0.0}
+  };
+  let r60 = match parser::parser::items("test unnamed { true }") {
+    Err(_) => 1.0,
+    Ok(items) => {println!("Test item test case expected a test without a name not to parse but got {:?}", items); 0.0}
+  };
+  let r_tests = r57+r58+r59+r60;
+
+  // 3 tests of recursive groups
+  let r61 = test_decl(1.0, "rec f(x){g(x)} and g(y:number):boolean{f(y)>0}".to_string(), Some(dc::RecDefn(vec![
+    ("f".to_string(), vec![("x".to_string(), tp::Number)], tp::Number,
+      Box::new(ex::Call("g".to_string(), vec![ex::Id("x".to_string())]))),
+    ("g".to_string(), vec![("y".to_string(), tp::Number)], tp::Boolean,
+      Box::new(ex::Compare(Box::new(ex::Call("f".to_string(), vec![ex::Id("y".to_string())])), Comparison::Greater, Box::new(ex::Numeral(0))))),
+  ])));
+  let r62 = match parser::parser::expr("let rec f(x){x} in f(1)") {
+    Ok(e) => match e.strip_spans() {
+      ex::Let(d, _) if matches!(*d, dc::RecDefn(ref fs) if fs.len() == 1) => 1.0,
+      e => {println!("Recursive group test case expected a let rec but got {}", expr_to_string(e)); 0.0}
+    },
+    Err(e) => {println!("Recursive group test case did not parse: {}", e); 0.0}
+  };
+  let r63 = test_decl(1.0, "rec f(x){x} and".to_string(), None);
+  let r_rec = r61+r62+r63;
+
+  // 1 test of the unit type
+  let r64 = test_decl(1.0, "function log_with(log:(string)->unit, m:string):unit{log(m)}".to_string(),
+   Some(dc::FunDefn("log_with".to_string(), vec![("log".to_string(), tp::Function(vec![tp::String], Box::new(tp::Unit))), ("m".to_string(), tp::String)],
+    tp::Unit, Box::new(ex::Call("log".to_string(), vec![ex::Id("m".to_string())])))));
+
+  // 3 tests of conditionals
+  let r65 = test_expr(1.0, "if n == 0 then 1 else n * 2".to_string(),
+    Some(ex::If(Box::new(ex::Compare(Box::new(ex::Id("n".to_string())), Comparison::Equal, Box::new(ex::Numeral(0)))),
+      Box::new(ex::Numeral(1)), Box::new(ex::Times(Box::new(ex::Id("n".to_string())), Box::new(ex::Numeral(2)))))));
+  let r66 = test_id(1.0, "then".to_string(), None);
+  let r67 = test_round_trip(1.0, parser::parser::expr("let function f(n){if n<1 then 0 else f(n-1)} in f(3)").unwrap());
+
+
+  // 4 tests of printing, which keeps the parentheses the grammar needs
+  let r68 = test_print(1.0, "(1+2)*3", "(1+2)*3");
+  let r69 = test_print(1.0, "(1-2)-3", "(1-2)-3");
+  let r70 = test_print(1.0, "(1-2)-(3*(4*5))+6", "(1-2)-3*4*5+6");
+  let r71 = test_print(1.0, "(if x < 1 then 2 else 3) * f(let var y = 1.5 in y, (x == 1) == true)",
+    "(if x<1 then 2 else 3)*f(let var y = 1.5 in y,(x==1)==true)");
+  let r_print = r68+r69+r70+r71;
+
+  // 2 tests of input cut off after a separator or keyword, which fails at its end expecting a keyword, as the REPL needs
+  let cut_off = |input: &str| match parser::parser::expr(input) {
+    Err(e) if e.location.offset == input.len() && e.expected.tokens().any(|t| t == "var") => 1.0,
+    r => {println!("Expression test case {} expected to fail at its end expecting var but got {:?}", input, r); 0.0}
+  };
+  let r72 = cut_off("let var a = 1,");
+  let r73 = cut_off("let");
 
-  let r = r_id + r_num + r_expr + r_decl;
-  println!("Results: {}/40 tests succesfully completed", r)
+  let r = r_id + r_num + r_expr + r_decl + r_sugar + r_typed + r_encoding + r_tests + r_rec + r64 + r65 + r66 + r67 + r_print + r72 + r73;
+  println!("Results: {}/74 tests succesfully completed", r)
 }
\ No newline at end of file
@@ -1,4 +1,8 @@
   use peg::*;
+  use crate::surface;
+  use crate::surface::{ExprKind, DeclKind};
+  use crate::desugar::{desugar_expr, desugar_decl, desugar_item};
+  pub use toi_ast::{Expr, Defn, Item, Type, Comparison, Span};
 /* An implementation of a PEG parser for the below context-free grammar.
  * 
  * Terminal Symbols:
@@ -9,93 +13,152 @@
  * then an optional period and fractional part.
  * An integer part is either a single 0 or a nonzero digit.
  * A fractional part is a nonempty sequence of digits.
+ * A numeral whose fractional part is all zeros is a Numeral, and any other is a
+ * Decimal, which the typer rejects since Toi numbers are integers.
+ *
+ * A string is a double quote, any characters other than a double quote,
+ * then a closing double quote.
+ *
+ * The keywords let, in, var, function, rec, and, true, false, assert, if, then and
+ * else are not ids.
+ * Whitespace and // line comments may separate any two symbols.
  * 
  * Variable Symbols:
- * Atom <- numeral |  id "(" ArgList ")" | id | "(" Expr ")"
+ * Atom <- numeral | string | "true" | "false" | "assert" "(" Expr "," Expr ")"
+ *       | id "(" ExprList ")" | id | "(" Expr ")"
  * Op2 <- Atom * Op2 | Atom
  * Op1 <- Op2 + Op1 | Op2 - Op1 | Op2
- * Expr <- "let" Decl "in" Expr | Op1
- * Decl <- "var" id "=" Expr | "function" id "(" ArgList ")" "{" Expr "}"
+ * Pipe <- Pipe "|>" id "(" ExprList ")" | Pipe "|>" id | Op1
+ * Cmp <- Pipe CmpOp Pipe | Pipe
+ * Expr <- "let" DeclList "in" Expr | "if" Expr "then" Expr "else" Expr | Cmp
+ * Decl <- "var" id "=" Expr | "function" Fun | "rec" RecList
+ * Fun <- id "(" ArgList ")" RetType "{" Expr "}"
+ * RecList <- Fun "and" RecList | Fun
  *
- * NonEmptyArgList <- id, NonEmptyArgList | id
+ * CmpOp <- "<=" | "<" | "==" | "!=" | ">=" | ">"
+ * Arg <- id ":" Type | id
+ * RetType <- ":" Type | <empty string>
+ * Type <- "number" | "string" | "boolean" | "unit" | "(" TypeList ")" "->" Type
+ * NonEmptyArgList <- Arg, NonEmptyArgList | Arg
  * ArgList <-  NonEmptyArgList | <empty string>
+ * NonEmptyExprList <- Expr, NonEmptyExprList | Expr
+ * ExprList <-  NonEmptyExprList | <empty string>
+ * DeclList <- Decl, DeclList | Decl
+ * Decls <- Decl Decls | <empty string>
+ * Item <- Decl | "test" string "{" Expr "}"
+ * Items <- Item Items | <empty string>
+ *
+ * A parameter or return type left out of a function declaration is number,
+ * so untyped programs written for the original numeric grammar still check.
+ *
+ * The grammar rules build the surface AST (see surface.rs), which keeps the
+ * sugared forms (multi-declaration lets and pipelines) and source spans.
+ * expr() and decl() then desugar it into the core Expr and Defn of toi-ast,
+ * which the typer and evaluator consume directly.
  */
 
- /* Define an Expression and a Declaration */
-#[derive(Clone)]
-pub enum Expr {
-    Id(String),
-    Numeral(f64),
-    Times(Box<Expr>,Box<Expr>),
-    Plus(Box<Expr>,Box<Expr>),
-    Minus(Box<Expr>,Box<Expr>),
-    Let(Box<Decl>,Box<Expr>),
-    FunCall(String, Vec<Expr>),
-} 
-#[derive(Clone)]
-pub enum Decl {
-    VarDecl(String, Box<Expr>),
-    FunDecl(String, Vec<String>, Box<Expr>),
-}
-
 /* The following five functions are for debugging and testing code. */
 pub fn expr_eq(e1: Expr, e2: Expr) -> bool {
-  match (e1,e2) {
-    (Expr::Id(s1),Expr::Id(s2)) => s1 == s2,
-    (Expr::Numeral(n1), Expr::Numeral(n2)) => n1 == n2,
-    (Expr::Times(l1,r1),Expr::Times(l2,r2)) => expr_eq(*l1,*l2) && expr_eq(*r1,*r2),
-    (Expr::Plus(l1,r1),Expr::Plus(l2,r2)) => expr_eq(*l1,*l2) && expr_eq(*r1,*r2),
-    (Expr::Minus(l1,r1),Expr::Minus(l2,r2)) => expr_eq(*l1,*l2) && expr_eq(*r1,*r2),
-    (Expr::Let(d1,e1),Expr::Let(d2,e2)) => decl_eq(*d1,*d2) && expr_eq(*e1,*e2),
-    (Expr::FunCall(f1, args1),Expr::FunCall(f2,args2)) =>  {
-    for (x,y) in args1.iter().zip(args2.iter()) {
-      if !expr_eq(x.clone(),y.clone()) {
-        return false;
-      }
-    }
+  e1.strip_spans() == e2.strip_spans()
+}
 
-    return f1 == f2},
-    _ =>false,
+/* Removes any Spanned wrappers around the outermost node of an expression */
+pub fn strip_spans(e: Expr) -> Expr {
+  match e {
+    Expr::Spanned(_, e) => strip_spans(*e),
+    e => e,
   }
 }
 
-pub fn decl_eq(d1: Decl, d2: Decl) -> bool {
-  match (d1,d2) {
-    (Decl::FunDecl(f1, args1, body1), Decl::FunDecl(f2,args2,body2)) => 
-    f1 == f2 && args1 == args2 && expr_eq(*body1,*body2),
-    (Decl::VarDecl(x1, body1), Decl::VarDecl(x2, body2)) => x1 == x2 && expr_eq(*body1,*body2),
-    _ => false,
+pub fn decl_eq(d1: Defn, d2: Defn) -> bool {
+  d1.strip_spans() == d2.strip_spans()
+}
+
+pub fn type_to_string(t: Type) -> String {
+  t.to_string()
+}
+
+pub fn comparison_to_string(c: Comparison) -> String {
+  match c {
+    Comparison::LessEqual => "<=",
+    Comparison::Less => "<",
+    Comparison::Equal => "==",
+    Comparison::NotEqual => "!=",
+    Comparison::Greater => ">",
+    Comparison::GreaterEqual => ">=",
+  }.to_string()
+}
+
+/* How tightly e binds in the grammar above, loosest first: let and if,
+ * comparisons, + and -, *, and atoms */
+fn precedence(e: &Expr) -> u8 {
+  match e {
+    Expr::Let(_, _) | Expr::If(_, _, _) => 0,
+    Expr::Compare(_, _, _) => 1,
+    Expr::Plus(_, _) | Expr::Minus(_, _) => 2,
+    Expr::Times(_, _) => 3,
+    Expr::Spanned(_, e) => precedence(e),
+    _ => 4,
+  }
+}
+
+/* e where the grammar expects an expression of at least the given precedence,
+ * in parentheses if it binds more loosely */
+fn operand_to_string(e: Expr, level: u8) -> String {
+  if precedence(&e) < level {
+    format!("({})", expr_to_string(e))
+  } else {
+    expr_to_string(e)
   }
 }
 
+/* e as Toi source that parses back to e. Operators are right-associative, so
+ * only an operand on the left of its own operator needs parentheses. */
 pub fn expr_to_string(e: Expr) -> String {
  match e {
     Expr::Id(s) => s,
-    Expr::Numeral(f) => f.to_string(),
-    Expr::Times(l,r) =>format!("{}*{}", expr_to_string(*l), expr_to_string(*r)),
-    Expr::Plus(l,r) =>format!("{}+{}", expr_to_string(*l), expr_to_string(*r)),
-    Expr::Minus(l,r) =>format!("{}-{}", expr_to_string(*l), expr_to_string(*r)),
+    Expr::Numeral(n) => n.to_string(),
+    Expr::Decimal(d, p) => decimal_to_string(d, p),
+    Expr::StringLiteral(s) => format!("\"{}\"", s),
+    Expr::True => "true".to_string(),
+    Expr::False => "false".to_string(),
+    Expr::Compare(l,c,r) =>format!("{}{}{}", operand_to_string(*l, 2), comparison_to_string(c), operand_to_string(*r, 2)),
+    Expr::Times(l,r) =>format!("{}*{}", operand_to_string(*l, 4), operand_to_string(*r, 3)),
+    Expr::Plus(l,r) =>format!("{}+{}", operand_to_string(*l, 3), operand_to_string(*r, 2)),
+    Expr::Minus(l,r) =>format!("{}-{}", operand_to_string(*l, 3), operand_to_string(*r, 2)),
     Expr::Let(d,e)=>format!("let {} in {}", decl_to_string(*d), expr_to_string(*e)),
-    Expr::FunCall(f,args)=>{
-      let mut arg_str = "".to_string();
-      for s in args {
-        arg_str = format!("{},{}",arg_str, expr_to_string(s))
-      }
-    format!("{}({})", f,arg_str)
-    }  }
+    Expr::Call(f,args)=>{
+      let arg_strs: Vec<String> = args.into_iter().map(expr_to_string).collect();
+      format!("{}({})", f, arg_strs.join(","))
+    }
+    Expr::Assert(c,m)=>format!("assert({},{})", expr_to_string(*c), expr_to_string(*m)),
+    Expr::If(c,t,e)=>format!("if {} then {} else {}", expr_to_string(*c), expr_to_string(*t), expr_to_string(*e)),
+    Expr::Spanned(_, e) => expr_to_string(*e),
+  }
+}
+
+/* The digits of d with a decimal point p places from the right */
+fn decimal_to_string(d: i64, p: u32) -> String {
+  let digits = format!("{:0>width$}", d.unsigned_abs(), width = p as usize + 1);
+  let (int, frac) = digits.split_at(digits.len() - p as usize);
+  format!("{}{}.{}", if d < 0 { "-" } else { "" }, int, frac)
 }
 
-pub fn decl_to_string(d: Decl) -> String {
+pub fn decl_to_string(d: Defn) -> String {
  match d {
-  Decl::FunDecl(f, al, b) =>{
-    let mut arg_str = "".to_string();
-    for s in al {
-      arg_str = format!("{}{}",arg_str, s)
-    }
-    format!("function {}({}){{{}}}", f,arg_str,expr_to_string(*b))
+  Defn::FunDefn(f, al, t, b) =>{
+    let arg_strs: Vec<String> = al.into_iter().map(|(x, t)| format!("{}:{}", x, type_to_string(t))).collect();
+    format!("function {}({}):{}{{{}}}", f,arg_strs.join(","),type_to_string(t),expr_to_string(*b))
   }
   ,
-  Decl::VarDecl(x,e)=> format!("var {} = {}",x,expr_to_string(*e)),
+  Defn::VarDefn(x,e)=> format!("var {} = {}",x,expr_to_string(*e)),
+  Defn::RecDefn(fs)=>{
+    let fun_strs: Vec<String> = fs.into_iter().map(|(f, al, t, b)| {
+      let arg_strs: Vec<String> = al.into_iter().map(|(x, t)| format!("{}:{}", x, type_to_string(t))).collect();
+      format!("{}({}):{}{{{}}}", f,arg_strs.join(","),type_to_string(t),expr_to_string(*b))
+    }).collect();
+    format!("rec {}", fun_strs.join(" and "))
+  }
  }
 }
 
@@ -111,59 +174,156 @@ peg::parser!{
   pub grammar parser() for str {  
   /* Parse a single identifier (id) (i.e., variable name) */ 
   pub rule id() -> String 
-  = n:$(['a'..='z' | 'A'..='Z'] ['a'..='z' | 'A'..='Z' | '0'..='9' | '_']*)
+  = !reserved() n:$(['a'..='z' | 'A'..='Z'] ['a'..='z' | 'A'..='Z' | '0'..='9' | '_']*)
     {? n.parse().or(Err("String"))}
 
  /* Parse a single variable. var() behaves just like id(), except with a different return type.*/ 
   pub rule var() -> Expr 
   = n:(id()) { Expr::Id(n) }
     
-  /* Parse a single literal number or numeral.*/
-  rule numeral_f64() -> f64
-  = (n:$("-"? ((['1'..='9'] ['0'..='9']*) / "0") ("." ['0'..='9']+)?)
-    {? n.parse::<f64>().or(Err("f64")) })
+  /* Parse a single literal number or numeral, as its digits and the number of
+     them after the decimal point, not counting trailing zeros.*/
+  rule numeral_digits() -> (i64, u32)
+  = (n:$("-"? ((['1'..='9'] ['0'..='9']*) / "0")) f:$("." ['0'..='9']+)?
+    {? let places = f.map_or("", |f| f[1..].trim_end_matches('0'));
+       format!("{}{}", n, places).parse::<i64>().map(|d| (d, places.len() as u32)).or(Err("i64")) })
 
   pub rule numeral() -> Expr 
-  = n:(numeral_f64()) { Expr::Numeral((n)) }
+  = n:(numeral_digits()) { match n { (d, 0) => Expr::Numeral(d), (d, p) => Expr::Decimal(d, p) } }
+
+  /* Parse a single string literal, without the surrounding quotes.*/
+  rule string_literal() -> String
+  = "\"" s:$([^ '"']*) "\"" { s.to_string() }
+
+  // Keywords must not run on into an identifier, so "trueish" is an id. A
+  // missing keyword is expected where it was looked for, like any other token.
+  rule keyword(k: &'static str)
+  = quiet!{##parse_string_literal(k) !['a'..='z' | 'A'..='Z' | '0'..='9' | '_']} / expected!(k)
+
+  rule reserved()
+  = keyword("let") / keyword("in") / keyword("var") / keyword("function")
+  / keyword("rec") / keyword("and")
+  / keyword("true") / keyword("false") / keyword("assert")
+  / keyword("if") / keyword("then") / keyword("else")
+
+  // Whitespace and line comments may appear between any two tokens
+  rule _()
+  = quiet!{([' ' | '\t' | '\r' | '\n'] / ("//" [^ '\n']*))*}
+
+  /* Parse a type annotation.*/
+  pub rule type_() -> Type
+  = (keyword("number") {Type::Number})
+  / (keyword("string") {Type::String})
+  / (keyword("boolean") {Type::Boolean})
+  / (keyword("unit") {Type::Unit})
+  / ("(" _ a:(type_() ** (_ "," _)) _ ")" _ "->" _ r:type_() {Type::Function(a, Box::new(r))})
   
   /* Parser implementation for all expressions and declarations. Uses a precedence-climbing approach.
-     Both expr() and decl() call eachother.*/
+     Both surface_expr() and surface_decl() call eachother and build the surface AST.*/
 
   // An atom in the precedence hierachy
-  rule atom() -> Expr
-  = numeral() / (i:id() "(" a:arg_list_expr() ")" {Expr::FunCall(i, a)})
-  / var() / ("(" e:expr() ")" {e}) 
+  rule atom() -> surface::Expr
+  = (s:position!() k:atom_kind() e:position!() {surface::Expr::new(k, Span::new(s, e))})
+  / ("(" _ e:surface_expr() _ ")" {e})
+
+  rule atom_kind() -> ExprKind
+  = (n:numeral_digits() { match n { (d, 0) => ExprKind::Numeral(d), (d, p) => ExprKind::Decimal(d, p) } })
+  / (s:string_literal() {ExprKind::StringLiteral(s)})
+  / (keyword("true") {ExprKind::True})
+  / (keyword("false") {ExprKind::False})
+  / (keyword("assert") _ "(" _ c:surface_expr() _ "," _ m:surface_expr() _ ")"
+      {ExprKind::Assert(Box::new(c), Box::new(m))})
+  / (i:id() _ "(" _ a:expr_list() _ ")" {ExprKind::Call(i, a)})
+  / (i:id() {ExprKind::Id(i)})
 
   // Higher level operations in precedence hierachy
-  rule op2() -> Expr
-  = (l:atom() "*" r:op2() {Expr::Times(Box::new(l), Box::new(r))}) / atom()
+  rule op2() -> surface::Expr
+  = (s:position!() l:atom() _ "*" _ r:op2() e:position!()
+      {surface::Expr::new(ExprKind::Times(Box::new(l), Box::new(r)), Span::new(s, e))})
+  / atom()
 
   // Lower level operations in precedence hierachy
-  rule op1() -> Expr 
-  = (l:op2() "+" r:op1() {Expr::Plus(Box::new(l), Box::new(r))}) 
-  / (l:op2() "-" r:op1() {Expr::Minus(Box::new(l), Box::new(r))}) / op2()
+  rule op1() -> surface::Expr
+  = (s:position!() l:op2() _ "+" _ r:op1() e:position!()
+      {surface::Expr::new(ExprKind::Plus(Box::new(l), Box::new(r)), Span::new(s, e))})
+  / (s:position!() l:op2() _ "-" _ r:op1() e:position!()
+      {surface::Expr::new(ExprKind::Minus(Box::new(l), Box::new(r)), Span::new(s, e))})
+  / op2()
 
-  // Argument lists split into empty and non empty to allow for correct types to be prsed
-  rule non_empty_arg_list() -> Vec<String>
-  = (i:(id() ** ("," non_empty_arg_list())) {i})   / i:id() {vec![i]}
+  // Pipelines are left-associative: x |> f |> g(y) is g(f(x), y)
+  rule pipe() -> surface::Expr
+  = s:position!() l:op1() stages:(_ p:pipe_stage() {p})* {
+      stages.into_iter().fold(l, |acc, (f, a, e)|
+        surface::Expr::new(ExprKind::Pipe(Box::new(acc), f, a), Span::new(s, e)))
+    }
 
-  rule arg_list() -> Vec<String>
-  = (n:non_empty_arg_list() {n}) / ({vec![]})
+  rule pipe_stage() -> (String, Vec<surface::Expr>, usize)
+  = "|>" _ f:id() a:(_ "(" _ a:expr_list() _ ")" {a})? e:position!() {(f, a.unwrap_or_default(), e)}
 
-  rule non_empty_arg_list_expr() -> Vec<Expr>
-  = (e:(expr() ** ("," non_empty_arg_list_expr())) {e}) / e:expr() {vec![e]}
+  // Comparisons bind more loosely than arithmetic and pipelines, and do not chain
+  rule cmp() -> surface::Expr
+  = (s:position!() l:pipe() _ c:cmp_op() _ r:pipe() e:position!()
+      {surface::Expr::new(ExprKind::Compare(Box::new(l), c, Box::new(r)), Span::new(s, e))})
+  / pipe()
 
-  rule arg_list_expr() -> Vec<Expr>
-  = (n:non_empty_arg_list_expr() {n}) / ({vec![]})
+  rule cmp_op() -> Comparison
+  = ("<=" {Comparison::LessEqual}) / ("<" {Comparison::Less})
+  / ("==" {Comparison::Equal}) / ("!=" {Comparison::NotEqual})
+  / (">=" {Comparison::GreaterEqual}) / (">" {Comparison::Greater})
 
-  // Expressions
-  pub rule expr() -> Expr 
-  = ("let " d:decl() " in " e:expr() {Expr::Let(Box::new(d), Box::new(e))}) / op1()
-  
-  // Declarations
-  pub rule decl() -> Decl 
-  = ("var " i:id() " = " e:expr() {Decl::VarDecl(i, Box::new(e))}) 
-  / ("function " i:id() "(" a:arg_list() ")" "{" e:expr() "}" {Decl::FunDecl(i, a, Box::new(e))})
+  // A parameter is annotated with its type, which defaults to number
+  rule arg() -> (String, Type)
+  = i:id() t:(_ ":" _ t:type_() {t})? {(i, t.unwrap_or(Type::Number))}
+
+  // Argument lists of parameters and of expressions may both be empty
+  rule arg_list() -> Vec<(String, Type)>
+  = arg() ** (_ "," _)
+
+  rule expr_list() -> Vec<surface::Expr>
+  = surface_expr() ** (_ "," _)
+
+  // Expressions in the surface syntax
+  pub rule surface_expr() -> surface::Expr
+  = (s:position!() keyword("let") _ d:(surface_decl() ++ (_ "," _)) _ keyword("in") _ b:surface_expr() e:position!()
+      {surface::Expr::new(ExprKind::Let(d, Box::new(b)), Span::new(s, e))})
+  / (s:position!() keyword("if") _ c:surface_expr() _ keyword("then") _ t:surface_expr() _ keyword("else") _ f:surface_expr() e:position!()
+      {surface::Expr::new(ExprKind::If(Box::new(c), Box::new(t), Box::new(f)), Span::new(s, e))})
+  / cmp()
+
+  // Declarations in the surface syntax
+  pub rule surface_decl() -> surface::Decl
+  = (s:position!() keyword("var") _ i:id() _ "=" _ v:surface_expr() e:position!()
+      {surface::Decl::new(DeclKind::VarDecl(i, Box::new(v)), Span::new(s, e))})
+  / (s:position!() keyword("function") _ f:fun() e:position!()
+      {let (i, a, t, b) = f; surface::Decl::new(DeclKind::FunDecl(i, a, t, b), Span::new(s, e))})
+  / (s:position!() keyword("rec") _ fs:(fun() ++ (_ keyword("and") _)) e:position!()
+      {surface::Decl::new(DeclKind::RecDecl(fs), Span::new(s, e))})
+
+  // A function's name, parameters, return type and body
+  rule fun() -> surface::Fun
+  = i:id() _ "(" _ a:arg_list() _ ")" t:(_ ":" _ t:type_() {t})? _ "{" _ b:surface_expr() _ "}"
+      {(i, a, t.unwrap_or(Type::Number), Box::new(b))}
+
+  // Expressions, desugared into the core language
+  pub rule expr() -> Expr
+  = _ e:surface_expr() _ {desugar_expr(&e)}
+
+  // Declarations, desugared into the core language
+  pub rule decl() -> Defn
+  = _ d:surface_decl() _ {desugar_decl(&d)}
+
+  // A sequence of declarations, such as a file of definitions loaded into the REPL
+  pub rule decls() -> Vec<Defn>
+  = _ ds:(surface_decl() ** _) _ {ds.iter().map(desugar_decl).collect()}
+
+  // A top-level item: a declaration or a named test
+  pub rule surface_item() -> surface::Item
+  = (d:surface_decl() {surface::Item::Decl(d)})
+  / (keyword("test") _ n:string_literal() _ "{" _ b:surface_expr() _ "}" {surface::Item::Test(n, Box::new(b))})
+
+  // A file of declarations and tests, as run by toi test
+  pub rule items() -> Vec<Item>
+  = _ is:(surface_item() ** _) _ {is.iter().map(desugar_item).collect()}
   }
 }
 
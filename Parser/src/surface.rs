@@ -0,0 +1,73 @@
+/* The surface AST is what the parser produces: it mirrors the concrete syntax the
+ * user wrote, including syntactic sugar, and every node remembers the span of
+ * source text it was parsed from.
+ *
+ * Surface forms and their meaning in the core language (see desugar.rs):
+ *  Let([d1,...,dN], e)       let d1, ..., dN in e  =  let d1 in ... let dN in e
+ *  Pipe(e, f, [a1,...,aN])   e |> f(a1,...,aN)     =  f(e, a1, ..., aN)
+ * All other forms correspond one-to-one with the core Expr and Defn of toi-ast.
+ */
+
+pub use toi_ast::Span;
+use serde::{Deserialize, Serialize};
+use toi_ast::{Comparison, Type};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ExprKind {
+    Id(String),
+    Numeral(i64),
+    Decimal(i64, u32),
+    StringLiteral(String),
+    True,
+    False,
+    Compare(Box<Expr>, Comparison, Box<Expr>),
+    Times(Box<Expr>, Box<Expr>),
+    Plus(Box<Expr>, Box<Expr>),
+    Minus(Box<Expr>, Box<Expr>),
+    Let(Vec<Decl>, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Pipe(Box<Expr>, String, Vec<Expr>),
+    Assert(Box<Expr>, Box<Expr>),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Decl {
+    pub kind: DeclKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum DeclKind {
+    VarDecl(String, Box<Expr>),
+    FunDecl(String, Vec<(String, Type)>, Type, Box<Expr>),
+    RecDecl(Vec<Fun>),
+}
+
+/* The name, parameters, return type and body of a function */
+pub type Fun = (String, Vec<(String, Type)>, Type, Box<Expr>);
+
+/* A top-level item of a file of tests */
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Item {
+    Decl(Decl),
+    Test(String, Box<Expr>),
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Expr {
+        Expr { kind, span }
+    }
+}
+
+impl Decl {
+    pub fn new(kind: DeclKind, span: Span) -> Decl {
+        Decl { kind, span }
+    }
+}
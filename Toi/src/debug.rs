@@ -0,0 +1,161 @@
+/* The debugger's console, through which a person steps through a program run
+ * with toi run --debug FILE or :debug in the REPL (see debugger.rs).
+ *
+ * At each pause the console shows where evaluation is and reads commands until
+ * one of them lets evaluation go on:
+ *   step, s             go on to the next expression, into calls
+ *   next, n             go on past the current expression
+ *   finish, f           go on until the current function returns
+ *   continue, c         go on until a breakpoint
+ *   break F|LINE, b     pause on entering function F, or on reaching line LINE
+ *   delete F|LINE, d    remove a breakpoint
+ *   breakpoints         list the breakpoints
+ *   env                 list the names in scope and their values
+ *   print X, p X        print the value of the name X
+ *   stack, bt           print the calls in progress, innermost first
+ *   quit, q             stop the program, as does end of input
+ *   help, h             list the commands
+ */
+use toi_ast::{Expr, Span};
+use toi_parser::parser;
+use toi_evaluator::debugger::{Breakpoint, Command, Controller, Pause, Reason};
+use toi_evaluator::evaluator::EnvRecord;
+
+const HELP: &str = "commands:
+  step, s             go on to the next expression, into calls
+  next, n             go on past the current expression
+  finish, f           go on until the current function returns
+  continue, c         go on until a breakpoint
+  break F|LINE, b     pause on entering function F, or on reaching line LINE
+  delete F|LINE, d    remove a breakpoint
+  breakpoints         list the breakpoints
+  env                 list the names in scope and their values
+  print X, p X        print the value of the name X
+  stack, bt           print the calls in progress, innermost first
+  quit, q             stop the program";
+
+/* How long an expression may be shown before it is cut short */
+const SHOWN_LENGTH: usize = 60;
+
+/* A Controller that asks a person what to do. read shows a prompt and gives the
+ * next line of input, or None at the end of it. */
+pub struct Console<R: FnMut(&str) -> Option<String>> {
+  name: String,
+  text: String,
+  read: R,
+}
+
+impl<R: FnMut(&str) -> Option<String>> Console<R> {
+  /* A console for the program text, reported under name */
+  pub fn new(name: &str, text: &str, read: R) -> Console<R> {
+    Console { name: name.to_string(), text: text.to_string(), read }
+  }
+
+  /* The file, line and column a span starts at, or just the file */
+  fn at(&self, span: Option<Span>) -> String {
+    match span {
+      Some(s) => {
+        let (line, col) = s.line_col(&self.text);
+        format!("{}:{}:{}", self.name, line, col)
+      },
+      None => self.name.clone(),
+    }
+  }
+}
+
+/* e as Toi source, cut short if it is long */
+fn shown(e: &Expr) -> String {
+  let text = parser::expr_to_string(e.clone());
+  match text.char_indices().nth(SHOWN_LENGTH) {
+    Some((cut, _)) => format!("{}…", &text[..cut]),
+    None => text,
+  }
+}
+
+fn breakpoint(arg: &str) -> Option<Breakpoint> {
+  if arg.is_empty() {
+    return None
+  }
+  match arg.parse::<usize>() {
+    Ok(line) => Some(Breakpoint::Line(line)),
+    Err(_) => Some(Breakpoint::Function(arg.to_string())),
+  }
+}
+
+fn describe(b: &Breakpoint) -> String {
+  match b {
+    Breakpoint::Function(f) => format!("function {}", f),
+    Breakpoint::Line(l) => format!("line {}", l),
+  }
+}
+
+impl<R: FnMut(&str) -> Option<String>> Controller for Console<R> {
+  fn pause(&mut self, pause: &Pause, breakpoints: &mut Vec<Breakpoint>) -> Command {
+    if let Reason::Breakpoint(b) = &pause.reason {
+      println!("breakpoint on {}", describe(b));
+    }
+    println!("{}: {}", self.at(pause.span), shown(pause.expr));
+    loop {
+      let Some(input) = (self.read)("(debug) ") else { return Command::Quit };
+      let input = input.trim();
+      let (name, arg) = match input.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (input, ""),
+      };
+      match name {
+        "step" | "s" => return Command::StepInto,
+        "next" | "n" => return Command::StepOver,
+        "finish" | "f" => return Command::StepOut,
+        "continue" | "c" => return Command::Continue,
+        "quit" | "q" => return Command::Quit,
+        "break" | "b" => match breakpoint(arg) {
+          Some(b) if breakpoints.contains(&b) => println!("there is already a breakpoint on {}", describe(&b)),
+          Some(b) => {
+            println!("breakpoint on {}", describe(&b));
+            breakpoints.push(b);
+          },
+          None => println!("break needs a function name or a line number"),
+        },
+        "delete" | "d" => match breakpoint(arg) {
+          Some(b) if breakpoints.contains(&b) => {
+            breakpoints.retain(|other| *other != b);
+            println!("deleted the breakpoint on {}", describe(&b));
+          },
+          Some(b) => println!("there is no breakpoint on {}", describe(&b)),
+          None => println!("delete needs a function name or a line number"),
+        },
+        "breakpoints" => {
+          if breakpoints.is_empty() {
+            println!("no breakpoints");
+          }
+          breakpoints.iter().for_each(|b| println!("{}", describe(b)));
+        },
+        "env" => {
+          // Builtins are always there, so only list what the program defined
+          let mut names: Vec<(&String, &EnvRecord)> = pause.env.iter()
+            .filter(|(_, r)| !matches!(r, EnvRecord::NativeRecord(_)))
+            .collect();
+          names.sort_by(|a, b| a.0.cmp(b.0));
+          if names.is_empty() {
+            println!("no definitions");
+          }
+          names.into_iter().for_each(|(x, r)| println!("{} = {}", x, r.value()));
+        },
+        "print" | "p" => match pause.env.get(arg) {
+          Some(r) => println!("{} = {}", arg, r.value()),
+          None => println!("{} is not defined here", arg),
+        },
+        "stack" | "bt" => {
+          for (i, frame) in pause.stack.iter().rev().enumerate() {
+            let args: Vec<String> = frame.args.iter().map(|(x, v)| format!("{} = {}", x, v)).collect();
+            println!("#{} {}({}) called at {}", i, frame.function, args.join(", "), self.at(frame.call));
+          }
+          println!("#{} the program", pause.stack.len());
+        },
+        "help" | "h" => println!("{}", HELP),
+        "" => {},
+        _ => println!("unknown command {} (try help)", name),
+      }
+    }
+  }
+}
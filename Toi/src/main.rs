@@ -0,0 +1,415 @@
+/* The toi command-line driver, which runs the parser, typer and evaluator on a
+ * Toi source file.
+ *
+ * Usage:
+ *   toi parse [--dump-ast] [FILE]   parse a program and print it back (or its AST)
+ *   toi check [FILE]                type-check a program and print its type
+ *   toi run [FILE]                  type-check a program, evaluate it and print its value
+ *   toi test [FILE]                 run the tests declared in a file (see runner.rs)
+ *   toi repl                        start an interactive session (see repl.rs)
+ *
+ * FILE defaults to standard input, which can also be named explicitly as "-".
+ * With --json or --sexp, parse prints the program's AST in that encoding, and
+ * check and run read an AST in that encoding instead of Toi source.
+ * run --arith=POLICY evaluates with the checked (the default), wrapping,
+ * saturating or bigint arithmetic policy (see ArithPolicy in evaluator.rs).
+ * run --memo caches the value of each call of a function with its arguments (see
+ * memo.rs), so that a call made again is not evaluated again, and reports how
+ * the cache was used on standard error.
+ * run --strategy=STRATEGY passes the arguments of calls by value (the default),
+ * by name or by need (see strategy.rs). --trace, --derive and --debug always
+ * evaluate by value, so they cannot be given with the other two.
+ * run --trace prints each expression the program reduces through instead of its
+ * value (see smallstep.rs), and --trace=N only the first N steps and the last.
+ * run --derive prints the derivation tree of the program's value instead (see
+ * derivation.rs), as indented text or, with --derive=latex, as LaTeX. Like
+ * --debug and --trace, it stops with an error once STACK_DEPTH calls are in
+ * progress.
+ * run --debug runs the program in the debugger, reading debugger commands from
+ * standard input (see debug.rs), so the program must be read from FILE. It stops
+ * with an error once STACK_DEPTH calls are in progress (see evaluator.rs).
+ * run --profile prints the program's value and then how many times each function
+ * was called and how long it took (see profiler.rs), and --profile=folded just
+ * the folded call stacks that flame graph tools draw. A program that stops with
+ * an error still has its profile printed.
+ * Programs are checked and run with the standard prelude of builtin functions, on
+ * the CEK machine so that deep recursion does not overflow the stack.
+ *
+ * Exit codes:
+ *   0  success
+ *   1  the command line was invalid or the input could not be read
+ *   2  the program does not parse
+ *   3  the program does not type-check
+ *   4  the program stopped with an error while running, or a test failed
+ */
+mod debug;
+mod repl;
+mod runner;
+
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::process::ExitCode;
+use toi_ast::{sexp, Expr, Span};
+use toi_parser::parser;
+use toi_typing::typer::{CheckedProgram, TypeError};
+use toi_evaluator::engine::Engine;
+use toi_evaluator::evaluator::{ArithPolicy, EvalError, Evaluator};
+use toi_evaluator::smallstep::Trace;
+use toi_evaluator::debugger::Debugger;
+use toi_evaluator::strategy::Strategy;
+
+/* The stack the driver runs on, which has room for STACK_DEPTH calls in the
+ * debugger, a derivation or a trace even in a debug build */
+const STACK_SIZE: usize = 256 << 20;
+
+const EXIT_USAGE: u8 = 1;
+const EXIT_PARSE: u8 = 2;
+const EXIT_TYPE: u8 = 3;
+const EXIT_RUNTIME: u8 = 4;
+
+const USAGE: &str = "usage:
+  toi parse [--dump-ast | --json | --sexp] [FILE]
+  toi check [--json | --sexp] [FILE]
+  toi run [--json | --sexp] [--arith=checked|wrapping|saturating|bigint] [--memo] [--strategy=value|name|need]
+          [--trace[=N] | --derive[=text|latex] | --debug | --profile[=report|folded]] [FILE]
+  toi test [FILE]
+  toi repl
+FILE defaults to standard input";
+
+/* The machine-readable encodings of a program's AST */
+#[derive(Clone, Copy)]
+enum Encoding {
+  Json,
+  Sexp,
+}
+
+/* A program's text and the name to report it under */
+struct Source {
+  name: String,
+  text: String,
+}
+
+/* How run shows a program's profile */
+#[derive(Clone, Copy)]
+enum ProfileFormat {
+  Report,
+  Folded,
+}
+
+/* How run shows a program's derivation tree */
+#[derive(Clone, Copy)]
+enum Rendering {
+  Text,
+  Latex,
+}
+
+/* What run prints: the value, the steps of the program or its derivation, the
+ * value after a debugging session, or its profile */
+#[derive(Clone, Copy)]
+enum Output {
+  Value,
+  Trace(usize),
+  Derivation(Rendering),
+  Debug,
+  Profile(ProfileFormat),
+}
+
+/* Why a command stopped early, and the exit code that reports it */
+struct Failure {
+  code: u8,
+  message: String,
+}
+
+impl Failure {
+  fn new(code: u8, message: String) -> Failure {
+    Failure { code, message }
+  }
+}
+
+impl Source {
+  /* The file, line and column a span starts at, or just the file */
+  fn at(&self, span: Option<Span>) -> String {
+    match span {
+      Some(s) => {
+        let (line, col) = s.line_col(&self.text);
+        format!("{}:{}:{}", self.name, line, col)
+      },
+      None => self.name.clone(),
+    }
+  }
+}
+
+fn read_source(path: Option<&str>) -> Result<Source, Failure> {
+  match path {
+    None | Some("-") => {
+      let mut text = String::new();
+      match io::stdin().read_to_string(&mut text) {
+        Ok(_) => Ok(Source { name: "<stdin>".to_string(), text }),
+        Err(e) => Err(Failure::new(EXIT_USAGE, format!("cannot read standard input: {}", e))),
+      }
+    },
+    Some(p) => match fs::read_to_string(p) {
+      Ok(text) => Ok(Source { name: p.to_string(), text }),
+      Err(e) => Err(Failure::new(EXIT_USAGE, format!("cannot read {}: {}", p, e))),
+    },
+  }
+}
+
+fn parse_error(src: &Source, e: peg::error::ParseError<peg::str::LineCol>) -> Failure {
+  Failure::new(EXIT_PARSE,
+    format!("{}:{}:{}: parse error: expected {}", src.name, e.location.line, e.location.column, e.expected))
+}
+
+fn parse(src: &Source) -> Result<Expr, Failure> {
+  parser::parser::expr(&src.text).map_err(|e| parse_error(src, e))
+}
+
+fn decode(src: &Source, encoding: Encoding) -> Result<Expr, Failure> {
+  let decoded = match encoding {
+    Encoding::Json => serde_json::from_str(&src.text).map_err(|e| e.to_string()),
+    Encoding::Sexp => sexp::from_str(&src.text).map_err(|e| e.to_string()),
+  };
+  decoded.map_err(|m| Failure::new(EXIT_PARSE, format!("{}: cannot decode AST: {}", src.name, m)))
+}
+
+fn encode(e: &Expr, encoding: Encoding) -> String {
+  // ASTs hold no floats, the one thing the encoders reject
+  match encoding {
+    Encoding::Json => serde_json::to_string(e).expect("ASTs encode as JSON"),
+    Encoding::Sexp => sexp::to_string(e).expect("ASTs encode as S-expressions"),
+  }
+}
+
+fn check(src: &Source, engine: &Engine, e: Expr) -> Result<CheckedProgram, Failure> {
+  engine.infer(e).map_err(|err| type_error(src, &err))
+}
+
+fn type_error(src: &Source, err: &TypeError) -> Failure {
+  Failure::new(EXIT_TYPE, format!("{}: type error: {}", src.at(err.span), err))
+}
+
+fn runtime_error(src: &Source, err: &EvalError) -> Failure {
+  Failure::new(EXIT_RUNTIME, format!("{}: error: {}", src.at(err.span()), err))
+}
+
+/* Prints the expressions of trace one per line, leaving out those between the
+ * first limit steps and the last expression */
+fn print_trace(src: &Source, trace: Trace, limit: usize) -> Result<(), Failure> {
+  let mut held = None;
+  let mut elided = 0;
+  let show_held = |held: Option<String>, elided: usize| {
+    if let Some(e) = held {
+      if elided > 0 {
+        println!("… ({} more steps)", elided);
+      }
+      println!("→ {}", e);
+    }
+  };
+  for (i, e) in trace.enumerate() {
+    let e = match e {
+      Ok(e) => parser::expr_to_string(e),
+      Err(err) => {
+        show_held(held, elided);
+        return Err(runtime_error(src, &err));
+      },
+    };
+    if i == 0 {
+      println!("{}", e);
+    } else if i <= limit {
+      println!("→ {}", e);
+    } else {
+      if held.is_some() {
+        elided += 1;
+      }
+      held = Some(e);
+    }
+  }
+  show_held(held, elided);
+  Ok(())
+}
+
+/* Prompts for a debugger command and reads it from standard input */
+fn read_command(prompt: &str) -> Option<String> {
+  print!("{}", prompt);
+  io::stdout().flush().ok()?;
+  let mut line = String::new();
+  match io::stdin().lock().read_line(&mut line) {
+    Ok(0) | Err(_) => None,
+    Ok(_) => Some(line),
+  }
+}
+
+fn run_command(command: &str, dump_ast: bool, encoding: Option<Encoding>, evaluator: &Evaluator, output: Output, path: Option<&str>) -> Result<(), Failure> {
+  let src = read_source(path)?;
+  if command == "test" {
+    return runner::run_tests(&src);
+  }
+  let e = match encoding {
+    Some(enc) if command != "parse" => decode(&src, enc)?,
+    _ => parse(&src)?,
+  };
+  let engine = Engine::with_prelude();
+  match command {
+    "parse" => {
+      if let Some(enc) = encoding {
+        println!("{}", encode(&e.strip_spans(), enc));
+      } else if dump_ast {
+        println!("{:#?}", e.strip_spans());
+      } else {
+        println!("{}", parser::expr_to_string(e));
+      }
+    },
+    "check" => {
+      let p = check(&src, &engine, e)?;
+      println!("{}", parser::type_to_string(p.ty().clone()));
+    },
+    "run" => {
+      let p = check(&src, &engine, e)?;
+      match output {
+        Output::Value => {
+          let result = engine.run_cek(evaluator, &p);
+          if evaluator.memo {
+            let stats = evaluator.memo_stats();
+            eprintln!("memo: {} hits, {} misses, {} cached values", stats.hits, stats.misses, stats.entries);
+          }
+          println!("{}", result.map_err(|err| runtime_error(&src, &err))?);
+        },
+        Output::Trace(limit) => print_trace(&src, engine.trace(evaluator, &p), limit)?,
+        Output::Derivation(rendering) => {
+          let d = engine.derive(evaluator, &p).map_err(|err| runtime_error(&src, &err))?;
+          match rendering {
+            Rendering::Text => print!("{}", d.to_text()),
+            Rendering::Latex => print!("{}", d.to_latex()),
+          }
+        },
+        Output::Debug => {
+          let mut debugger = Debugger::new(evaluator.clone()).with_source(&src.text);
+          let mut console = debug::Console::new(&src.name, &src.text, read_command);
+          match engine.debug(&mut debugger, &p, &mut console) {
+            Some(v) => println!("{}", v.map_err(|err| runtime_error(&src, &err))?),
+            None => println!("program stopped"),
+          }
+        },
+        Output::Profile(format) => {
+          let (result, profile) = engine.profile(evaluator, &p);
+          match format {
+            ProfileFormat::Report => {
+              if let Ok(v) = &result {
+                println!("{}", v);
+              }
+              print!("{}", profile.report());
+            },
+            ProfileFormat::Folded => print!("{}", profile.folded()),
+          }
+          result.map_err(|err| runtime_error(&src, &err))?;
+        },
+      }
+    },
+    _ => unreachable!("commands are validated before running"),
+  }
+  Ok(())
+}
+
+fn main() -> ExitCode {
+  let driver = std::thread::Builder::new().stack_size(STACK_SIZE).spawn(drive).expect("the driver thread starts");
+  driver.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+fn drive() -> ExitCode {
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  if args.iter().any(|a| a == "-h" || a == "--help") {
+    println!("{}", USAGE);
+    return ExitCode::SUCCESS;
+  }
+
+  if args.len() == 1 && args[0] == "repl" {
+    return match repl::run() {
+      Ok(()) => ExitCode::SUCCESS,
+      Err(e) => {
+        eprintln!("toi: {}", e);
+        ExitCode::from(EXIT_USAGE)
+      },
+    };
+  }
+
+  let mut dump_ast = false;
+  let mut encoding = None;
+  let mut evaluator = Evaluator::new();
+  let mut output = Output::Value;
+  let mut path = None;
+  let command = match args.first().map(String::as_str) {
+    Some(c @ ("parse" | "check" | "run" | "test")) => c,
+    Some(c) => {
+      eprintln!("toi: unknown command '{}'\n{}", c, USAGE);
+      return ExitCode::from(EXIT_USAGE);
+    },
+    None => {
+      eprintln!("{}", USAGE);
+      return ExitCode::from(EXIT_USAGE);
+    },
+  };
+  for a in &args[1..] {
+    match a.as_str() {
+      "--dump-ast" if command == "parse" && encoding.is_none() => dump_ast = true,
+      "--json" if command != "test" && !dump_ast && encoding.is_none() => encoding = Some(Encoding::Json),
+      "--sexp" if command != "test" && !dump_ast && encoding.is_none() => encoding = Some(Encoding::Sexp),
+      a if a.starts_with("--arith=") && command == "run" => {
+        match a["--arith=".len()..].parse::<ArithPolicy>() {
+          Ok(policy) => evaluator = evaluator.with_arith(policy),
+          Err(m) => {
+            eprintln!("toi: {}\n{}", m, USAGE);
+            return ExitCode::from(EXIT_USAGE);
+          },
+        }
+      },
+      a if a.starts_with("--strategy=") && command == "run" => {
+        match a["--strategy=".len()..].parse::<Strategy>() {
+          Ok(strategy) => evaluator = evaluator.with_strategy(strategy),
+          Err(m) => {
+            eprintln!("toi: {}\n{}", m, USAGE);
+            return ExitCode::from(EXIT_USAGE);
+          },
+        }
+      },
+      "--trace" if command == "run" && matches!(output, Output::Value) => output = Output::Trace(usize::MAX),
+      a if a.starts_with("--trace=") && command == "run" && matches!(output, Output::Value) => {
+        match a["--trace=".len()..].parse::<usize>() {
+          Ok(limit) => output = Output::Trace(limit),
+          Err(_) => {
+            eprintln!("toi: the --trace limit must be a number of steps\n{}", USAGE);
+            return ExitCode::from(EXIT_USAGE);
+          },
+        }
+      },
+      "--derive" | "--derive=text" if command == "run" && matches!(output, Output::Value) => output = Output::Derivation(Rendering::Text),
+      "--derive=latex" if command == "run" && matches!(output, Output::Value) => output = Output::Derivation(Rendering::Latex),
+      "--memo" if command == "run" => evaluator = evaluator.with_memo(true),
+      "--profile" | "--profile=report" if command == "run" && matches!(output, Output::Value) => output = Output::Profile(ProfileFormat::Report),
+      "--profile=folded" if command == "run" && matches!(output, Output::Value) => output = Output::Profile(ProfileFormat::Folded),
+      "--debug" if command == "run" && matches!(output, Output::Value) => output = Output::Debug,
+      f if (f == "-" || !f.starts_with('-')) && path.is_none() => path = Some(f),
+      _ => {
+        eprintln!("toi: unexpected argument '{}'\n{}", a, USAGE);
+        return ExitCode::from(EXIT_USAGE);
+      },
+    }
+  }
+
+  if matches!(output, Output::Debug) && matches!(path, None | Some("-")) {
+    eprintln!("toi: --debug reads commands from standard input, so the program must be in a FILE\n{}", USAGE);
+    return ExitCode::from(EXIT_USAGE);
+  }
+
+  if evaluator.strategy != Strategy::ByValue && matches!(output, Output::Trace(_) | Output::Derivation(_) | Output::Debug) {
+    eprintln!("toi: --trace, --derive and --debug always evaluate by value, so they cannot be given with --strategy=name or --strategy=need\n{}", USAGE);
+    return ExitCode::from(EXIT_USAGE);
+  }
+
+  match run_command(command, dump_ast, encoding, &evaluator, output, path) {
+    Ok(()) => ExitCode::SUCCESS,
+    Err(failure) => {
+      eprintln!("toi: {}", failure.message);
+      ExitCode::from(failure.code)
+    },
+  }
+}
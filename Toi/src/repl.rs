@@ -0,0 +1,248 @@
+/* An interactive read-eval-print loop for Toi.
+ *
+ * Each input is a declaration, an expression or a meta-command:
+ *   var x = e, function f(..){..}   extends the session's typing context and environment
+ *   rec f(..){..} and g(..){..}     (as does a group of mutually recursive functions)
+ *   e                               prints the value and type of e
+ *   :type e                         prints the type of e without evaluating it
+ *   :debug e                        evaluates e in the debugger (see debug.rs)
+ *   :env                            lists every name defined in the session
+ *   :reset                          forgets every definition, leaving only the prelude
+ *   :load FILE                      defines the declarations in FILE, or runs its expression
+ *   :quit                           leaves the REPL, as does end of input
+ *
+ * Input that stops part way through a declaration or expression continues on the
+ * next line; an empty line abandons it. Line history is kept in .toi_history in
+ * the working directory.
+ */
+use std::fs;
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use toi_ast::{Defn, Expr, Type};
+use toi_parser::parser;
+use toi_typing::typer::{CheckedProgram, TypeError};
+use toi_evaluator::debugger::Debugger;
+use toi_evaluator::engine::Engine;
+use toi_evaluator::evaluator::{EnvRecord, EvalError, Evaluator};
+use crate::debug::Console;
+
+const HISTORY_FILE: &str = ".toi_history";
+
+/* The definitions made so far in one REPL session, on top of the prelude */
+pub struct Session {
+  engine: Engine,
+}
+
+/* What the REPL should do after reading some input */
+pub enum Outcome {
+  /* Print this text, and read a fresh input */
+  Output(String),
+  /* The input is unfinished; read another line and append it */
+  Incomplete,
+  /* Evaluate this well-typed expression, written as this text, in the debugger,
+   * which reads its commands from the same input */
+  Debug(Expr, String),
+  Quit,
+}
+
+type ParseError = peg::error::ParseError<peg::str::LineCol>;
+
+impl Default for Session {
+  fn default() -> Session {
+    Session::new()
+  }
+}
+
+impl Session {
+  pub fn new() -> Session {
+    Session { engine: Engine::with_prelude() }
+  }
+
+  /* Handles one complete input. Unless `force` is set, input that ends part way
+   * through a declaration or expression is reported as Incomplete. */
+  pub fn handle(&mut self, input: &str, force: bool) -> Outcome {
+    let line = input.trim();
+    if line.is_empty() {
+      return Outcome::Output(String::new());
+    }
+    if let Some(cmd) = line.strip_prefix(':') {
+      let (name, arg) = match cmd.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (cmd, ""),
+      };
+      return match name {
+        "quit" | "q" => Outcome::Quit,
+        "reset" => {
+          *self = Session::new();
+          Outcome::Output("environment cleared".to_string())
+        },
+        "env" => Outcome::Output(self.show_env()),
+        "type" | "t" => match parser::parser::expr(arg) {
+          Ok(e) => Outcome::Output(self.type_of(&e, arg).map_or_else(|m| m, parser::type_to_string)),
+          Err(err) if !force && at_end(arg, &err) => Outcome::Incomplete,
+          Err(err) => Outcome::Output(parse_error(&err)),
+        },
+        "debug" => match parser::parser::expr(arg) {
+          Ok(e) => match self.type_of(&e, arg) {
+            Ok(_) => Outcome::Debug(e, arg.to_string()),
+            Err(m) => Outcome::Output(m),
+          },
+          Err(err) if !force && at_end(arg, &err) => Outcome::Incomplete,
+          Err(err) => Outcome::Output(parse_error(&err)),
+        },
+        "load" => Outcome::Output(self.load(arg).unwrap_or_else(|m| m)),
+        _ => Outcome::Output(format!("unknown command :{} (try :type, :debug, :env, :reset, :load or :quit)", name)),
+      };
+    }
+
+    match (parser::parser::decl(line), parser::parser::expr(line)) {
+      (Ok(d), _) => Outcome::Output(self.define(&d, line).unwrap_or_else(|m| m)),
+      (_, Ok(e)) => Outcome::Output(self.evaluate(&e, line).unwrap_or_else(|m| m)),
+      (Err(de), Err(ee)) => {
+        // Report whichever reading of the input got further
+        let err = if de.location.offset > ee.location.offset { de } else { ee };
+        if !force && at_end(line, &err) {
+          Outcome::Incomplete
+        } else {
+          Outcome::Output(parse_error(&err))
+        }
+      },
+    }
+  }
+
+  /* e, written as text, if it is well-typed */
+  fn check(&self, e: &Expr, text: &str) -> Result<CheckedProgram, String> {
+    self.engine.infer(e.clone()).map_err(|err| type_error(&err, text))
+  }
+
+  fn type_of(&self, e: &Expr, text: &str) -> Result<Type, String> {
+    self.check(e, text).map(|p| p.ty().clone())
+  }
+
+  fn define(&mut self, d: &Defn, text: &str) -> Result<String, String> {
+    match self.engine.define(&Evaluator::new(), d) {
+      Ok(bindings) => {
+        let out: Vec<String> = bindings.map_err(|e| runtime_error(&e))?.into_iter()
+          .map(|(name, t)| format!("{} : {}", name, parser::type_to_string(t)))
+          .collect();
+        Ok(out.join("\n"))
+      },
+      Err(err) => Err(type_error(&err, text)),
+    }
+  }
+
+  fn evaluate(&mut self, e: &Expr, text: &str) -> Result<String, String> {
+    let p = self.check(e, text)?;
+    let v = self.engine.run_cek(&Evaluator::new(), &p).map_err(|e| runtime_error(&e))?;
+    Ok(format!("{} : {}", v, parser::type_to_string(p.ty().clone())))
+  }
+
+  /* Evaluates e, written as text, in the debugger, with commands from read */
+  pub fn debug(&self, e: &Expr, text: &str, read: impl FnMut(&str) -> Option<String>) -> String {
+    let p = match self.check(e, text) {
+      Ok(p) => p,
+      Err(m) => return m,
+    };
+    let mut debugger = Debugger::new(Evaluator::new()).with_source(text);
+    match self.engine.debug(&mut debugger, &p, &mut Console::new("<input>", text, read)) {
+      Some(Ok(v)) => format!("{} : {}", v, parser::type_to_string(p.ty().clone())),
+      Some(Err(err)) => runtime_error(&err),
+      None => "program stopped".to_string(),
+    }
+  }
+
+  fn load(&mut self, path: &str) -> Result<String, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    match (parser::parser::decls(&text), parser::parser::expr(&text)) {
+      (Ok(ds), _) => {
+        let mut out = vec![];
+        for d in ds.iter() {
+          out.push(self.define(d, &text)?);
+        }
+        Ok(out.join("\n"))
+      },
+      (_, Ok(e)) => self.evaluate(&e, &text),
+      (Err(de), Err(ee)) => {
+        let err = if de.location.offset > ee.location.offset { de } else { ee };
+        Err(format!("{}: {}", path, parse_error(&err)))
+      },
+    }
+  }
+
+  fn show_env(&self) -> String {
+    // Builtins are always there, so only list what the session defined
+    let (con, env) = (self.engine.context(), self.engine.environment());
+    let mut names: Vec<&String> = con.keys()
+      .filter(|x| !matches!(env.get(*x), Some(EnvRecord::NativeRecord(_))))
+      .collect();
+    names.sort();
+    let lines: Vec<String> = names.into_iter().map(|x| {
+      let t = parser::type_to_string(con[x].clone());
+      match env.get(x) {
+        Some(EnvRecord::VarRecord(v)) => format!("{} : {} = {}", x, t, v),
+        _ => format!("{} : {}", x, t),
+      }
+    }).collect();
+    if lines.is_empty() { "no definitions".to_string() } else { lines.join("\n") }
+  }
+}
+
+/* A parse error at the very end of the input means more input could complete it */
+fn at_end(input: &str, err: &ParseError) -> bool {
+  err.location.offset >= input.trim_end().len()
+}
+
+fn runtime_error(err: &EvalError) -> String {
+  format!("error: {}", err)
+}
+
+/* A type error in text, at the line and column of the span it was found at */
+fn type_error(err: &TypeError, text: &str) -> String {
+  match err.span {
+    Some(s) => {
+      let (line, col) = s.line_col(text);
+      format!("type error at {}:{}: {}", line, col, err)
+    },
+    None => format!("type error: {}", err),
+  }
+}
+
+fn parse_error(err: &ParseError) -> String {
+  format!("parse error at {}:{}: expected {}", err.location.line, err.location.column, err.expected)
+}
+
+pub fn run() -> rustyline::Result<()> {
+  let mut editor = DefaultEditor::new()?;
+  // A missing history file just means this is the first session
+  let _ = editor.load_history(HISTORY_FILE);
+  let mut session = Session::new();
+  let mut buffer = String::new();
+  println!("Toi REPL. Enter declarations, expressions or :quit.");
+
+  loop {
+    let prompt = if buffer.is_empty() { "toi> " } else { "...> " };
+    match editor.readline(prompt) {
+      Ok(line) => {
+        let abandon = !buffer.is_empty() && line.trim().is_empty();
+        buffer.push_str(&line);
+        buffer.push('\n');
+        match session.handle(&buffer, abandon) {
+          Outcome::Incomplete => continue,
+          Outcome::Output(text) => {
+            if !text.is_empty() {
+              println!("{}", text);
+            }
+          },
+          Outcome::Debug(e, text) => println!("{}", session.debug(&e, &text, |prompt| editor.readline(prompt).ok())),
+          Outcome::Quit => break,
+        }
+        editor.add_history_entry(buffer.trim_end())?;
+        buffer.clear();
+      },
+      Err(ReadlineError::Interrupted) => buffer.clear(),
+      Err(ReadlineError::Eof) => break,
+      Err(e) => return Err(e),
+    }
+  }
+  editor.save_history(HISTORY_FILE)
+}
@@ -0,0 +1,66 @@
+/* The toi test runner.
+ *
+ * A file of tests holds declarations and named tests, in any order:
+ *   function square(x) { x * x }
+ *   test "square works" { square(4) == 16 }
+ *   test "square of a negative" { assert(square(0 - 3) == 9, "squares are positive") }
+ *
+ * Declarations are checked and evaluated in order, on top of the prelude, and each
+ * test sees the declarations above it. Every test is then type-checked and run on
+ * its own: a test passes when its body has type boolean and evaluates to true, so
+ * a failing assert or an ill-typed test only fails that test.
+ */
+use toi_ast::{Expr, Item, Type};
+use toi_parser::parser;
+use toi_evaluator::engine::Engine;
+use toi_evaluator::evaluator::{Evaluator, Value};
+use crate::{parse_error, runtime_error, type_error, Failure, Source, EXIT_RUNTIME};
+
+/* Why a single test did not pass */
+fn run_test(src: &Source, engine: &Engine, body: &Expr) -> Result<(), String> {
+  let at = src.at(body.span());
+  let p = match engine.infer(body.clone()) {
+    Ok(p) if *p.ty() == Type::Boolean => p,
+    Ok(p) => return Err(format!("{}: type error: the test has type {} instead of boolean", at, parser::type_to_string(p.ty().clone()))),
+    Err(err) => return Err(format!("{}: type error: {}", src.at(err.span), err)),
+  };
+  match engine.run_cek(&Evaluator::new(), &p) {
+    Ok(Value::Boolean(true)) => Ok(()),
+    Ok(_) => Err(format!("{}: the test evaluated to false", at)),
+    Err(err) => Err(format!("{}: error: {}", src.at(err.span()), err)),
+  }
+}
+
+pub fn run_tests(src: &Source) -> Result<(), Failure> {
+  let items = parser::parser::items(&src.text).map_err(|e| parse_error(src, e))?;
+  let mut engine = Engine::with_prelude();
+  let (mut passed, mut failed) = (0, 0);
+
+  for item in items.iter() {
+    match item {
+      Item::Defn(d) => {
+        match engine.define(&Evaluator::new(), d) {
+          Ok(result) => result.map_err(|err| runtime_error(src, &err))?,
+          Err(err) => return Err(type_error(src, &err)),
+        };
+      },
+      Item::Test(name, body) => match run_test(src, &engine, body) {
+        Ok(()) => {
+          println!("test {} ... ok", name);
+          passed += 1;
+        },
+        Err(reason) => {
+          println!("test {} ... FAILED\n  {}", name, reason);
+          failed += 1;
+        },
+      },
+    }
+  }
+
+  println!("\n{} passed, {} failed", passed, failed);
+  if failed > 0 {
+    Err(Failure::new(EXIT_RUNTIME, format!("{}: {} of {} tests failed", src.name, failed, passed + failed)))
+  } else {
+    Ok(())
+  }
+}
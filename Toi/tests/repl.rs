@@ -0,0 +1,38 @@
+/* Tests of toi repl, which feed it lines on standard input and read what it
+ * prints. Each session runs in the temporary directory, where it keeps its
+ * history file.
+ */
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/* What a REPL session given these lines prints on standard output */
+fn session(lines: &str) -> String {
+  let mut repl = Command::new(env!("CARGO_BIN_EXE_toi"))
+    .arg("repl")
+    .current_dir(std::env::temp_dir())
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .spawn()
+    .expect("toi repl starts");
+  repl.stdin.take().expect("toi repl reads standard input").write_all(lines.as_bytes()).expect("toi repl takes its input");
+  let output = repl.wait_with_output().expect("toi repl finishes");
+  String::from_utf8(output.stdout).expect("toi repl prints text")
+}
+
+#[test]
+fn continues_after_a_trailing_separator() {
+  let out = session("let var a = 1,\nvar b = 2 in a + b\n:quit\n");
+  assert!(out.contains("3 : number") && !out.contains("parse error"), "{}", out);
+}
+
+#[test]
+fn continues_after_a_trailing_keyword() {
+  let out = session("let\nvar a = 1 in a\n:quit\n");
+  assert!(out.contains("1 : number") && !out.contains("parse error"), "{}", out);
+}
+
+#[test]
+fn reports_where_and_why_input_is_ill_typed() {
+  let out = session("1 + true\n:quit\n");
+  assert!(out.contains("type error at 1:5: expected number but found boolean"), "{}", out);
+}
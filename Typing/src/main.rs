@@ -1,5 +1,5 @@
-pub mod typer;
-use typer::*;
+use toi_typing::typer;
+use toi_typing::typer::*;
 use typer::Expr as ex;
 use typer::Defn as dc;
 use typer::Comparison as cmp;
@@ -7,8 +7,9 @@ use typer::Type as tp;
 use rpds::HashTrieMap;
 
 
+#[allow(clippy::needless_return, clippy::match_single_binding)]
 fn test_expr_internal(score: f64, in_env: HashTrieMap<String,tp>, in_e: Expr, out: Option<tp>) -> f64 {
-    match (asgn4::type_check_expr(&in_env, &in_e), out) {
+    match (typer::type_check_expr(&in_env, &in_e), out) {
       (e1, e2) =>
         if e1 == e2 {
           return score
@@ -23,8 +24,9 @@ fn test_expr_internal(score: f64, in_env: HashTrieMap<String,tp>, in_e: Expr, ou
     test_expr_internal(score, HashTrieMap::new(), in_e, out)
   }
 
+  #[allow(clippy::needless_return, clippy::match_single_binding)]
   fn test_defn_internal(score: f64, in_env: HashTrieMap<String,tp>, in_d: Defn, out: Option<(String,tp)>) -> f64 {
-    match (asgn4::type_check_defn(&in_env, &in_d), out) {
+    match (typer::type_check_defn(&in_env, &in_d), out.map(|b| vec![b])) {
       (e1, e2) =>
         if e1 == e2 {
           return score
@@ -39,6 +41,7 @@ fn test_expr_internal(score: f64, in_env: HashTrieMap<String,tp>, in_e: Expr, ou
      test_defn_internal(score, HashTrieMap::new(), in_d, out)
   }
 
+#[allow(clippy::unused_unit)]
 fn main() -> () {
     // 5 tests of numerals/numbers
     let r1 = test_expr(3.0, ex::Plus(Box::new(ex::Numeral(1)),Box::new(ex::Numeral(2))), Some(tp::Number));
@@ -117,7 +120,83 @@ fn main() -> () {
       vec![("h".to_string(), tp::Number)], tp::Boolean,Box::new(ex::Compare(Box::new(ex::Id("h".to_string())),cmp::Greater, Box::new(ex::Numeral(0))))),
     Some(("f".to_string(), tp::Function(vec![tp::Number], Box::new(tp::Boolean)))));
 
-    let r = r1+r2+r3+r4+r5+r6+r7+r8+r9+r10+r11+r12+r13+r14+r15+r16+r17+r18+r19+r20+r21+r22+r23+r24+r25;
-    println!("Results: {}/25 tests succesfully completed", r)
+    // 4 tests of soundness and checked programs
+    // incorrect - body does not have the declared return type
+    let r26 = test_defn(3.0,
+      dc::FunDefn("f".to_string(), vec![("h".to_string(), tp::Number)], tp::String, Box::new(ex::Id("h".to_string()))),
+      None);
+    // incorrect - calling a variable that is not a function
+    let r27 = test_expr(3.0,
+        ex::Let(Box::new(dc::VarDefn("x".to_string(), Box::new(ex::Numeral(1)))),
+        Box::new(ex::Call("x".to_string(), vec![]))), None);
+    let r28 = match CheckedProgram::check(ex::Plus(Box::new(ex::Numeral(1)), Box::new(ex::Numeral(2)))) {
+        Some(p) if *p.ty() == tp::Number => 3.0,
+        _ => { println!("Checked program test case 1+2 expected a number program"); 0.0 }
+    };
+    let r29 = match CheckedProgram::check(ex::Plus(Box::new(ex::Numeral(1)), Box::new(ex::True))) {
+        None => 3.0,
+        Some(p) => { println!("Checked program test case 1+true expected no program but got {:?}", p); 0.0 }
+    };
+
+    // 2 tests of assertions
+    // correct - a boolean condition and a string message
+    let r30 = test_expr(3.0,
+        ex::Assert(Box::new(ex::Compare(Box::new(ex::Numeral(1)), cmp::Less, Box::new(ex::Numeral(2)))), Box::new(ex::StringLiteral("ordered".to_string()))),
+        Some(tp::Boolean));
+    // incorrect - the condition must be a boolean
+    let r31 = test_expr(3.0,
+        ex::Assert(Box::new(ex::Numeral(1)), Box::new(ex::StringLiteral("one".to_string()))), None);
+
+    // 3 tests of recursive groups
+    let num_fun = || tp::Function(vec![tp::Number], Box::new(tp::Number));
+    let call = |f: &str, x: &str| ex::Call(f.to_string(), vec![ex::Id(x.to_string())]);
+    // correct - f and g can call each other
+    let group = dc::RecDefn(vec![
+        ("f".to_string(), vec![("x".to_string(), tp::Number)], tp::Number, Box::new(call("g", "x"))),
+        ("g".to_string(), vec![("y".to_string(), tp::Number)], tp::Number, Box::new(ex::Plus(Box::new(call("f", "y")), Box::new(ex::Numeral(1))))),
+    ]);
+    let r32 = match typer::type_check_defn(&HashTrieMap::new(), &group) {
+        Some(b) if b == vec![("f".to_string(), num_fun()), ("g".to_string(), num_fun())] => 3.0,
+        b => { println!("Recursive group test case {:?} expected f and g of type (number)->number but got {:?}", group, b); 0.0 }
+    };
+    // incorrect - g returns a boolean but is declared to return a number
+    let r33 = test_expr(3.0, ex::Let(Box::new(dc::RecDefn(vec![
+        ("f".to_string(), vec![("x".to_string(), tp::Number)], tp::Number, Box::new(call("g", "x"))),
+        ("g".to_string(), vec![("y".to_string(), tp::Number)], tp::Number, Box::new(ex::True)),
+    ])), Box::new(ex::Call("f".to_string(), vec![ex::Numeral(1)]))), None);
+    // incorrect - outside a group, a function cannot call one defined after it
+    let r34 = test_expr(3.0, ex::Let(Box::new(dc::FunDefn("f".to_string(), vec![("x".to_string(), tp::Number)], tp::Number, Box::new(call("g", "x")))),
+        Box::new(ex::Let(Box::new(dc::FunDefn("g".to_string(), vec![("y".to_string(), tp::Number)], tp::Number, Box::new(ex::Id("y".to_string())))),
+        Box::new(ex::Call("f".to_string(), vec![ex::Numeral(1)]))))), None);
+
+    // 3 tests of conditionals
+    let zero = || Box::new(ex::Compare(Box::new(ex::Id("n".to_string())), cmp::Equal, Box::new(ex::Numeral(0))));
+    let n_in = |e: Expr| ex::Let(Box::new(dc::VarDefn("n".to_string(), Box::new(ex::Numeral(1)))), Box::new(e));
+    // correct - both branches are strings
+    let r35 = test_expr(3.0, n_in(ex::If(zero(), Box::new(ex::StringLiteral("zero".to_string())), Box::new(ex::StringLiteral("other".to_string())))),
+        Some(tp::String));
+    // incorrect - the branches have different types
+    let r36 = test_expr(3.0, n_in(ex::If(zero(), Box::new(ex::StringLiteral("zero".to_string())), Box::new(ex::Numeral(1)))), None);
+    // incorrect - the condition must be a boolean
+    let r37 = test_expr(3.0, ex::If(Box::new(ex::Numeral(1)), Box::new(ex::Numeral(2)), Box::new(ex::Numeral(3))), None);
+    // incorrect - numbers are integers, so 1.2 has no type
+    let r38 = test_expr(3.0, ex::Plus(Box::new(ex::Decimal(12, 1)), Box::new(ex::Numeral(1))), None);
+
+    // 2 tests of type errors, which say why and where a program is ill-typed
+    // the operand that is not a number is at fault, at its own span
+    let plus_true = ex::Spanned(Span::new(0, 8), Box::new(ex::Plus(Box::new(ex::Numeral(1)), Box::new(ex::Spanned(Span::new(4, 8), Box::new(ex::True))))));
+    let r39 = match typer::infer_expr(&HashTrieMap::new(), &plus_true) {
+        Err(err) if err.span == Some(Span::new(4, 8)) && err.reason == "expected number but found boolean" => 3.0,
+        r => { println!("Type error test case 1 + true expected a boolean at 4..8 instead of a number but got {:?}", r); 0.0 }
+    };
+    // a function body of the wrong type is at fault, where there is no closer span
+    let wrong_body = dc::FunDefn("f".to_string(), vec![("h".to_string(), tp::Number)], tp::String, Box::new(ex::Id("h".to_string())));
+    let r40 = match typer::infer_defn(&HashTrieMap::new(), &wrong_body) {
+        Err(err) if err.span.is_none() && err.reason == "f returns string but its body has type number" => 3.0,
+        r => { println!("Type error test case {:?} expected a body of the wrong type but got {:?}", wrong_body, r); 0.0 }
+    };
+
+    let r = r1+r2+r3+r4+r5+r6+r7+r8+r9+r10+r11+r12+r13+r14+r15+r16+r17+r18+r19+r20+r21+r22+r23+r24+r25+r26+r27+r28+r29+r30+r31+r32+r33+r34+r35+r36+r37+r38+r39+r40;
+    println!("Results: {}/40 tests succesfully completed", r)
   }
 
@@ -4,190 +4,257 @@
  * The following two functions are implemented for this task:
  * type_check_expr (for expressions)
  * type_check_defn (for definitions)
+ * infer_expr and infer_defn check in the same way, but give a TypeError saying
+ * why a program is ill-typed and where, for reporting to whoever wrote it.
+ *
+ * A CheckedProgram packages an expression together with the proof that it
+ * type-checks, for evaluators that should only ever run well-typed programs.
  * */
 
-use std::{hash::Hash, env, any::TypeId};
+use std::fmt;
 use rpds::HashTrieMap;
 
-#[derive(Hash, Eq, PartialEq, Debug, Clone)]
-/* A value of t : Type represents a given type */
-pub enum Type {
-    Number,  /* represents "num" type */
-    String,  /* represents "string" type */
-    Boolean, /* represents "boolean" type */
-    Function(Vec<Type>, Box<Type>), /* represents type of function t1 -> t2 */
+/* Types and syntax trees are shared with the parser and evaluator through toi-ast
+ * (see Ast/src/lib.rs), so parsed programs can be checked directly. */
+pub use toi_ast::{Type, Comparison, Expr, Defn, Span};
+
+/* Why a program is ill-typed, and the span of the innermost subexpression at
+ * fault, if the program was parsed with spans */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeError {
+    pub span: Option<Span>,
+    pub reason: String,
 }
 
-/* This enumeration type lists out the different comparison operators */
-#[derive(Hash, Eq, PartialEq, Debug, Clone)]
-pub enum Comparison {
-    LessEqual,
-    Less,
-    Equal,
-    NotEqual,
-    Greater,
-    GreaterEqual,
+impl TypeError {
+    fn new(reason: String) -> TypeError {
+        TypeError { span: None, reason }
+    }
+
+    /* Places the error at span, unless a more precise span is already known */
+    pub fn at(self, span: Span) -> TypeError {
+        TypeError { span: self.span.or(Some(span)), reason: self.reason }
+    }
 }
 
-/* A value e : Expr is an AST for a Toi expression  */
-#[derive(Hash, Eq, PartialEq, Debug, Clone)]
-pub enum Expr {
-    Id(String),  /* Identifier, i.e., variable name */
-    Numeral(i64), /* Number literal, e.g., 5 */
-    StringLiteral(String), /* String literal, e.g., "hi" */
-    True, /* Boolean literal true */
-    False, /* Boolean literal true */
-    /* To reduce the number of cases, we combine all the comparison operators.
-     * The arguments indicate the first operand, what kind of comparison, 
-     * then the second operand.
-     * For example, Compare(e1,Greater,e2) is (e1 > e2) */
-    Compare(Box<Expr>, Comparison, Box<Expr>),
-    Times(Box<Expr>,Box<Expr>), /* Multiplication */
-    Plus(Box<Expr>,Box<Expr>),  /* Addition */
-    Minus(Box<Expr>,Box<Expr>), /* Subtraction */
-    Let(Box<Defn>,Box<Expr>),   /* Let-definitions */
-    Call(String, Vec<Expr>),    /* Function calls */
-} 
-#[derive(Hash, Eq, PartialEq, Debug, Clone)]
-pub enum Defn {
-    /* Variable definitions */
-    VarDefn(String, Box<Expr>), 
-    /* Function definitions. Functions can be called recursively
-     * For example in pseudo-code:
-     * FunDecl("f", [("x",number),("y",number)], bool, Compare(x,Equal,y))
-     * is the AST for the Toi function definition:
-     *   function f(x:number,y:number):bool = 
-     *     (x = y) */
-    FunDefn(String, Vec<(String,Type)>, Type, Box<Expr>),
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
 }
 
 /* Type-checking for definitions.
  * Arguments: "con" is the typing context Γ (Gamma)
  *            "d" is the AST for a definition
  *   If the judgement Γ ⊢ d : Γ' holds, then 
- *   type_check_defn(Γ, d) = Some(Γ'). If not, type_check_defn(Γ,d) = None */
-pub fn type_check_defn(con: &HashTrieMap<String, Type>, d: &Defn) -> Option<(String,Type)> {
-    // Match definition type to variable or function
+ *   type_check_defn(Γ, d) = Some(bindings), where Γ' is Γ extended with the
+ *   name and type of everything d defines. If not, type_check_defn(Γ,d) = None */
+pub fn type_check_defn(con: &HashTrieMap<String, Type>, d: &Defn) -> Option<Vec<(String,Type)>> {
+    infer_defn(con, d).ok()
+}
+
+/* Type-checking for definitions, like type_check_defn, but saying why d is
+ * ill-typed if it is */
+pub fn infer_defn(con: &HashTrieMap<String, Type>, d: &Defn) -> Result<Vec<(String,Type)>, TypeError> {
+    // Match definition type to variable, function or recursive group
     match d {
         Defn::VarDefn(var, val) => {
-            match type_check_expr(con, val) {
-                Some(n) => Some((var.to_string(), n)),
-                None => None
-            }
+            Ok(vec![(var.to_string(), infer_expr(con, val)?)])
         }, 
         Defn::FunDefn(func, params, t, expr) => {
-            let mut param_type = vec![];
+            let fun_type = function_type(params, t);
+            // The function is in scope in its own body, so it can call itself
+            let updated_con = HashTrieMap::insert(con, func.to_string(), fun_type.clone());
+            check_body(&updated_con, func, params, t, expr)?;
+            Ok(vec![(func.to_string(), fun_type)])
+        },
+        Defn::RecDefn(funcs) => {
+            // Every function of the group is in scope in every body
+            let mut bindings = vec![];
             let mut updated_con = con.clone();
-            for p in params.iter() {
-                param_type.push(p.clone().1);
-                updated_con = HashTrieMap::insert(&updated_con, p.clone().0, p.clone().1);
+            for (func, params, t, _) in funcs.iter() {
+                let fun_type = function_type(params, t);
+                bindings.push((func.to_string(), fun_type.clone()));
+                updated_con = HashTrieMap::insert(&updated_con, func.to_string(), fun_type);
             }
-            updated_con = HashTrieMap::insert(&updated_con, func.to_string(), Type::Function(param_type.clone(), Box::new(t.clone())).clone());
-            match type_check_expr(&updated_con, expr) {
-                Some(p) => Some((func.to_string(), Type::Function(param_type.clone(), Box::new(t.clone())))),
-                None => None
+            for (func, params, t, expr) in funcs.iter() {
+                check_body(&updated_con, func, params, t, expr)?;
             }
+            Ok(bindings)
         },
     }
 }
 
+fn function_type(params: &[(String,Type)], t: &Type) -> Type {
+    Type::Function(params.iter().map(|p| p.1.clone()).collect(), Box::new(t.clone()))
+}
+
+/* The body of func must have the declared return type when its parameters are in scope */
+fn check_body(con: &HashTrieMap<String, Type>, func: &str, params: &[(String,Type)], t: &Type, expr: &Expr) -> Result<(), TypeError> {
+    let mut updated_con = con.clone();
+    for p in params.iter() {
+        updated_con = HashTrieMap::insert(&updated_con, p.clone().0, p.clone().1);
+    }
+    let found = infer_expr(&updated_con, expr)?;
+    if found == *t {
+        Ok(())
+    } else {
+        Err(mismatch(expr, format!("{} returns {} but its body has type {}", func, t, found)))
+    }
+}
+
+/* e must have type t */
+fn expect(con: &HashTrieMap<String, Type>, e: &Expr, t: &Type) -> Result<(), TypeError> {
+    let found = infer_expr(con, e)?;
+    if found == *t {
+        Ok(())
+    } else {
+        Err(mismatch(e, format!("expected {} but found {}", t, found)))
+    }
+}
+
+/* The error that e, whose type is known, has the wrong one */
+fn mismatch(e: &Expr, reason: String) -> TypeError {
+    let err = TypeError::new(reason);
+    match e.span() {
+        Some(s) => err.at(s),
+        None => err,
+    }
+}
+
 /* Type-checking for expressions.
  * Arguments: "con" is the typing context Γ (Gamma)
  *            "e" is the AST for an expression
  *   If the judgement Γ ⊢ e : t holds, then 
  *   type_check_expr(Γ, e) = Some(t). If not, type_check_expr(Γ,e) = None */
 pub fn type_check_expr(con: &HashTrieMap<String, Type>, e: &Expr) -> Option<Type> {
+    infer_expr(con, e).ok()
+}
+
+/* Type-checking for expressions, like type_check_expr, but saying why e is
+ * ill-typed if it is */
+pub fn infer_expr(con: &HashTrieMap<String, Type>, e: &Expr) -> Result<Type, TypeError> {
     match e {
         Expr::Id(s) => {
             match HashTrieMap::get(con, s) {
-                Some(val) => Some(val.clone()),
-                None => None
+                Some(val) => Ok(val.clone()),
+                None => Err(TypeError::new(format!("{} is not defined", s)))
             }
         },
-        Expr::Numeral(n) => {
-            Some(Type::Number)
+        Expr::Numeral(_) => {
+            Ok(Type::Number)
         },
-        Expr::StringLiteral(s) => {
-            Some(Type::String)
+        // Toi numbers are integers, so a fractional numeral has no type
+        Expr::Decimal(_, _) => {
+            Err(TypeError::new("numbers must be integers, so a fractional numeral has no type".to_string()))
+        },
+        Expr::StringLiteral(_) => {
+            Ok(Type::String)
         },
         Expr::True => {
-            Some(Type::Boolean)
+            Ok(Type::Boolean)
         },
         Expr::False => {
-            Some(Type::Boolean)
-        },
-        Expr::Compare(l, comp, r) => {
-            let tcl = type_check_expr(con, l);
-            let tcr = type_check_expr(con, r);
-
-            if tcl == Some(Type::Number) && tcr == Some(Type::Number) {
-                Some(Type::Boolean)
-            }
-            else {
-                None
-            }
-        },
-        Expr::Times(l, r) => {
-            let tcl = type_check_expr(con, l);
-            let tcr = type_check_expr(con, r);
-
-            if tcl == Some(Type::Number) && tcr == Some(Type::Number) {
-                Some(Type::Number)
-            }
-            else {
-                None
-            }
+            Ok(Type::Boolean)
         },
-        Expr::Plus(l,r ) => {
-            let tcl = type_check_expr(con, l);
-            let tcr = type_check_expr(con, r);
-
-            if tcl == Some(Type::Number) && tcr == Some(Type::Number) {
-                Some(Type::Number)
-            }
-            else {
-                None
-            }
+        Expr::Compare(l, _, r) => {
+            expect(con, l, &Type::Number)?;
+            expect(con, r, &Type::Number)?;
+            Ok(Type::Boolean)
         },
-        Expr::Minus(l, r) => {
-            let tcl = type_check_expr(con, l);
-            let tcr = type_check_expr(con, r);
-
-            if tcl == Some(Type::Number) && tcr == Some(Type::Number) {
-                Some(Type::Number)
-            }
-            else {
-                None
-            }
+        Expr::Times(l, r) | Expr::Plus(l, r) | Expr::Minus(l, r) => {
+            expect(con, l, &Type::Number)?;
+            expect(con, r, &Type::Number)?;
+            Ok(Type::Number)
         },
         Expr::Let(d, v) => {
-            let Some(tcd) = type_check_defn(con, d) else { return None };
-            let updated_con = HashTrieMap::insert(con, tcd.0, tcd.1);
-            type_check_expr(&updated_con, v)
+            let tcd = infer_defn(con, d)?;
+            let mut updated_con = con.clone();
+            for (x, t) in tcd {
+                updated_con = HashTrieMap::insert(&updated_con, x, t);
+            }
+            infer_expr(&updated_con, v)
         },
         Expr::Call(f, params) => {
             match HashTrieMap::get(con, f) {
-                Some(t) => {
-                    match t {
-                        Type::Function(pt, t) => {
-                            if pt.len() != params.len() {
-                                return None
-                            }
-                            for (pti, paramsi) in pt.iter().zip(params.iter()) {
-                                let paramsi_type = type_check_expr(con, paramsi);
-                                    if Some(pti.clone()) != paramsi_type {
-                                        return None
-                                    }
-                                }
-                            Some(*t.clone())
-                        },
-                        _ => Some(t.clone())
+                Some(Type::Function(pt, t)) => {
+                    if pt.len() != params.len() {
+                        return Err(TypeError::new(format!("{} takes {} arguments but is given {}", f, pt.len(), params.len())))
                     }
+                    for (pti, paramsi) in pt.iter().zip(params.iter()) {
+                        expect(con, paramsi, pti)?;
+                    }
+                    Ok(*t.clone())
                 },
-                None => None 
+                // Only functions can be called
+                Some(t) => Err(TypeError::new(format!("{} has type {} and cannot be called", f, t))),
+                None => Err(TypeError::new(format!("{} is not defined", f)))
+            }
+        },
+        Expr::Assert(c, m) => {
+            expect(con, c, &Type::Boolean)?;
+            expect(con, m, &Type::String)?;
+            Ok(Type::Boolean)
+        },
+        Expr::If(c, t, e) => {
+            // Both branches must have the same type, which is the type of the whole
+            expect(con, c, &Type::Boolean)?;
+            let tct = infer_expr(con, t)?;
+            let tce = infer_expr(con, e)?;
+            if tct == tce {
+                Ok(tct)
+            }
+            else {
+                Err(TypeError::new(format!("the branches of if have different types, {} and {}", tct, tce)))
             }
+        },
+        Expr::Spanned(s, e) => {
+            infer_expr(con, e).map_err(|err| err.at(*s))
         }
     }
 }
 
+/* A Toi program that is known to be well-typed in a given typing context. The
+ * fields are private and the check functions are the only constructors, so holding
+ * a CheckedProgram means type_check_expr(Γ, e) = Some(t) for its context Γ,
+ * expression e and type t. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckedProgram {
+    expr: Expr,
+    ty: Type,
+    con: HashTrieMap<String, Type>,
+}
+
+impl CheckedProgram {
+    /* Type-checks a closed program, i.e. in the empty context */
+    pub fn check(e: Expr) -> Option<CheckedProgram> {
+        CheckedProgram::check_in(&HashTrieMap::new(), e)
+    }
+
+    /* Type-checks e in the context con, keeping it only if it is well-typed */
+    pub fn check_in(con: &HashTrieMap<String, Type>, e: Expr) -> Option<CheckedProgram> {
+        CheckedProgram::infer_in(con, e).ok()
+    }
+
+    /* Type-checks e in the context con like check_in, but saying why e is
+     * ill-typed if it is */
+    pub fn infer_in(con: &HashTrieMap<String, Type>, e: Expr) -> Result<CheckedProgram, TypeError> {
+        let ty = infer_expr(con, &e)?;
+        Ok(CheckedProgram { expr: e, ty, con: con.clone() })
+    }
+
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+
+    pub fn ty(&self) -> &Type {
+        &self.ty
+    }
+
+    /* The context the program was checked in, which its environment must match */
+    pub fn context(&self) -> &HashTrieMap<String, Type> {
+        &self.con
+    }
+}
+
 /* Materials Copyright Rose Bohrer 2023, Completed and Edited by Alasdair Campbell 2023 */
\ No newline at end of file